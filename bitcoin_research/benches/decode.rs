@@ -0,0 +1,74 @@
+//! Decoding throughput for a full block.
+//!
+//! Run with `cargo bench`. The block is made up: nearly 5,000 one input, two output
+//! SegWit transactions, the shape of a typical mainnet block.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use bitcoin_research::{
+    block::Block,
+    block_header::BlockHeader,
+    encoding::Decodable,
+    raw_message::RawMessage,
+    transaction::{OutPoint, Transaction, TxIn, TxOut},
+};
+
+const PAYLOAD_SIZE: usize = 1024 * 1024;
+const ROUNDS: u32 = 20;
+
+fn transaction(i: u32) -> Transaction {
+    Transaction {
+        version: 2,
+        inputs: vec![TxIn {
+            previous_output: OutPoint {
+                txid: [i as u8; 32],
+                vout: i,
+            },
+            script_sig: Vec::new(),
+            sequence: u32::MAX,
+            witness: vec![vec![0x30; 71], vec![0x02; 33]],
+        }],
+        outputs: (0..2)
+            .map(|_| TxOut {
+                value: i64::from(i),
+                script_pubkey: vec![0x00; 22],
+            })
+            .collect(),
+        lock_time: 0,
+    }
+}
+
+fn main() {
+    let tx_size = transaction(0).to_bytes().unwrap().len();
+    let block = Block {
+        header: BlockHeader {
+            version: 0x20000000,
+            prev_block: [0; 32],
+            merkle_root: [0; 32],
+            time: 0,
+            bits: 0x207fffff,
+            nonce: 0,
+        },
+        transactions: (0..(PAYLOAD_SIZE / tx_size) as u32)
+            .map(transaction)
+            .collect(),
+    };
+    let payload = block.to_bytes().unwrap();
+
+    let mut best = Duration::MAX;
+    for _ in 0..ROUNDS {
+        let started = Instant::now();
+        let decoded = Block::decode(&mut black_box(&payload[..])).unwrap();
+        best = best.min(started.elapsed());
+        assert_eq!(decoded.transactions.len(), block.transactions.len());
+    }
+    println!(
+        "decode {} bytes ({} transactions): best of {} {:?}, {:.0} MB/s",
+        payload.len(),
+        block.transactions.len(),
+        ROUNDS,
+        best,
+        payload.len() as f64 / best.as_secs_f64() / 1e6
+    );
+}
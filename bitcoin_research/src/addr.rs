@@ -0,0 +1,291 @@
+use std::io::{Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use crate::{
+    encoding::{decode_be, read_length, CompactSize, Decodable, Encodable},
+    errors::BitcoinMessageError,
+    net_addr::NetAddr,
+};
+
+/// Most addresses in one `addr` or `addrv2` message.
+pub const MAX_ADDR: usize = 1000;
+/// Longest address in `addrv2` (BIP 155), for networks we don't know as well.
+pub const MAX_ADDRV2_SIZE: usize = 512;
+
+/// https://en.bitcoin.it/wiki/Protocol_documentation#addr
+///
+/// size | field     | type     | description
+/// ---  | -----     | ----     | ------------
+/// 4    | time      | u32      | when the node was last seen, UNIX timestamp
+/// 26   | net_addr  | net_addr | services, IP and port
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AddrEntry {
+    pub time: u32,
+    pub addr: NetAddr,
+}
+
+impl Encodable for AddrEntry {
+    fn encode<W: Write>(&self, w: &mut W) -> Result<(), BitcoinMessageError> {
+        self.time.encode(w)?;
+        self.addr.encode(w)
+    }
+}
+
+impl Decodable for AddrEntry {
+    fn decode<R: Read>(r: &mut R) -> Result<Self, BitcoinMessageError> {
+        Ok(AddrEntry {
+            time: u32::decode(r)?,
+            addr: NetAddr::decode(r)?,
+        })
+    }
+}
+
+/// An address of one of the networks in BIP 155.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AddrV2 {
+    Ipv4(Ipv4Addr),
+    Ipv6(Ipv6Addr),
+    /// ed25519 public key of a Tor v3 onion service.
+    TorV3([u8; 32]),
+    /// SHA256 of an I2P destination, the part before `.b32.i2p`.
+    I2p([u8; 32]),
+    /// A network we don't handle, kept so it can be relayed as it came in.
+    Unknown {
+        network_id: u8,
+        addr: Vec<u8>,
+    },
+}
+
+impl AddrV2 {
+    const IPV4: u8 = 1;
+    const IPV6: u8 = 2;
+    const TORV3: u8 = 4;
+    const I2P: u8 = 5;
+
+    pub fn network_id(&self) -> u8 {
+        match self {
+            AddrV2::Ipv4(_) => Self::IPV4,
+            AddrV2::Ipv6(_) => Self::IPV6,
+            AddrV2::TorV3(_) => Self::TORV3,
+            AddrV2::I2p(_) => Self::I2P,
+            AddrV2::Unknown { network_id, .. } => *network_id,
+        }
+    }
+
+    fn to_vec(&self) -> Vec<u8> {
+        match self {
+            AddrV2::Ipv4(ip) => ip.octets().to_vec(),
+            AddrV2::Ipv6(ip) => ip.octets().to_vec(),
+            AddrV2::TorV3(key) | AddrV2::I2p(key) => key.to_vec(),
+            AddrV2::Unknown { addr, .. } => addr.clone(),
+        }
+    }
+
+    fn from_vec(network_id: u8, addr: Vec<u8>) -> Result<Self, BitcoinMessageError> {
+        let wrong_length = |addr: Vec<u8>| BitcoinMessageError::AddressLength {
+            network_id,
+            len: addr.len(),
+        };
+        match network_id {
+            Self::IPV4 => Ok(AddrV2::Ipv4(Ipv4Addr::from(
+                <[u8; 4]>::try_from(addr).map_err(wrong_length)?,
+            ))),
+            Self::IPV6 => Ok(AddrV2::Ipv6(Ipv6Addr::from(
+                <[u8; 16]>::try_from(addr).map_err(wrong_length)?,
+            ))),
+            Self::TORV3 => Ok(AddrV2::TorV3(
+                <[u8; 32]>::try_from(addr).map_err(wrong_length)?,
+            )),
+            Self::I2P => Ok(AddrV2::I2p(
+                <[u8; 32]>::try_from(addr).map_err(wrong_length)?,
+            )),
+            _ => Ok(AddrV2::Unknown { network_id, addr }),
+        }
+    }
+}
+
+impl From<IpAddr> for AddrV2 {
+    fn from(ip: IpAddr) -> Self {
+        // IPv4-mapped addresses are sent as IPv4 in addrv2
+        match ip {
+            IpAddr::V4(ip) => AddrV2::Ipv4(ip),
+            IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+                Some(ip) => AddrV2::Ipv4(ip),
+                None => AddrV2::Ipv6(ip),
+            },
+        }
+    }
+}
+
+/// https://github.com/bitcoin/bips/blob/master/bip-0155.mediawiki
+///
+/// size | field      | type        | description
+/// ---  | -----      | ----        | ------------
+/// 4    | time       | u32         | when the node was last seen, UNIX timestamp
+/// 1-9  | services   | CompactSize | service bits
+/// 1    | network_id | u8          | network of the address
+/// 1-3  | addr len   | CompactSize | at most 512
+/// ?    | addr       | [u8]        | the address, its length fixed by the network
+/// 2    | port       | u16         | port number, big endian
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AddrV2Entry {
+    pub time: u32,
+    pub services: u64,
+    pub addr: AddrV2,
+    pub port: u16,
+}
+
+impl AddrV2Entry {
+    /// The socket address, for networks reachable over IP.
+    pub fn socket_addr(&self) -> Option<SocketAddr> {
+        match self.addr {
+            AddrV2::Ipv4(ip) => Some(SocketAddr::new(IpAddr::V4(ip), self.port)),
+            AddrV2::Ipv6(ip) => Some(SocketAddr::new(IpAddr::V6(ip), self.port)),
+            _ => None,
+        }
+    }
+}
+
+impl From<AddrEntry> for AddrV2Entry {
+    fn from(entry: AddrEntry) -> Self {
+        AddrV2Entry {
+            time: entry.time,
+            services: entry.addr.services,
+            addr: AddrV2::from(entry.addr.addr.ip()),
+            port: entry.addr.addr.port(),
+        }
+    }
+}
+
+impl Encodable for AddrV2Entry {
+    fn encode<W: Write>(&self, w: &mut W) -> Result<(), BitcoinMessageError> {
+        let addr = self.addr.to_vec();
+        if addr.len() > MAX_ADDRV2_SIZE {
+            return Err(BitcoinMessageError::LengthTooBig {
+                len: addr.len() as u64,
+                max: MAX_ADDRV2_SIZE,
+            });
+        }
+        self.time.encode(w)?;
+        CompactSize(self.services).encode(w)?;
+        self.addr.network_id().encode(w)?;
+        CompactSize(addr.len() as u64).encode(w)?;
+        w.write_all(&addr)?;
+        Ok(w.write_all(&self.port.to_be_bytes())?)
+    }
+}
+
+impl Decodable for AddrV2Entry {
+    fn decode<R: Read>(r: &mut R) -> Result<Self, BitcoinMessageError> {
+        let time = u32::decode(r)?;
+        let CompactSize(services) = CompactSize::decode(r)?;
+        let network_id = u8::decode(r)?;
+        let mut addr = vec![0u8; read_length(r, MAX_ADDRV2_SIZE)?];
+        r.read_exact(&mut addr)?;
+        Ok(AddrV2Entry {
+            time,
+            services,
+            addr: AddrV2::from_vec(network_id, addr)?,
+            port: decode_be::<u16, 2, _>(r)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::raw_message::RawMessage;
+
+    #[test]
+    fn addr_entry_layout() {
+        let entry = AddrEntry {
+            time: 0x4966bc61,
+            addr: NetAddr::new(1, "10.0.0.1:8333".parse().unwrap()),
+        };
+        let bytes = entry.to_bytes().unwrap();
+        assert_eq!(bytes.len(), 30);
+        assert_eq!(&bytes[..4], &[0x61, 0xbc, 0x66, 0x49]);
+        assert_eq!(&bytes[22..], &[0xff, 0xff, 10, 0, 0, 1, 0x20, 0x8d]);
+        assert_eq!(*AddrEntry::from_bytes(&bytes).unwrap(), entry);
+    }
+
+    #[test]
+    fn addrv2_entry_layout() {
+        // like the first address of the addrv2 test vector in Bitcoin Core's net_tests.cpp
+        let bytes = [
+            0x61, 0xbc, 0x66, 0x49, // time
+            0x00, // services
+            0x02, // IPv6
+            0x10, // address length
+            0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, // ::1
+            0x00, 0x00, // port
+        ];
+        let entry = AddrV2Entry::from_bytes(&bytes).unwrap();
+        assert_eq!(entry.time, 0x4966bc61);
+        assert_eq!(entry.services, 0);
+        assert_eq!(entry.addr, AddrV2::Ipv6(Ipv6Addr::LOCALHOST));
+        assert_eq!(entry.port, 0);
+        assert_eq!(entry.to_bytes().unwrap(), bytes);
+    }
+
+    #[test]
+    fn addrv2_round_trip() {
+        let addrs = [
+            AddrV2::Ipv4(Ipv4Addr::new(1, 2, 3, 4)),
+            AddrV2::Ipv6("2001:db8::1".parse().unwrap()),
+            AddrV2::TorV3([0x53; 32]),
+            AddrV2::I2p([0xa2; 32]),
+            // CJDNS, which we don't handle
+            AddrV2::Unknown {
+                network_id: 6,
+                addr: vec![0xfc; 16],
+            },
+        ];
+        for addr in addrs {
+            let entry = AddrV2Entry {
+                time: 1_700_000_000,
+                services: 0x409,
+                addr,
+                port: 8333,
+            };
+            let bytes = entry.to_bytes().unwrap();
+            // services 0x409 take the 3 byte CompactSize
+            assert_eq!(&bytes[4..7], &[0xfd, 0x09, 0x04]);
+            assert_eq!(*AddrV2Entry::from_bytes(&bytes).unwrap(), entry);
+        }
+    }
+
+    #[test]
+    fn addrv2_wrong_length() {
+        // an IPv4 address of 5 bytes
+        let bytes = [0, 0, 0, 0, 0, 0x01, 0x05, 1, 2, 3, 4, 5, 0x20, 0x8d];
+        assert!(matches!(
+            AddrV2Entry::from_bytes(&bytes),
+            Err(BitcoinMessageError::AddressLength {
+                network_id: 1,
+                len: 5
+            })
+        ));
+
+        let mut bytes = vec![0, 0, 0, 0, 0, 0x06, 0xfd, 0x01, 0x02];
+        bytes.extend_from_slice(&[0; 515]);
+        assert!(matches!(
+            AddrV2Entry::from_bytes(&bytes),
+            Err(BitcoinMessageError::LengthTooBig { len: 513, .. })
+        ));
+    }
+
+    #[test]
+    fn from_addr_entry() {
+        let entry = AddrV2Entry::from(AddrEntry {
+            time: 1,
+            addr: NetAddr::new(1, "10.0.0.1:8333".parse().unwrap()),
+        });
+        assert_eq!(entry.addr, AddrV2::Ipv4(Ipv4Addr::new(10, 0, 0, 1)));
+        assert_eq!(entry.socket_addr(), Some("10.0.0.1:8333".parse().unwrap()));
+        assert_eq!(
+            AddrV2::from(IpAddr::V6(Ipv4Addr::new(10, 0, 0, 1).to_ipv6_mapped())),
+            entry.addr
+        );
+    }
+}
@@ -0,0 +1,308 @@
+use std::io::{Read, Write};
+
+use crate::{
+    block_header::BlockHeader,
+    encoding::{read_var_array, write_var_array, Decodable, Encodable},
+    errors::BitcoinMessageError,
+    payload::MAX_SIZE,
+    transaction::Transaction,
+    utils::sha256d,
+};
+
+/// Start of the coinbase output committing to the witnesses: OP_RETURN, a push of 36
+/// bytes, and the BIP 141 header 0xaa21a9ed.
+const WITNESS_COMMITMENT_HEADER: [u8; 6] = [0x6a, 0x24, 0xaa, 0x21, 0xa9, 0xed];
+
+/// https://en.bitcoin.it/wiki/Protocol_documentation#block
+///
+/// size | field      | type          | description
+/// ---  | -----      | ----          | ------------
+/// 80   | header     | block header  | see [`BlockHeader`]
+/// ?    | txn        | [Transaction] | transactions, the coinbase first
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Block {
+    pub header: BlockHeader,
+    pub transactions: Vec<Transaction>,
+}
+
+impl Block {
+    pub fn block_hash(&self) -> [u8; 32] {
+        self.header.block_hash()
+    }
+
+    /// Merkle root of the txids, and whether the transaction list is mutated, see
+    /// [`merkle_root`].
+    pub fn compute_merkle_root(&self) -> ([u8; 32], bool) {
+        merkle_root(self.transactions.iter().map(|tx| tx.txid()).collect())
+    }
+
+    /// Merkle root of the wtxids, with the coinbase's taken as all zero (BIP 141).
+    pub fn compute_witness_root(&self) -> [u8; 32] {
+        let wtxids = self
+            .transactions
+            .iter()
+            .enumerate()
+            .map(|(i, tx)| if i == 0 { [0; 32] } else { tx.wtxid() })
+            .collect();
+        merkle_root(wtxids).0
+    }
+
+    /// The witness commitment in the coinbase, from its last output that has one.
+    pub fn witness_commitment(&self) -> Option<[u8; 32]> {
+        let coinbase = self.transactions.first()?;
+        coinbase.outputs.iter().rev().find_map(|output| {
+            let script = &output.script_pubkey;
+            if script.len() >= 38 && script.starts_with(&WITNESS_COMMITMENT_HEADER) {
+                script[6..38].try_into().ok()
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Checks the transactions are the ones the header commits to: the merkle root
+    /// matches and isn't from a mutated list, the first and only the first transaction
+    /// is a coinbase, and witnesses match the coinbase's commitment or, without one,
+    /// there aren't any.
+    ///
+    /// Nothing about the transactions themselves is validated.
+    pub fn validate(&self) -> Result<(), BitcoinMessageError> {
+        let (root, mutated) = self.compute_merkle_root();
+        if root != self.header.merkle_root {
+            return Err(BitcoinMessageError::MerkleRootMismatch);
+        }
+        if mutated {
+            return Err(BitcoinMessageError::MerkleRootMutated);
+        }
+        match self.transactions.split_first() {
+            Some((coinbase, rest))
+                if coinbase.is_coinbase() && !rest.iter().any(|tx| tx.is_coinbase()) => {}
+            _ => return Err(BitcoinMessageError::CoinbaseInvalid),
+        }
+        self.validate_witness_commitment()
+    }
+
+    fn validate_witness_commitment(&self) -> Result<(), BitcoinMessageError> {
+        let Some(commitment) = self.witness_commitment() else {
+            if self.transactions.iter().any(|tx| tx.has_witness()) {
+                return Err(BitcoinMessageError::WitnessUnexpected);
+            }
+            return Ok(());
+        };
+        // the coinbase witness is a single 32 byte reserved value
+        let witness = &self.transactions[0].inputs[0].witness;
+        let reserved: &[u8] = match witness.as_slice() {
+            [reserved] if reserved.len() == 32 => reserved,
+            _ => return Err(BitcoinMessageError::WitnessCommitmentMismatch),
+        };
+        let mut data = self.compute_witness_root().to_vec();
+        data.extend_from_slice(reserved);
+        if sha256d(&data) != commitment {
+            return Err(BitcoinMessageError::WitnessCommitmentMismatch);
+        }
+        Ok(())
+    }
+}
+
+impl Encodable for Block {
+    fn encode<W: Write>(&self, w: &mut W) -> Result<(), BitcoinMessageError> {
+        self.header.encode(w)?;
+        write_var_array(w, &self.transactions)
+    }
+}
+
+impl Decodable for Block {
+    fn decode<R: Read>(r: &mut R) -> Result<Self, BitcoinMessageError> {
+        Ok(Block {
+            header: BlockHeader::decode(r)?,
+            transactions: read_var_array(r, MAX_SIZE)?,
+        })
+    }
+}
+
+/// Merkle root of `hashes`, as Bitcoin Core's `ComputeMerkleRoot`: pairs are hashed
+/// together level by level, the last hash paired with itself when a level is odd.
+///
+/// That makes [a, b, c] and [a, b, c, c] have the same root (CVE-2012-2459), so the
+/// second value tells whether two identical hashes were paired, which only a mutated
+/// list does. The root of no hashes is all zero.
+pub fn merkle_root(mut hashes: Vec<[u8; 32]>) -> ([u8; 32], bool) {
+    let mut mutated = false;
+    while hashes.len() > 1 {
+        // before padding, the odd one out pairing with itself is fine
+        mutated |= hashes.chunks_exact(2).any(|pair| pair[0] == pair[1]);
+        if !hashes.len().is_multiple_of(2) {
+            hashes.push(*hashes.last().unwrap());
+        }
+        hashes = hashes
+            .chunks_exact(2)
+            .map(|pair| {
+                let mut data = pair[0].to_vec();
+                data.extend_from_slice(&pair[1]);
+                sha256d(&data)
+            })
+            .collect();
+    }
+    (hashes.first().copied().unwrap_or_default(), mutated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        network::Network,
+        raw_message::RawMessage,
+        utils::{hash_to_hex, hex},
+    };
+
+    const BLOCK_0: &str = include_str!("../resources/block_0.hex");
+    const BLOCK_170: &str = include_str!("../resources/block_170.hex");
+    /// A mainnet SegWit block, committing to the witnesses of most of its 2500
+    /// transactions.
+    const BLOCK_702861: &[u8] = include_bytes!("../resources/block_702861.raw");
+
+    fn block(fixture: &str) -> Block {
+        *Block::from_bytes(&hex(fixture)).unwrap()
+    }
+
+    #[test]
+    fn genesis_block() {
+        let data = hex(BLOCK_0);
+        let block = block(BLOCK_0);
+        assert_eq!(block.header, Network::Mainnet.params().genesis);
+        assert_eq!(block.transactions.len(), 1);
+        assert_eq!(
+            block.compute_merkle_root(),
+            (block.header.merkle_root, false)
+        );
+        block.validate().unwrap();
+        assert_eq!(block.to_bytes().unwrap(), data);
+    }
+
+    #[test]
+    fn block_170() {
+        let data = hex(BLOCK_170);
+        let block = block(BLOCK_170);
+        assert_eq!(
+            hash_to_hex(&block.block_hash()),
+            "00000000d1145790a8694403d4063f323d499e655c83426834d4ce2f8dd4a2ee"
+        );
+        assert_eq!(block.transactions.len(), 2);
+        assert_eq!(
+            hash_to_hex(&block.transactions[1].txid()),
+            "f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16"
+        );
+        block.header.validate_pow(Network::Mainnet).unwrap();
+        block.validate().unwrap();
+        assert_eq!(block.to_bytes().unwrap(), data);
+        assert!(Block::from_bytes(&data[..data.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn changed_transactions() {
+        let mut block = block(BLOCK_170);
+        block.transactions[1].lock_time = 1;
+        assert!(matches!(
+            block.validate(),
+            Err(BitcoinMessageError::MerkleRootMismatch)
+        ));
+
+        let mut block = self::block(BLOCK_170);
+        block.transactions.swap(0, 1);
+        block.header.merkle_root = block.compute_merkle_root().0;
+        assert!(matches!(
+            block.validate(),
+            Err(BitcoinMessageError::CoinbaseInvalid)
+        ));
+    }
+
+    #[test]
+    fn merkle_root_mutation() {
+        let hashes: Vec<[u8; 32]> = (1..=3).map(|i| [i; 32]).collect();
+        let (root, mutated) = merkle_root(hashes.clone());
+        assert!(!mutated);
+
+        // duplicating the odd one out gives the same root
+        let mut duplicated = hashes.clone();
+        duplicated.push(hashes[2]);
+        assert_eq!(merkle_root(duplicated), (root, true));
+
+        // also when the duplicates are a level up
+        let hashes: Vec<[u8; 32]> = (1..=6).map(|i| [i; 32]).collect();
+        let (root, mutated) = merkle_root(hashes.clone());
+        assert!(!mutated);
+        let mut duplicated = hashes.clone();
+        duplicated.extend_from_slice(&hashes[4..]);
+        assert_eq!(merkle_root(duplicated), (root, true));
+
+        assert_eq!(merkle_root(vec![[7; 32]]), ([7; 32], false));
+        assert_eq!(merkle_root(Vec::new()), ([0; 32], false));
+
+        // with an odd number of transactions, repeating the last passes the root check only
+        let mut block = block(BLOCK_170);
+        let mut extra = block.transactions[1].clone();
+        extra.lock_time = 1;
+        block.transactions.push(extra.clone());
+        block.header.merkle_root = block.compute_merkle_root().0;
+        block.validate().unwrap();
+        block.transactions.push(extra);
+        assert_eq!(block.compute_merkle_root().0, block.header.merkle_root);
+        assert!(matches!(
+            block.validate(),
+            Err(BitcoinMessageError::MerkleRootMutated)
+        ));
+    }
+
+    #[test]
+    fn witness_commitment() {
+        let data = BLOCK_702861;
+        let block = *Block::from_bytes(data).unwrap();
+        assert_eq!(
+            hash_to_hex(&block.block_hash()),
+            "000000000000000000000c835b2adcaedc20fdf6ee440009c249452c726dafae"
+        );
+        assert_eq!(block.transactions.len(), 2500);
+        // as it appears in the coinbase's second output
+        assert_eq!(
+            block
+                .witness_commitment()
+                .map(|commitment| commitment.to_vec()),
+            Some(hex(
+                "71bfcc287cd6271682f35f5fba3963861571e0f186899eb0a41a5ebc360a3faa"
+            ))
+        );
+        block.header.validate_pow(Network::Mainnet).unwrap();
+        block.validate().unwrap();
+        assert_eq!(block.to_bytes().unwrap(), data);
+
+        // witnesses aren't covered by the merkle root, only by the commitment
+        let spend = block
+            .transactions
+            .iter()
+            .position(|tx| tx.has_witness())
+            .unwrap();
+        let mut changed = block.clone();
+        changed.transactions[spend].inputs[0].witness[0].push(0);
+        assert_eq!(changed.compute_merkle_root().0, block.header.merkle_root);
+        assert!(matches!(
+            changed.validate(),
+            Err(BitcoinMessageError::WitnessCommitmentMismatch)
+        ));
+
+        let mut changed = block.clone();
+        changed.transactions[0].inputs[0].witness.clear();
+        assert!(matches!(
+            changed.validate(),
+            Err(BitcoinMessageError::WitnessCommitmentMismatch)
+        ));
+
+        // without a commitment there can't be witnesses
+        let mut changed = block;
+        changed.transactions[0].outputs.pop();
+        changed.header.merkle_root = changed.compute_merkle_root().0;
+        assert!(matches!(
+            changed.validate(),
+            Err(BitcoinMessageError::WitnessUnexpected)
+        ));
+    }
+}
@@ -0,0 +1,217 @@
+use std::io::{Read, Write};
+
+use crate::{
+    encoding::{Decodable, Encodable},
+    errors::BitcoinMessageError,
+    network::Network,
+    uint::U256,
+    utils::sha256d,
+};
+
+pub const BLOCK_HEADER_SIZE: usize = 80;
+
+/// https://en.bitcoin.it/wiki/Protocol_documentation#Block_Headers
+///
+/// size | field       | type     | description
+/// ---  | -----       | ----     | ------------
+/// 4    | version     | i32      | block version, with BIP 9 version bits
+/// 32   | prev_block  | [u8; 32] | hash of the previous block header
+/// 32   | merkle_root | [u8; 32] | root of the merkle tree of the block's transactions
+/// 4    | time        | u32      | when the miner started hashing, UNIX timestamp
+/// 4    | bits        | u32      | target of the proof of work, in compact form
+/// 4    | nonce       | u32      | nonce varied to meet the target
+///
+/// Hashes are in internal byte order, the reverse of how they're displayed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockHeader {
+    pub version: i32,
+    pub prev_block: [u8; 32],
+    pub merkle_root: [u8; 32],
+    pub time: u32,
+    pub bits: u32,
+    pub nonce: u32,
+}
+
+impl BlockHeader {
+    /// The block hash, sha256d of the serialized header.
+    pub fn block_hash(&self) -> [u8; 32] {
+        let mut data = Vec::with_capacity(BLOCK_HEADER_SIZE);
+        // writing to a Vec doesn't fail
+        self.encode(&mut data).unwrap();
+        sha256d(&data)
+    }
+
+    /// The target the block hash has to be at or below.
+    pub fn target(&self) -> Result<U256, BitcoinMessageError> {
+        U256::from_compact(self.bits).ok_or(BitcoinMessageError::TargetInvalid(self.bits))
+    }
+
+    /// How many times harder than the mainnet minimum the target is, as Bitcoin Core's
+    /// `GetDifficulty`.
+    pub fn difficulty(&self) -> f64 {
+        let mut shift = (self.bits >> 24) & 0xff;
+        let mut difficulty = 0x0000ffff as f64 / (self.bits & 0x00ffffff) as f64;
+        while shift < 29 {
+            difficulty *= 256.0;
+            shift += 1;
+        }
+        while shift > 29 {
+            difficulty /= 256.0;
+            shift -= 1;
+        }
+        difficulty
+    }
+
+    /// Checks the block hash meets a target that's allowed on `network`.
+    ///
+    /// Whether `bits` is the target the chain asks for at this height isn't checked.
+    pub fn validate_pow(&self, network: Network) -> Result<(), BitcoinMessageError> {
+        let target = self.target()?;
+        let pow_limit = network.params().pow_limit_bits;
+        if target.is_zero() || target > U256::from_compact(pow_limit).unwrap_or(U256::ZERO) {
+            return Err(BitcoinMessageError::TargetInvalid(self.bits));
+        }
+        if U256::from_le_bytes(self.block_hash()) > target {
+            return Err(BitcoinMessageError::ProofOfWorkInvalid);
+        }
+        Ok(())
+    }
+}
+
+impl Encodable for BlockHeader {
+    fn encode<W: Write>(&self, w: &mut W) -> Result<(), BitcoinMessageError> {
+        self.version.encode(w)?;
+        self.prev_block.encode(w)?;
+        self.merkle_root.encode(w)?;
+        self.time.encode(w)?;
+        self.bits.encode(w)?;
+        self.nonce.encode(w)
+    }
+}
+
+impl Decodable for BlockHeader {
+    fn decode<R: Read>(r: &mut R) -> Result<Self, BitcoinMessageError> {
+        Ok(BlockHeader {
+            version: i32::decode(r)?,
+            prev_block: <[u8; 32]>::decode(r)?,
+            merkle_root: <[u8; 32]>::decode(r)?,
+            time: u32::decode(r)?,
+            bits: u32::decode(r)?,
+            nonce: u32::decode(r)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        raw_message::RawMessage,
+        utils::{hash_from_hex, hash_to_hex, hex},
+    };
+
+    /// Mainnet headers: the genesis block, blocks 1 and 2, and block 125552, the example
+    /// of https://en.bitcoin.it/wiki/Block_hashing_algorithm.
+    const MAINNET_HEADERS: [(&str, &str); 4] = [
+        (
+            "0100000000000000000000000000000000000000000000000000000000000000\
+             000000003ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa\
+             4b1e5e4a29ab5f49ffff001d1dac2b7c",
+            "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f",
+        ),
+        (
+            "010000006fe28c0ab6f1b372c1a6a246ae63f74f931e8365e15a089c68d61900\
+             00000000982051fd1e4ba744bbbe680e1fee14677ba1a3c3540bf7b1cdb606e8\
+             57233e0e61bc6649ffff001d01e36299",
+            "00000000839a8e6886ab5951d76f411475428afc90947ee320161bbf18eb6048",
+        ),
+        (
+            "010000004860eb18bf1b1620e37e9490fc8a427514416fd75159ab86688e9a83\
+             00000000d5fdcc541e25de1c7a5addedf24858b8bb665c9f36ef744ee42c3160\
+             22c90f9bb0bc6649ffff001d08d2bd61",
+            "000000006a625f06636b8bb6ac7b960a8d03705d1ace08b1a19da3fdcc99ddbd",
+        ),
+        (
+            "0100000081cd02ab7e569e8bcd9317e2fe99f2de44d49ab2b8851ba4a3080000\
+             00000000e320b6c2fffc8d750423db8b1eb942ae710e951ed797f7affc8892b0\
+             f1fc122bc7f5d74df2b9441a42a14695",
+            "00000000000000001e8d6829a8a21adc5d38d0a473b144b6765798e61f98bd1d",
+        ),
+    ];
+
+    fn header(i: usize) -> BlockHeader {
+        *BlockHeader::from_bytes(&hex(MAINNET_HEADERS[i].0)).unwrap()
+    }
+
+    #[test]
+    fn genesis() {
+        let genesis = header(0);
+        assert_eq!(genesis.version, 1);
+        assert_eq!(genesis.prev_block, [0; 32]);
+        assert_eq!(
+            genesis.merkle_root,
+            hash_from_hex("4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b")
+        );
+        assert_eq!(genesis.time, 1231006505);
+        assert_eq!(genesis.bits, 0x1d00ffff);
+        assert_eq!(genesis.nonce, 2083236893);
+        assert_eq!(genesis.block_hash(), Network::Mainnet.params().genesis_hash);
+        assert_eq!(genesis.difficulty(), 1.0);
+    }
+
+    #[test]
+    fn mainnet_headers() {
+        for (i, (header_hex, hash)) in MAINNET_HEADERS.into_iter().enumerate() {
+            let data = hex(header_hex);
+            assert_eq!(data.len(), BLOCK_HEADER_SIZE);
+            let header = header(i);
+            assert_eq!(header.to_bytes().unwrap(), data);
+            assert_eq!(hash_to_hex(&header.block_hash()), hash);
+            header.validate_pow(Network::Mainnet).unwrap();
+        }
+        // each links to the one before
+        assert_eq!(header(1).prev_block, header(0).block_hash());
+        assert_eq!(header(2).prev_block, header(1).block_hash());
+    }
+
+    #[test]
+    fn difficulty() {
+        let header = header(3);
+        assert_eq!(header.bits, 0x1a44b9f2);
+        assert!((header.difficulty() - 244_112.487_774_74).abs() < 1e-6);
+        assert_eq!(
+            header.target().unwrap(),
+            U256::from(0x44b9f2) << (8 * (0x1a - 3))
+        );
+    }
+
+    #[test]
+    fn invalid_pow() {
+        let mut header = header(1);
+        header.nonce += 1;
+        assert!(matches!(
+            header.validate_pow(Network::Mainnet),
+            Err(BitcoinMessageError::ProofOfWorkInvalid)
+        ));
+
+        // regtest's easier target isn't allowed on mainnet
+        header.bits = Network::Regtest.params().pow_limit_bits;
+        assert!(matches!(
+            header.validate_pow(Network::Mainnet),
+            Err(BitcoinMessageError::TargetInvalid(0x207fffff))
+        ));
+        for bits in [0, 0x04923456, 0xff123456] {
+            header.bits = bits;
+            assert!(matches!(
+                header.validate_pow(Network::Regtest),
+                Err(BitcoinMessageError::TargetInvalid(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn too_short() {
+        let data = hex(MAINNET_HEADERS[0].0);
+        assert!(BlockHeader::from_bytes(&data[..BLOCK_HEADER_SIZE - 1]).is_err());
+    }
+}
@@ -1,16 +1,18 @@
-use std::net::{Ipv4Addr, SocketAddrV4};
+use std::io::{Read, Write};
 
 use crate::{
+    command::{Command, COMMAND_NAME_SIZE},
+    encoding::{Decodable, Encodable},
     errors::BitcoinMessageError,
-    payload::{Payload, VersionMessage},
-    utils::{parse_frombytes_le, read_drop_slice},
+    network::Network,
+    payload::{Payload, MAX_SIZE},
+    utils::{checksum, CHECKSUM_SIZE},
 };
 
 // const PROTOCOL_VERSION: i32 = 70016;
 
-// const START_STRING: [u8; 4] = [0xf9, 0xbe, 0xb4, 0xd9];
-pub const MAGIC_NUMBER: u32 = 0xD9B4BEF9;  //mainnet
-// pub const MAGIC_NUMBER: u32 = 0xDAB5BFFA; //testnet
+/// Size of the message header: magic, command, length and checksum.
+pub const HEADER_SIZE: usize = 24;
 
 /// Message structure (see https://en.bitcoin.it/wiki/Protocol_documentation#Message_structure)
 ///
@@ -37,43 +39,227 @@ pub struct BtcMessage {
 }
 
 impl BtcMessage {
-    /// Creates new [`Message`].
-    pub fn new(command: [u8; 12], payload: Payload, checksum: u32) -> Self {
+    /// Creates new [`Message`] for `network`.
+    pub fn new(network: Network, command: [u8; 12], payload: Payload, checksum: u32) -> Self {
         Self {
-            magic_number: MAGIC_NUMBER,
+            magic_number: network.magic(),
             command,
             payload,
             checksum,
         }
     }
 
+    /// Creates a `command` message for `network`, with the checksum of `payload`.
+    pub fn with_payload(
+        network: Network,
+        command: Command,
+        payload: Payload,
+    ) -> Result<Self, BitcoinMessageError> {
+        let checksum = u32::from_ne_bytes(checksum(&payload.to_bytes()?));
+        Ok(Self::new(
+            network,
+            command.to_padded_bytes(),
+            payload,
+            checksum,
+        ))
+    }
+
+    pub fn network(&self) -> Option<Network> {
+        Network::from_magic(self.magic_number)
+    }
+
     pub fn to_bytes(&self) -> Result<Vec<u8>, BitcoinMessageError> {
-        let mut buff = Vec::new();
-        buff.extend_from_slice(&self.magic_number.to_le_bytes());
-        buff.extend_from_slice(&self.command);
-        buff.extend_from_slice(&(self.payload.to_bytes().unwrap().len() as u32).to_le_bytes());
-        buff.extend_from_slice(&self.checksum.to_ne_bytes());
-        buff.extend_from_slice(&self.payload.to_bytes().unwrap());
-        Ok(buff)
-    }
-
-    pub fn from_bytes(data: &[u8]) -> Result<Box<Self>, BitcoinMessageError> {
-        println!("{:?}", data);
-        let (magic, buff) = parse_frombytes_le::<u32>(&data.to_vec())?;
-        let (cmd, buff) = read_drop_slice(&buff, 12)?;
-        println!("{:?}", cmd);
-        let command = <[u8; 12]>::try_from(cmd).unwrap();
-        // let (length, buff) = parse_frombytes_le::<u32>(&buff)?;
-        let (checksum, _) = parse_frombytes_le::<u32>(&buff)?;
-        let address = SocketAddrV4::new(Ipv4Addr::new(162, 120,69, 182), 8333);
- 
-
-        Ok(Box::new(Self {
-            magic_number: magic,
+        let mut buffer = Vec::new();
+        self.encode(&mut buffer)?;
+        Ok(buffer)
+    }
+
+    /// Decodes a message of `network`. Bytes after the message are ignored.
+    pub fn from_bytes(mut data: &[u8], network: Network) -> Result<Box<Self>, BitcoinMessageError> {
+        Ok(Box::new(Self::decode(&mut data, network)?))
+    }
+
+    /// The payload of this message.
+    pub fn payload(&self) -> &Payload {
+        &self.payload
+    }
+
+    pub fn into_payload(self) -> Payload {
+        self.payload
+    }
+
+    /// Reads the payload length from a message header, so the caller knows how many
+    /// bytes to read after it.
+    pub fn payload_len(header: &[u8]) -> Result<usize, BitcoinMessageError> {
+        let mut length = header.get(4 + COMMAND_NAME_SIZE..).unwrap_or_default();
+        let length = u32::decode(&mut length)? as usize;
+        if length > MAX_SIZE {
+            return Err(BitcoinMessageError::PayloadTooBig);
+        }
+        Ok(length)
+    }
+}
+
+impl Encodable for BtcMessage {
+    fn encode<W: Write>(&self, w: &mut W) -> Result<(), BitcoinMessageError> {
+        let payload = self.payload.to_bytes()?;
+        self.magic_number.encode(w)?;
+        self.command.encode(w)?;
+        (payload.len() as u32).encode(w)?;
+        self.checksum.to_ne_bytes().encode(w)?;
+        Ok(w.write_all(&payload)?)
+    }
+}
+
+impl BtcMessage {
+    /// Reads a message of `network` from its header and exactly `length` bytes of payload.
+    pub fn decode<R: Read>(r: &mut R, network: Network) -> Result<Self, BitcoinMessageError> {
+        let magic_number = u32::decode(r)?;
+        if magic_number != network.magic() {
+            return Err(BitcoinMessageError::WrongNetwork(magic_number, network));
+        }
+        let command = <[u8; COMMAND_NAME_SIZE]>::decode(r)?;
+        let length = u32::decode(r)? as usize;
+        if length > MAX_SIZE {
+            return Err(BitcoinMessageError::PayloadTooBig);
+        }
+        let expected_checksum = <[u8; CHECKSUM_SIZE]>::decode(r)?;
+
+        // the length isn't trusted for preallocation until the bytes are there
+        let mut payload = Vec::new();
+        r.take(length as u64).read_to_end(&mut payload)?;
+        if payload.len() < length {
+            return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+        }
+        if checksum(&payload) != expected_checksum {
+            return Err(BitcoinMessageError::ChecksumMismatch);
+        }
+        let payload = Payload::from_bytes(&payload, &Command::from_bytes(&command)?)?;
+
+        Ok(Self {
+            magic_number,
             command,
-            payload: Payload::Version(VersionMessage::new(address)), // not checking the payload
+            payload,
+            // same byte order as in `encode`
+            checksum: u32::from_ne_bytes(expected_checksum),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        payload::VersionMessage,
+        raw_message::RawMessage,
+        utils::{VERSION_COMMAND, VER_ACK_COMMAND},
+    };
+
+    // verack as sent by a mainnet node
+    const VERACK: [u8; 24] = [
+        0xf9, 0xbe, 0xb4, 0xd9, b'v', b'e', b'r', b'a', b'c', b'k', 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        0x5d, 0xf6, 0xe0, 0xe2,
+    ];
+
+    #[test]
+    fn verack_from_bytes() {
+        let msg = BtcMessage::from_bytes(&VERACK, Network::Mainnet).unwrap();
+        assert_eq!(msg.network(), Some(Network::Mainnet));
+        assert_eq!(msg.command, VER_ACK_COMMAND);
+        assert!(matches!(msg.payload(), Payload::Empty));
+        assert_eq!(msg.to_bytes().unwrap(), VERACK);
+    }
+
+    #[test]
+    fn version_round_trip() {
+        let version = VersionMessage::new("10.0.0.1:8333".parse().unwrap());
+        let payload = version.to_bytes().unwrap();
+        let checksum = u32::from_ne_bytes(checksum(&payload));
+        let data = BtcMessage::new(
+            Network::Regtest,
+            VERSION_COMMAND,
+            Payload::Version(version.clone()),
             checksum,
-        }))
-        // Err(BitcoinMessageError::ChecksumMismatch)
+        )
+        .to_bytes()
+        .unwrap();
+        let msg = BtcMessage::from_bytes(&data, Network::Regtest).unwrap();
+        assert!(matches!(msg.payload(), Payload::Version(v) if *v == version));
+        assert_eq!(msg.to_bytes().unwrap(), data);
+    }
+
+    #[test]
+    fn wrong_network() {
+        for network in [
+            Network::Testnet3,
+            Network::Testnet4,
+            Network::Signet,
+            Network::Regtest,
+        ] {
+            assert!(matches!(
+                BtcMessage::from_bytes(&VERACK, network),
+                Err(BitcoinMessageError::WrongNetwork(0xD9B4BEF9, n)) if n == network
+            ));
+        }
+        let mut data = VERACK;
+        data[..4].copy_from_slice(&[0x0b, 0x11, 0x09, 0x07]);
+        assert!(BtcMessage::from_bytes(&data, Network::Testnet3).is_ok());
+    }
+
+    #[test]
+    fn trailing_bytes_are_ignored() {
+        let mut data = VERACK.to_vec();
+        data.extend_from_slice(&VERACK);
+        assert!(BtcMessage::from_bytes(&data, Network::Mainnet).is_ok());
+    }
+
+    #[test]
+    fn checksum_mismatch() {
+        let mut data = VERACK;
+        data[23] ^= 0xff;
+        assert!(matches!(
+            BtcMessage::from_bytes(&data, Network::Mainnet),
+            Err(BitcoinMessageError::ChecksumMismatch)
+        ));
+    }
+
+    #[test]
+    fn payload_shorter_than_length() {
+        let mut data = VERACK;
+        data[16] = 1;
+        assert!(matches!(
+            BtcMessage::from_bytes(&data, Network::Mainnet),
+            Err(BitcoinMessageError::SerializationError(_))
+        ));
+        assert_eq!(BtcMessage::payload_len(&data).unwrap(), 1);
+    }
+
+    #[test]
+    fn payload_too_big() {
+        let mut data = VERACK;
+        data[16..20].copy_from_slice(&(MAX_SIZE as u32 + 1).to_le_bytes());
+        assert!(matches!(
+            BtcMessage::payload_len(&data),
+            Err(BitcoinMessageError::PayloadTooBig)
+        ));
+        assert!(matches!(
+            BtcMessage::from_bytes(&data, Network::Mainnet),
+            Err(BitcoinMessageError::PayloadTooBig)
+        ));
+    }
+
+    #[test]
+    fn unknown_command() {
+        let mut data = VERACK;
+        data[4..16].copy_from_slice(b"sendcmpct\0\0\0");
+        assert!(matches!(
+            BtcMessage::from_bytes(&data, Network::Mainnet),
+            Err(BitcoinMessageError::CommandNameUnknown(name)) if name == "sendcmpct"
+        ));
+    }
+
+    #[test]
+    fn header_too_short() {
+        assert!(BtcMessage::from_bytes(&VERACK[..20], Network::Mainnet).is_err());
     }
 }
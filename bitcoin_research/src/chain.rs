@@ -0,0 +1,700 @@
+//! Chain of block headers, validated and synchronised headers-first.
+
+use std::collections::HashMap;
+use std::time::{Duration, UNIX_EPOCH};
+
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::time;
+
+use crate::{
+    block_header::BlockHeader,
+    command::Command,
+    errors::BitcoinMessageError,
+    headers::{GetHeadersMessage, MAX_HEADERS_RESULTS},
+    network::{Network, NetworkParams},
+    payload::Payload,
+    peer::Peer,
+    uint::U256,
+    utils::hash_to_hex,
+};
+
+/// Blocks between two changes of the target.
+pub const DIFFICULTY_ADJUSTMENT_INTERVAL: u32 = 2016;
+/// Time between blocks the target aims for.
+pub const POW_TARGET_SPACING: u32 = 10 * 60;
+/// Time [`DIFFICULTY_ADJUSTMENT_INTERVAL`] blocks should take.
+pub const POW_TARGET_TIMESPAN: u32 = 14 * 24 * 60 * 60;
+/// How far ahead of our clock a block's time may be.
+pub const MAX_FUTURE_BLOCK_TIME: u32 = 2 * 60 * 60;
+/// Number of blocks whose median time a new block has to be after.
+const MEDIAN_TIME_SPAN: usize = 11;
+
+/// A header in the chain store.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChainEntry {
+    pub hash: [u8; 32],
+    pub header: BlockHeader,
+    pub height: u32,
+    /// Work of this block and all its ancestors.
+    pub chain_work: U256,
+}
+
+/// What accepting a header did to the best chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChainUpdate {
+    /// The header was already known.
+    Duplicate,
+    /// The header is on a chain with less or as much work as the best one.
+    SideChain,
+    /// The header is the new tip, on top of the old one.
+    Extended,
+    /// The header is the new tip of a chain that split from the old best chain after
+    /// `fork_height`.
+    Reorg { fork_height: u32 },
+}
+
+/// All valid headers we know of, and the chain with the most work among them.
+///
+/// Headers are kept in memory, starting from the genesis block of the network.
+pub struct HeaderChain {
+    network: Network,
+    params: &'static NetworkParams,
+    entries: HashMap<[u8; 32], ChainEntry>,
+    /// Hashes of the best chain, by height.
+    active: Vec<[u8; 32]>,
+}
+
+impl HeaderChain {
+    pub fn new(network: Network) -> Self {
+        Self::with_params(network, network.params())
+    }
+
+    /// A chain of `network` that follows `params` instead of the network's own.
+    pub(crate) fn with_params(network: Network, params: &'static NetworkParams) -> Self {
+        let genesis = ChainEntry {
+            hash: params.genesis_hash,
+            header: params.genesis,
+            height: 0,
+            chain_work: block_proof(params.genesis.bits),
+        };
+        HeaderChain {
+            network,
+            params,
+            entries: HashMap::from([(genesis.hash, genesis)]),
+            active: vec![params.genesis_hash],
+        }
+    }
+
+    pub fn network(&self) -> Network {
+        self.network
+    }
+
+    /// Height of the best chain.
+    pub fn height(&self) -> u32 {
+        self.active.len() as u32 - 1
+    }
+
+    /// Last header of the best chain.
+    pub fn tip(&self) -> &ChainEntry {
+        &self.entries[self.active.last().unwrap()]
+    }
+
+    /// A header on any chain we know.
+    pub fn get(&self, hash: &[u8; 32]) -> Option<&ChainEntry> {
+        self.entries.get(hash)
+    }
+
+    /// The hash at `height` in the best chain.
+    pub fn hash_at(&self, height: u32) -> Option<[u8; 32]> {
+        self.active.get(height as usize).copied()
+    }
+
+    /// Hashes of the best chain for `getheaders`, as Bitcoin Core's `GetLocator`: the
+    /// last ten, then exponentially further apart down to the genesis block.
+    pub fn locator(&self) -> Vec<[u8; 32]> {
+        let mut locator = Vec::new();
+        let mut height = self.height();
+        let mut step = 1;
+        loop {
+            locator.push(self.active[height as usize]);
+            if height == 0 {
+                return locator;
+            }
+            height = height.saturating_sub(step);
+            if locator.len() > 10 {
+                step *= 2;
+            }
+        }
+    }
+
+    /// Validates `header` and adds it to the store, switching the best chain if it now
+    /// has the most work.
+    ///
+    /// Checks the header connects to one we know, asks for the target the chain
+    /// requires, meets it, and has a time after the median of the last 11 blocks and no
+    /// more than [`MAX_FUTURE_BLOCK_TIME`] ahead of ours.
+    pub fn accept_header(
+        &mut self,
+        header: BlockHeader,
+    ) -> Result<ChainUpdate, BitcoinMessageError> {
+        let hash = header.block_hash();
+        if self.entries.contains_key(&hash) {
+            return Ok(ChainUpdate::Duplicate);
+        }
+        let parent = self.entries.get(&header.prev_block).ok_or_else(|| {
+            BitcoinMessageError::PrevBlockUnknown(hash_to_hex(&header.prev_block))
+        })?;
+
+        let expected = self.next_work_required(parent, &header);
+        if header.bits != expected {
+            return Err(BitcoinMessageError::DifficultyUnexpected {
+                expected,
+                got: header.bits,
+            });
+        }
+        header.validate_pow(self.network)?;
+        if header.time <= self.median_time_past(parent) {
+            return Err(BitcoinMessageError::TimeTooOld(header.time));
+        }
+        let now = UNIX_EPOCH.elapsed().unwrap_or_default().as_secs();
+        if header.time as u64 > now + MAX_FUTURE_BLOCK_TIME as u64 {
+            return Err(BitcoinMessageError::TimeTooNew(header.time));
+        }
+
+        let entry = ChainEntry {
+            hash,
+            header,
+            height: parent.height + 1,
+            chain_work: parent.chain_work + block_proof(header.bits),
+        };
+        // on equal work the chain seen first stays, like Bitcoin Core does
+        let update = if entry.chain_work <= self.tip().chain_work {
+            ChainUpdate::SideChain
+        } else if header.prev_block == self.tip().hash {
+            self.active.push(hash);
+            ChainUpdate::Extended
+        } else {
+            ChainUpdate::Reorg {
+                fork_height: self.set_tip(&entry),
+            }
+        };
+        self.entries.insert(hash, entry);
+        Ok(update)
+    }
+
+    /// Accepts headers in order, returning how many were new. Headers before an invalid
+    /// one stay accepted.
+    pub fn accept_headers(
+        &mut self,
+        headers: &[BlockHeader],
+    ) -> Result<usize, BitcoinMessageError> {
+        let mut accepted = 0;
+        for header in headers {
+            if self.accept_header(*header)? != ChainUpdate::Duplicate {
+                accepted += 1;
+            }
+        }
+        Ok(accepted)
+    }
+
+    /// Downloads the headers `peer` has beyond our best chain, returning how many were
+    /// new.
+    ///
+    /// Other messages coming in meanwhile are handled by the peer and dropped. Fails if
+    /// the peer doesn't answer a `getheaders` within `timeout`, and with [`BitcoinMessageError::HeadersUnhelpful`] if a full batch has nothing
+    /// new, as asking again would get the same batch.
+    pub async fn sync<S: AsyncRead + AsyncWrite + Unpin>(
+        &mut self,
+        peer: &mut Peer<S>,
+        timeout: Duration,
+    ) -> Result<usize, BitcoinMessageError> {
+        let mut accepted = 0;
+        loop {
+            let request = GetHeadersMessage::new(self.locator());
+            peer.send(Command::GetHeaders, Payload::GetHeaders(request))
+                .await?;
+            let headers = wait_for_headers(peer, timeout).await?;
+            let new = self.accept_headers(&headers)?;
+            accepted += new;
+            if headers.len() < MAX_HEADERS_RESULTS {
+                return Ok(accepted);
+            }
+            if new == 0 {
+                return Err(BitcoinMessageError::HeadersUnhelpful);
+            }
+        }
+    }
+
+    /// Makes the best chain end at `tip`, returning the height it forked at.
+    fn set_tip(&mut self, tip: &ChainEntry) -> u32 {
+        let mut connected = vec![tip.hash];
+        let mut entry = &self.entries[&tip.header.prev_block];
+        while self.active.get(entry.height as usize) != Some(&entry.hash) {
+            connected.push(entry.hash);
+            entry = &self.entries[&entry.header.prev_block];
+        }
+        let fork_height = entry.height;
+        self.active.truncate(fork_height as usize + 1);
+        self.active.extend(connected.into_iter().rev());
+        fork_height
+    }
+
+    /// The ancestor of `entry` at `height`, which has to be at most `entry.height`.
+    fn ancestor<'a>(&'a self, mut entry: &'a ChainEntry, height: u32) -> &'a ChainEntry {
+        while entry.height > height {
+            // the rest of the way is in the best chain
+            if self.active.get(entry.height as usize) == Some(&entry.hash) {
+                return &self.entries[&self.active[height as usize]];
+            }
+            entry = &self.entries[&entry.header.prev_block];
+        }
+        entry
+    }
+
+    /// Median time of `last` and the 10 blocks before it.
+    fn median_time_past(&self, last: &ChainEntry) -> u32 {
+        let mut times = Vec::with_capacity(MEDIAN_TIME_SPAN);
+        let mut entry = last;
+        loop {
+            times.push(entry.header.time);
+            if entry.height == 0 || times.len() == MEDIAN_TIME_SPAN {
+                break;
+            }
+            entry = &self.entries[&entry.header.prev_block];
+        }
+        times.sort_unstable();
+        times[times.len() / 2]
+    }
+
+    /// The `bits` a header on top of `last` has to have, as Bitcoin Core's
+    /// `GetNextWorkRequired`.
+    fn next_work_required(&self, last: &ChainEntry, header: &BlockHeader) -> u32 {
+        let params = self.params;
+        let height = last.height + 1;
+        if !height.is_multiple_of(DIFFICULTY_ADJUSTMENT_INTERVAL) {
+            if !params.allow_min_difficulty_blocks {
+                return last.header.bits;
+            }
+            // testnets: after 20 minutes without a block, the easiest target will do
+            if header.time > last.header.time.saturating_add(2 * POW_TARGET_SPACING) {
+                return params.pow_limit_bits;
+            }
+            // otherwise the target of the last block that didn't use that exception
+            let mut entry = last;
+            while !entry.height.is_multiple_of(DIFFICULTY_ADJUSTMENT_INTERVAL)
+                && entry.header.bits == params.pow_limit_bits
+            {
+                entry = &self.entries[&entry.header.prev_block];
+            }
+            return entry.header.bits;
+        }
+        let first = self.ancestor(last, height - DIFFICULTY_ADJUSTMENT_INTERVAL);
+        calculate_next_work_required(params, &first.header, &last.header)
+    }
+}
+
+/// The target after the period from `first` to `last`, scaled by how much longer or
+/// shorter than [`POW_TARGET_TIMESPAN`] it took, at most by a factor of 4.
+pub fn calculate_next_work_required(
+    params: &NetworkParams,
+    first: &BlockHeader,
+    last: &BlockHeader,
+) -> u32 {
+    if params.no_retargeting {
+        return last.bits;
+    }
+    let timespan = (last.time as i64 - first.time as i64).clamp(
+        POW_TARGET_TIMESPAN as i64 / 4,
+        POW_TARGET_TIMESPAN as i64 * 4,
+    );
+    // testnet4 doesn't let a last block at minimum difficulty ease the next period
+    let bits = if params.enforce_bip94 {
+        first.bits
+    } else {
+        last.bits
+    };
+    // the pow limits of all networks are valid targets
+    let pow_limit = U256::from_compact(params.pow_limit_bits).unwrap();
+    let target = U256::from_compact(bits).unwrap_or(pow_limit);
+    let target = target * timespan as u64 / U256::from(POW_TARGET_TIMESPAN as u64);
+    target.min(pow_limit).to_compact()
+}
+
+/// Expected number of hashes to find a block with target `bits`, 2^256 / (target + 1).
+/// Invalid targets are worth nothing.
+pub fn block_proof(bits: u32) -> U256 {
+    match U256::from_compact(bits) {
+        // 2^256 doesn't fit, but (2^256 - target - 1) / (target + 1) + 1 is the same
+        Some(target) if !target.is_zero() => !target / (target + U256::from(1)) + U256::from(1),
+        _ => U256::ZERO,
+    }
+}
+
+/// Waits for a `headers` message, dropping everything else.
+async fn wait_for_headers<S: AsyncRead + AsyncWrite + Unpin>(
+    peer: &mut Peer<S>,
+    timeout: Duration,
+) -> Result<Vec<BlockHeader>, BitcoinMessageError> {
+    let headers = async {
+        loop {
+            if let Payload::Headers(headers) = peer.next_message().await?.into_payload() {
+                return Ok(headers);
+            }
+        }
+    };
+    time::timeout(timeout, headers)
+        .await
+        .unwrap_or(Err(BitcoinMessageError::Timeout(Command::Headers)))
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{SinkExt, StreamExt};
+    use tokio::io::DuplexStream;
+
+    use super::*;
+    use crate::btc_message::BtcMessage;
+    use crate::peer::tests::{accept_handshake, mock_peer};
+
+    const NETWORK: Network = Network::Regtest;
+
+    /// Mines a regtest header on top of `prev`, which takes 2 tries on average.
+    fn mine(prev: &BlockHeader, time: u32, tag: u8) -> BlockHeader {
+        mine_with_bits(prev, time, tag, prev.bits)
+    }
+
+    /// Mines a header with target `bits` on top of `prev`.
+    fn mine_with_bits(prev: &BlockHeader, time: u32, tag: u8, bits: u32) -> BlockHeader {
+        let mut header = BlockHeader {
+            version: 4,
+            prev_block: prev.block_hash(),
+            merkle_root: [tag; 32],
+            time,
+            bits,
+            nonce: 0,
+        };
+        while header.validate_pow(NETWORK).is_err() {
+            header.nonce += 1;
+        }
+        header
+    }
+
+    /// `n` headers on top of `prev`, 10 minutes apart. `tag` tells forks apart.
+    fn mine_chain(prev: &BlockHeader, n: usize, tag: u8) -> Vec<BlockHeader> {
+        let mut headers: Vec<BlockHeader> = Vec::with_capacity(n);
+        for _ in 0..n {
+            let prev = headers.last().unwrap_or(prev);
+            headers.push(mine(prev, prev.time + POW_TARGET_SPACING, tag));
+        }
+        headers
+    }
+
+    fn genesis() -> BlockHeader {
+        NETWORK.params().genesis
+    }
+
+    #[test]
+    fn extends_the_best_chain() {
+        let mut chain = HeaderChain::new(NETWORK);
+        assert_eq!(chain.height(), 0);
+        let headers = mine_chain(&genesis(), 30, 0);
+        for header in &headers {
+            assert_eq!(chain.accept_header(*header).unwrap(), ChainUpdate::Extended);
+        }
+        assert_eq!(chain.accept_headers(&headers).unwrap(), 0);
+        assert_eq!(chain.height(), 30);
+        assert_eq!(chain.tip().header, headers[29]);
+        assert_eq!(chain.tip().chain_work, block_proof(genesis().bits) * 31);
+        assert_eq!(chain.hash_at(1), Some(headers[0].block_hash()));
+
+        let heights: Vec<u32> = chain
+            .locator()
+            .iter()
+            .map(|hash| chain.get(hash).unwrap().height)
+            .collect();
+        assert_eq!(
+            heights,
+            [30, 29, 28, 27, 26, 25, 24, 23, 22, 21, 20, 19, 17, 13, 5, 0]
+        );
+    }
+
+    #[test]
+    fn reorgs_to_the_most_work() {
+        let mut chain = HeaderChain::new(NETWORK);
+        let main = mine_chain(&genesis(), 5, 0);
+        chain.accept_headers(&main).unwrap();
+
+        // a fork after height 2, as long as the best chain and then longer
+        let fork = mine_chain(&main[1], 4, 1);
+        assert_eq!(chain.accept_headers(&fork[..3]).unwrap(), 3);
+        assert_eq!(chain.tip().header, main[4]);
+        assert_eq!(
+            chain.accept_header(fork[3]).unwrap(),
+            ChainUpdate::Reorg { fork_height: 2 }
+        );
+        assert_eq!(chain.height(), 6);
+        assert_eq!(chain.hash_at(2), Some(main[1].block_hash()));
+        assert_eq!(chain.hash_at(3), Some(fork[0].block_hash()));
+        assert_eq!(chain.tip().header, fork[3]);
+
+        // and back, once the old chain overtakes it
+        let more = mine_chain(&main[4], 2, 0);
+        assert_eq!(
+            chain.accept_header(more[0]).unwrap(),
+            ChainUpdate::SideChain
+        );
+        assert_eq!(
+            chain.accept_header(more[1]).unwrap(),
+            ChainUpdate::Reorg { fork_height: 2 }
+        );
+        assert_eq!(chain.height(), 7);
+        assert_eq!(chain.hash_at(3), Some(main[2].block_hash()));
+        assert_eq!(chain.tip().header, more[1]);
+        // the fork is still known
+        assert_eq!(chain.get(&fork[3].block_hash()).unwrap().height, 6);
+    }
+
+    #[test]
+    fn rejects_invalid_headers() {
+        let mut chain = HeaderChain::new(NETWORK);
+        let headers = mine_chain(&genesis(), 11, 0);
+        chain.accept_headers(&headers).unwrap();
+        let tip = headers[10];
+
+        let orphan = mine(&mine(&tip, tip.time + 1, 0), tip.time + 2, 0);
+        assert!(matches!(
+            chain.accept_header(orphan),
+            Err(BitcoinMessageError::PrevBlockUnknown(_))
+        ));
+
+        let mut header = mine(&tip, tip.time + 1, 0);
+        header.bits = 0x1d00ffff;
+        assert!(matches!(
+            chain.accept_header(header),
+            Err(BitcoinMessageError::DifficultyUnexpected {
+                expected: 0x207fffff,
+                got: 0x1d00ffff
+            })
+        ));
+
+        let mut header = mine(&tip, tip.time + 1, 0);
+        while header.validate_pow(NETWORK).is_ok() {
+            header.nonce += 1;
+        }
+        assert!(matches!(
+            chain.accept_header(header),
+            Err(BitcoinMessageError::ProofOfWorkInvalid)
+        ));
+
+        // the median of the last 11 blocks is the time of the 6th
+        let median = headers[5].time;
+        assert!(matches!(
+            chain.accept_header(mine(&tip, median, 0)),
+            Err(BitcoinMessageError::TimeTooOld(_))
+        ));
+        chain.accept_header(mine(&tip, median + 1, 0)).unwrap();
+
+        let now = UNIX_EPOCH.elapsed().unwrap().as_secs() as u32;
+        assert!(matches!(
+            chain.accept_header(mine(&tip, now + MAX_FUTURE_BLOCK_TIME + 60, 0)),
+            Err(BitcoinMessageError::TimeTooNew(_))
+        ));
+        assert_eq!(chain.height(), 12);
+    }
+
+    #[test]
+    fn retargeting() {
+        // from Bitcoin Core's pow_tests.cpp, the first is the first retarget of mainnet
+        let cases = [
+            (1261130161, 1262152739, 0x1d00ffff, 0x1d00d86a),
+            // at the pow limit already
+            (1231006505, 1233061996, 0x1d00ffff, 0x1d00ffff),
+            // too fast, the target only gets 4 times harder
+            (1279008237, 1279297671, 0x1c05a3f4, 0x1c0168fd),
+            // too slow, the target only gets 4 times easier
+            (1263163443, 1269211443, 0x1c387f6f, 0x1d00e1fd),
+        ];
+        for (first_time, last_time, bits, expected) in cases {
+            let first = BlockHeader {
+                time: first_time,
+                ..genesis()
+            };
+            let last = BlockHeader {
+                time: last_time,
+                bits,
+                ..genesis()
+            };
+            let params = Network::Mainnet.params();
+            assert_eq!(
+                calculate_next_work_required(params, &first, &last),
+                expected
+            );
+        }
+
+        // testnet4 retargets from the first block's target
+        let first = BlockHeader {
+            time: 1261130161,
+            ..genesis()
+        };
+        let last = BlockHeader {
+            time: 1262152739,
+            bits: 0x1d00ffff,
+            ..genesis()
+        };
+        let first = BlockHeader {
+            bits: 0x1c05a3f4,
+            ..first
+        };
+        let testnet4 = Network::Testnet4.params();
+        let testnet3 = Network::Testnet3.params();
+        assert_eq!(
+            calculate_next_work_required(testnet3, &first, &last),
+            0x1d00d86a
+        );
+        assert_eq!(
+            calculate_next_work_required(testnet4, &first, &last),
+            0x1c04c4b2
+        );
+        // regtest never retargets
+        assert_eq!(
+            calculate_next_work_required(NETWORK.params(), &first, &last),
+            0x1d00ffff
+        );
+    }
+
+    #[test]
+    fn retargets_at_the_period_boundary() {
+        // testnet4's rules, but with regtest's easiest target, so that the blocks that
+        // use it are cheap to mine. A harder genesis gives the period a real target to
+        // retarget from; its own proof of work is never checked.
+        let genesis = BlockHeader {
+            bits: 0x1d00ffff,
+            ..genesis()
+        };
+        let params = Box::leak(Box::new(NetworkParams {
+            genesis_hash: genesis.block_hash(),
+            genesis,
+            ..Network::Testnet4.params().clone()
+        }));
+        params.pow_limit_bits = NETWORK.params().pow_limit_bits;
+        let mut chain = HeaderChain::with_params(NETWORK, params);
+
+        // blocks just over 20 minutes apart, all at the easiest target
+        let spacing = 2 * POW_TARGET_SPACING + 1;
+        let mut headers: Vec<BlockHeader> = Vec::new();
+        for _ in 1..DIFFICULTY_ADJUSTMENT_INTERVAL {
+            let prev = headers.last().unwrap_or(&genesis);
+            headers.push(mine_with_bits(
+                prev,
+                prev.time + spacing,
+                0,
+                params.pow_limit_bits,
+            ));
+        }
+        chain.accept_headers(&headers).unwrap();
+        assert_eq!(chain.height(), DIFFICULTY_ADJUSTMENT_INTERVAL - 1);
+
+        // the period took twice as long as it should, so the genesis target doubles, and
+        // the easiest target is no longer allowed at the boundary
+        let last = headers.last().unwrap();
+        let mut header = mine_with_bits(last, last.time + spacing, 0, params.pow_limit_bits);
+        assert!(matches!(
+            chain.accept_header(header),
+            Err(BitcoinMessageError::DifficultyUnexpected {
+                expected: 0x1d02002a,
+                got: 0x207fffff
+            })
+        ));
+        // the right target, but mining it would take billions of tries
+        header.bits = 0x1d02002a;
+        assert!(matches!(
+            chain.accept_header(header),
+            Err(BitcoinMessageError::ProofOfWorkInvalid)
+        ));
+    }
+
+    #[test]
+    fn work() {
+        assert_eq!(block_proof(0x207fffff), U256::from(2));
+        assert_eq!(block_proof(0x1d00ffff), U256::from(0x1_0001_0001));
+        assert_eq!(block_proof(0), U256::ZERO);
+        assert_eq!(block_proof(0x04923456), U256::ZERO);
+    }
+
+    /// A peer with `headers` on top of the regtest genesis, answering `getheaders` the
+    /// way Bitcoin Core does, `requests` times.
+    async fn fixture_peer(headers: Vec<BlockHeader>, requests: usize) -> Peer<DuplexStream> {
+        let (mut peer, mut theirs) = mock_peer(NETWORK);
+        tokio::spawn(async move {
+            accept_handshake(&mut theirs).await;
+            let hashes: Vec<[u8; 32]> = std::iter::once(genesis())
+                .chain(headers.iter().copied())
+                .map(|header| header.block_hash())
+                .collect();
+            for _ in 0..requests {
+                let message = theirs.next().await.unwrap().unwrap();
+                let Payload::GetHeaders(request) = message.into_payload() else {
+                    panic!("expected getheaders");
+                };
+                let start = request
+                    .locator
+                    .iter()
+                    .find_map(|hash| hashes.iter().position(|h| h == hash))
+                    .unwrap_or(0);
+                let end = headers.len().min(start + MAX_HEADERS_RESULTS);
+                let response = BtcMessage::with_payload(
+                    NETWORK,
+                    Command::Headers,
+                    Payload::Headers(headers[start..end].to_vec()),
+                )
+                .unwrap();
+                theirs.send(response).await.unwrap();
+            }
+            // stay connected, without answering
+            while let Some(Ok(_)) = theirs.next().await {}
+        });
+        peer.handshake().await.unwrap();
+        peer
+    }
+
+    #[tokio::test]
+    async fn syncs_from_a_peer() {
+        let headers = mine_chain(&genesis(), MAX_HEADERS_RESULTS + 100, 0);
+        let mut chain = HeaderChain::new(NETWORK);
+        chain.accept_headers(&headers[..10]).unwrap();
+
+        // two batches, the first full
+        let mut peer = fixture_peer(headers.clone(), 2).await;
+        let accepted = chain.sync(&mut peer, Duration::from_secs(5)).await.unwrap();
+        assert_eq!(accepted, MAX_HEADERS_RESULTS + 90);
+        assert_eq!(chain.height(), MAX_HEADERS_RESULTS as u32 + 100);
+        assert_eq!(chain.tip().header, *headers.last().unwrap());
+    }
+
+    #[tokio::test]
+    async fn sync_stops_when_a_full_batch_adds_nothing() {
+        let headers = mine_chain(&genesis(), MAX_HEADERS_RESULTS, 0);
+        let mut chain = HeaderChain::new(NETWORK);
+        chain.accept_headers(&headers).unwrap();
+        // our best chain is a longer fork the peer doesn't know, so it starts over from
+        // the genesis block every time
+        let fork = mine_chain(&genesis(), MAX_HEADERS_RESULTS + 1, 1);
+        chain.accept_headers(&fork).unwrap();
+
+        let mut peer = fixture_peer(headers, 2).await;
+        assert!(matches!(
+            chain.sync(&mut peer, Duration::from_secs(5)).await,
+            Err(BitcoinMessageError::HeadersUnhelpful)
+        ));
+    }
+
+    #[tokio::test]
+    async fn sync_times_out() {
+        let mut chain = HeaderChain::new(NETWORK);
+        let mut peer = fixture_peer(Vec::new(), 0).await;
+        assert!(matches!(
+            chain.sync(&mut peer, Duration::from_millis(50)).await,
+            Err(BitcoinMessageError::Timeout(Command::Headers))
+        ));
+    }
+}
@@ -0,0 +1,228 @@
+use bytes::{Buf, BufMut, BytesMut};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_util::codec::{Decoder, Encoder, Framed};
+
+use crate::{
+    btc_message::{BtcMessage, HEADER_SIZE},
+    encoding::Encodable,
+    errors::BitcoinMessageError,
+    network::Network,
+    payload::MAX_SIZE,
+};
+
+/// Frames [`BtcMessage`]s of one network on an async stream, see [`framed`], and for
+/// [`Connection`] on a blocking one.
+///
+/// Bytes before the network magic are skipped, so the stream resynchronises after
+/// garbage. So are messages with commands we don't know. A header whose checksum doesn't
+/// match, or whose length is over [`MAX_SIZE`], is taken for a magic that happened to be
+/// in the garbage. Sending a payload over [`MAX_SIZE`] is an error.
+///
+/// [`Connection`]: crate::connection::Connection
+#[derive(Debug, Clone, Copy)]
+pub struct MessageCodec {
+    network: Network,
+}
+
+impl MessageCodec {
+    pub fn new(network: Network) -> Self {
+        MessageCodec { network }
+    }
+
+    pub fn network(&self) -> Network {
+        self.network
+    }
+
+    /// Drops the bytes before the first network magic, returning whether there is one.
+    /// Without one, the last bytes are kept in case they're the start of a magic.
+    fn sync(&self, src: &mut BytesMut) -> bool {
+        let magic = self.network.magic().to_le_bytes();
+        match src.windows(magic.len()).position(|window| window == magic) {
+            Some(start) => {
+                src.advance(start);
+                true
+            }
+            None => {
+                src.advance(src.len().saturating_sub(magic.len() - 1));
+                false
+            }
+        }
+    }
+}
+
+impl Decoder for MessageCodec {
+    type Item = BtcMessage;
+    type Error = BitcoinMessageError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<BtcMessage>, BitcoinMessageError> {
+        loop {
+            if !self.sync(src) || src.len() < HEADER_SIZE {
+                return Ok(None);
+            }
+            // the length isn't trusted for preallocation, the buffer grows as bytes come
+            let len = match BtcMessage::payload_len(src) {
+                Ok(payload_len) => HEADER_SIZE + payload_len,
+                // not a message after all, look for the next magic
+                Err(BitcoinMessageError::PayloadTooBig) => {
+                    src.advance(1);
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            if src.len() < len {
+                return Ok(None);
+            }
+            match BtcMessage::from_bytes(&src[..len], self.network) {
+                Ok(message) => {
+                    src.advance(len);
+                    return Ok(Some(*message));
+                }
+                Err(BitcoinMessageError::CommandNameUnknown(_)) => src.advance(len),
+                // not a message after all, look for the next magic
+                Err(BitcoinMessageError::ChecksumMismatch) => src.advance(1),
+                Err(e) => {
+                    src.advance(len);
+                    return Err(e);
+                }
+            }
+        }
+    }
+}
+
+impl Encoder<BtcMessage> for MessageCodec {
+    type Error = BitcoinMessageError;
+
+    fn encode(
+        &mut self,
+        message: BtcMessage,
+        dst: &mut BytesMut,
+    ) -> Result<(), BitcoinMessageError> {
+        let mut data = Vec::new();
+        message.encode(&mut data)?;
+        if data.len() - HEADER_SIZE > MAX_SIZE {
+            return Err(BitcoinMessageError::PayloadTooBig);
+        }
+        dst.put_slice(&data);
+        Ok(())
+    }
+}
+
+/// Messages of `network` over `stream`, as a `Stream` of received messages and a `Sink`
+/// of messages to send.
+pub fn framed<S: AsyncRead + AsyncWrite>(stream: S, network: Network) -> Framed<S, MessageCodec> {
+    Framed::new(stream, MessageCodec::new(network))
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{SinkExt, StreamExt};
+
+    use super::*;
+    use crate::{
+        command::Command,
+        payload::{Payload, VersionMessage},
+        utils::checksum,
+    };
+
+    const NETWORK: Network = Network::Regtest;
+
+    fn ping(nonce: u64) -> Vec<u8> {
+        BtcMessage::with_payload(NETWORK, Command::Ping, Payload::Ping(nonce))
+            .unwrap()
+            .to_bytes()
+            .unwrap()
+    }
+
+    fn decode_all(data: &[u8]) -> Vec<Payload> {
+        let mut codec = MessageCodec::new(NETWORK);
+        let mut src = BytesMut::from(data);
+        let mut payloads = Vec::new();
+        while let Some(message) = codec.decode(&mut src).unwrap() {
+            payloads.push(message.into_payload());
+        }
+        payloads
+    }
+
+    #[test]
+    fn byte_by_byte() {
+        let data = ping(1);
+        let mut codec = MessageCodec::new(NETWORK);
+        let mut src = BytesMut::new();
+        for (i, byte) in data.iter().enumerate() {
+            src.put_u8(*byte);
+            let message = codec.decode(&mut src).unwrap();
+            assert_eq!(message.is_some(), i == data.len() - 1);
+        }
+        assert!(src.is_empty());
+    }
+
+    #[test]
+    fn skips_garbage() {
+        let magic = NETWORK.magic().to_le_bytes();
+        // ends with the start of a magic, then has a whole one with a bad checksum
+        let mut data = vec![0xff, 0x00, magic[0], magic[1], magic[2]];
+        let mut bad_checksum = ping(1);
+        bad_checksum[HEADER_SIZE - 1] ^= 0xff;
+        data.extend_from_slice(&bad_checksum);
+        data.extend_from_slice(&ping(2));
+        data.extend_from_slice(&[0xaa; 7]);
+        data.extend_from_slice(&ping(3));
+        assert!(matches!(
+            decode_all(&data)[..],
+            [Payload::Ping(2), Payload::Ping(3)]
+        ));
+
+        // no magic, only the bytes that could start one are kept
+        let mut codec = MessageCodec::new(NETWORK);
+        let mut src = BytesMut::from(&[0xaa; 100][..]);
+        assert!(codec.decode(&mut src).unwrap().is_none());
+        assert_eq!(src.len(), 3);
+    }
+
+    #[test]
+    fn skips_unknown_commands() {
+        let mut data = NETWORK.magic().to_le_bytes().to_vec();
+        data.extend_from_slice(b"sendcmpct\0\0\0");
+        data.extend_from_slice(&9u32.to_le_bytes());
+        data.extend_from_slice(&checksum(&[0; 9]));
+        data.extend_from_slice(&[0; 9]);
+        data.extend_from_slice(&ping(4));
+        assert!(matches!(decode_all(&data)[..], [Payload::Ping(4)]));
+    }
+
+    #[test]
+    fn payload_too_big() {
+        // a magic in the garbage, followed by a length no message can have
+        let mut data = vec![0xaa; 3];
+        data.extend_from_slice(&NETWORK.magic().to_le_bytes());
+        data.extend_from_slice(b"ping\0\0\0\0\0\0\0\0");
+        data.extend_from_slice(&u32::MAX.to_le_bytes());
+        data.extend_from_slice(&[0xaa; 9]);
+        data.extend_from_slice(&ping(5));
+        assert!(matches!(decode_all(&data)[..], [Payload::Ping(5)]));
+
+        let mut too_big = ping(6);
+        too_big[16..20].copy_from_slice(&(MAX_SIZE as u32 + 1).to_le_bytes());
+        assert!(decode_all(&too_big).is_empty());
+    }
+
+    #[tokio::test]
+    async fn over_a_stream() {
+        let (ours, theirs) = tokio::io::duplex(64);
+        let (mut ours, mut theirs) = (framed(ours, NETWORK), framed(theirs, NETWORK));
+        let version = VersionMessage::new("10.0.0.1:18444".parse().unwrap());
+        let messages = [
+            (Command::Ping, Payload::Ping(6)),
+            // bigger than the pipe, so it takes several reads
+            (Command::Version, Payload::Version(version)),
+            (Command::VerAck, Payload::Empty),
+        ];
+        for (command, payload) in messages {
+            let message = BtcMessage::with_payload(NETWORK, command, payload).unwrap();
+            let expected = message.to_bytes().unwrap();
+            let (sent, received) = tokio::join!(ours.send(message), theirs.next());
+            sent.unwrap();
+            assert_eq!(received.unwrap().unwrap().to_bytes().unwrap(), expected);
+        }
+    }
+}
@@ -2,7 +2,7 @@ use std::fmt::Display;
 
 use crate::errors::BitcoinMessageError;
 
-// const COMMAND_NAME_SIZE: usize = 12;
+pub const COMMAND_NAME_SIZE: usize = 12;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 /// Enum corresponding to the `command_name` from Message header.
@@ -12,6 +12,51 @@ pub enum Command {
 
     /// `verack` command_name
     VerAck,
+
+    /// `ping` command_name
+    Ping,
+
+    /// `pong` command_name
+    Pong,
+
+    /// `getaddr` command_name
+    GetAddr,
+
+    /// `addr` command_name
+    Addr,
+
+    /// `addrv2` command_name
+    AddrV2,
+
+    /// `sendaddrv2` command_name
+    SendAddrV2,
+
+    /// `wtxidrelay` command_name
+    WtxidRelay,
+
+    /// `inv` command_name
+    Inv,
+
+    /// `getdata` command_name
+    GetData,
+
+    /// `notfound` command_name
+    NotFound,
+
+    /// `getheaders` command_name
+    GetHeaders,
+
+    /// `headers` command_name
+    Headers,
+
+    /// `sendheaders` command_name
+    SendHeaders,
+
+    /// `tx` command_name
+    Tx,
+
+    /// `block` command_name
+    Block,
 }
 
 impl Command {
@@ -19,6 +64,29 @@ impl Command {
     pub fn to_bytes(&self) -> Vec<u8> {
         self.to_string().into_bytes()
     }
+
+    /// The `command_name` field of a message header, padded with NUL.
+    pub fn to_padded_bytes(&self) -> [u8; COMMAND_NAME_SIZE] {
+        let mut padded = [0u8; COMMAND_NAME_SIZE];
+        let name = self.to_bytes();
+        padded[..name.len()].copy_from_slice(&name);
+        padded
+    }
+
+    /// Parses the NUL padded `command_name` field of a message header.
+    pub fn from_bytes(data: &[u8]) -> Result<Self, BitcoinMessageError> {
+        if data.len() > COMMAND_NAME_SIZE {
+            return Err(BitcoinMessageError::CommandNameTooLong);
+        }
+        let name_len = data.iter().position(|b| *b == 0).unwrap_or(data.len());
+        let (name, padding) = data.split_at(name_len);
+        // the padding has to be all NUL, as in "version\0\0\0\0\0"
+        if !name.is_ascii() || padding.iter().any(|b| *b != 0) {
+            return Err(BitcoinMessageError::CommandNameNonAscii);
+        }
+        // ASCII is always valid UTF-8
+        Command::try_from(std::str::from_utf8(name).unwrap_or_default())
+    }
 }
 
 impl Display for Command {
@@ -26,6 +94,21 @@ impl Display for Command {
         let s = match self {
             Command::Version => "version",
             Command::VerAck => "verack",
+            Command::Ping => "ping",
+            Command::Pong => "pong",
+            Command::GetAddr => "getaddr",
+            Command::Addr => "addr",
+            Command::AddrV2 => "addrv2",
+            Command::SendAddrV2 => "sendaddrv2",
+            Command::WtxidRelay => "wtxidrelay",
+            Command::Inv => "inv",
+            Command::GetData => "getdata",
+            Command::NotFound => "notfound",
+            Command::GetHeaders => "getheaders",
+            Command::Headers => "headers",
+            Command::SendHeaders => "sendheaders",
+            Command::Tx => "tx",
+            Command::Block => "block",
         };
 
         write!(f, "{}", s)
@@ -39,6 +122,21 @@ impl TryFrom<&str> for Command {
         match value {
             "version" => Ok(Command::Version),
             "verack" => Ok(Command::VerAck),
+            "ping" => Ok(Command::Ping),
+            "pong" => Ok(Command::Pong),
+            "getaddr" => Ok(Command::GetAddr),
+            "addr" => Ok(Command::Addr),
+            "addrv2" => Ok(Command::AddrV2),
+            "sendaddrv2" => Ok(Command::SendAddrV2),
+            "wtxidrelay" => Ok(Command::WtxidRelay),
+            "inv" => Ok(Command::Inv),
+            "getdata" => Ok(Command::GetData),
+            "notfound" => Ok(Command::NotFound),
+            "getheaders" => Ok(Command::GetHeaders),
+            "headers" => Ok(Command::Headers),
+            "sendheaders" => Ok(Command::SendHeaders),
+            "tx" => Ok(Command::Tx),
+            "block" => Ok(Command::Block),
             x => Err(BitcoinMessageError::CommandNameUnknown(x.to_string())),
         }
     }
@@ -64,12 +162,68 @@ mod tests {
     fn command_as_string() {
         assert_eq!(Command::Version.to_string(), "version");
         assert_eq!(Command::VerAck.to_string(), "verack");
+        assert_eq!(Command::Ping.to_string(), "ping");
+        assert_eq!(Command::Pong.to_string(), "pong");
+        assert_eq!(Command::SendAddrV2.to_string(), "sendaddrv2");
     }
 
     #[test]
     fn string_as_command() {
         assert_eq!(Command::try_from("version").unwrap(), Command::Version);
         assert_eq!(Command::try_from("verack").unwrap(), Command::VerAck);
+        assert_eq!(Command::try_from("ping").unwrap(), Command::Ping);
+        assert_eq!(Command::try_from("pong").unwrap(), Command::Pong);
+        assert_eq!(Command::try_from("getaddr").unwrap(), Command::GetAddr);
+        assert_eq!(Command::try_from("addr").unwrap(), Command::Addr);
+        assert_eq!(Command::try_from("addrv2").unwrap(), Command::AddrV2);
+        assert_eq!(Command::try_from("inv").unwrap(), Command::Inv);
+        assert_eq!(Command::try_from("getdata").unwrap(), Command::GetData);
+        assert_eq!(Command::try_from("notfound").unwrap(), Command::NotFound);
+        assert_eq!(Command::try_from("tx").unwrap(), Command::Tx);
+        assert_eq!(Command::try_from("block").unwrap(), Command::Block);
+        assert_eq!(
+            Command::try_from("wtxidrelay").unwrap(),
+            Command::WtxidRelay
+        );
+        assert_eq!(
+            Command::try_from("getheaders").unwrap(),
+            Command::GetHeaders
+        );
+        assert_eq!(Command::try_from("headers").unwrap(), Command::Headers);
+        assert_eq!(
+            Command::try_from("sendheaders").unwrap(),
+            Command::SendHeaders
+        );
+    }
+
+    #[test]
+    fn padded_bytes_as_command() {
+        assert_eq!(
+            Command::from_bytes(b"version\0\0\0\0\0").unwrap(),
+            Command::Version
+        );
+        assert_eq!(
+            Command::from_bytes(b"verack\0\0\0\0\0\0").unwrap(),
+            Command::VerAck
+        );
+        assert!(matches!(
+            Command::from_bytes(b"verack\0\0x\0\0\0"),
+            Err(BitcoinMessageError::CommandNameNonAscii)
+        ));
+        assert!(matches!(
+            Command::from_bytes(b"ver\xffck\0\0\0\0\0\0"),
+            Err(BitcoinMessageError::CommandNameNonAscii)
+        ));
+        assert!(matches!(
+            Command::from_bytes(b"getblocktxn\0"),
+            Err(BitcoinMessageError::CommandNameUnknown(_))
+        ));
+    }
+
+    #[test]
+    fn command_as_padded_bytes() {
+        assert_eq!(Command::Version.to_padded_bytes(), *b"version\0\0\0\0\0");
+        assert_eq!(Command::Pong.to_padded_bytes(), *b"pong\0\0\0\0\0\0\0\0");
     }
 
     #[test]
@@ -0,0 +1,356 @@
+use std::io::{ErrorKind, Read, Write};
+use std::time::{Duration, Instant};
+
+use bytes::BytesMut;
+use tokio_util::codec::Decoder;
+
+use crate::{
+    btc_message::BtcMessage, codec::MessageCodec, command::Command, errors::BitcoinMessageError,
+    network::Network, payload::Payload,
+};
+
+/// How often to ping a peer, same as Bitcoin Core.
+pub const DEFAULT_PING_INTERVAL: Duration = Duration::from_secs(120);
+/// How long a ping may go unanswered, same as Bitcoin Core.
+pub const DEFAULT_PING_TIMEOUT: Duration = Duration::from_secs(20 * 60);
+
+const READ_CHUNK_SIZE: usize = 8 * 1024;
+
+/// Round trip times to a peer, measured with ping/pong.
+#[derive(Debug, Default, Clone)]
+pub struct Latency {
+    last: Option<Duration>,
+    min: Option<Duration>,
+    total: Duration,
+    samples: u32,
+}
+
+impl Latency {
+    pub fn record(&mut self, rtt: Duration) {
+        self.last = Some(rtt);
+        self.min = Some(self.min.map_or(rtt, |min| min.min(rtt)));
+        self.total += rtt;
+        self.samples += 1;
+    }
+
+    pub fn last(&self) -> Option<Duration> {
+        self.last
+    }
+
+    pub fn min(&self) -> Option<Duration> {
+        self.min
+    }
+
+    pub fn average(&self) -> Option<Duration> {
+        (self.samples > 0).then(|| self.total / self.samples)
+    }
+
+    pub fn samples(&self) -> u32 {
+        self.samples
+    }
+}
+
+/// When to ping a peer and how long its pongs take, for [`Connection`] and
+/// [`Peer`](crate::peer::Peer) alike.
+#[derive(Debug)]
+pub(crate) struct Pings {
+    interval: Duration,
+    timeout: Duration,
+    next: Instant,
+    /// Nonce and send time of the ping waiting for its pong.
+    in_flight: Option<(u64, Instant)>,
+    latency: Latency,
+}
+
+impl Pings {
+    pub(crate) fn new() -> Self {
+        Pings {
+            interval: DEFAULT_PING_INTERVAL,
+            timeout: DEFAULT_PING_TIMEOUT,
+            next: Instant::now() + DEFAULT_PING_INTERVAL,
+            in_flight: None,
+            latency: Latency::default(),
+        }
+    }
+
+    /// Sets the time between pings, counting from now.
+    pub(crate) fn set_interval(&mut self, interval: Duration) {
+        self.interval = interval;
+        self.next = Instant::now() + interval;
+    }
+
+    pub(crate) fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    pub(crate) fn latency(&self) -> &Latency {
+        &self.latency
+    }
+
+    /// The nonce of a ping to send now, if one is due and the last one was answered.
+    /// Fails with [`BitcoinMessageError::Timeout`] once the ping in flight has waited too
+    /// long for its pong.
+    pub(crate) fn due(&mut self) -> Result<Option<u64>, BitcoinMessageError> {
+        let now = Instant::now();
+        if let Some((_, sent)) = self.in_flight {
+            if now >= sent + self.timeout {
+                return Err(BitcoinMessageError::Timeout(Command::Pong));
+            }
+            return Ok(None);
+        }
+        if now < self.next {
+            return Ok(None);
+        }
+        let nonce = rand::random();
+        self.in_flight = Some((nonce, now));
+        self.next = now + self.interval;
+        Ok(Some(nonce))
+    }
+
+    /// When [`due`](Self::due) has something to do next.
+    pub(crate) fn deadline(&self) -> Instant {
+        match self.in_flight {
+            Some((_, sent)) => sent + self.timeout,
+            None => self.next,
+        }
+    }
+
+    pub(crate) fn pong(&mut self, nonce: u64) {
+        // pongs for other nonces are ignored, like Bitcoin Core does
+        if let Some((expected, sent)) = self.in_flight {
+            if nonce == expected {
+                self.latency.record(sent.elapsed());
+                self.in_flight = None;
+            }
+        }
+    }
+}
+
+/// Messages exchanged with one peer over a blocking stream, framed by [`MessageCodec`].
+///
+/// Pings from the peer are answered and the peer is pinged every
+/// [`DEFAULT_PING_INTERVAL`], so the connection stays up as long as it's being read. A
+/// peer that leaves a ping unanswered for [`DEFAULT_PING_TIMEOUT`] is given up on.
+pub struct Connection<S> {
+    stream: S,
+    codec: MessageCodec,
+    pings: Pings,
+    buffer: BytesMut,
+}
+
+impl<S: Read + Write> Connection<S> {
+    pub fn new(stream: S, network: Network) -> Self {
+        Connection {
+            stream,
+            codec: MessageCodec::new(network),
+            pings: Pings::new(),
+            buffer: BytesMut::new(),
+        }
+    }
+
+    /// Sets the time between pings, counting from now.
+    pub fn set_ping_interval(&mut self, interval: Duration) {
+        self.pings.set_interval(interval);
+    }
+
+    pub fn set_ping_timeout(&mut self, timeout: Duration) {
+        self.pings.set_timeout(timeout);
+    }
+
+    pub fn latency(&self) -> &Latency {
+        self.pings.latency()
+    }
+
+    pub fn stream(&self) -> &S {
+        &self.stream
+    }
+
+    pub fn send(&mut self, command: Command, payload: Payload) -> Result<(), BitcoinMessageError> {
+        let message = BtcMessage::with_payload(self.codec.network(), command, payload)?;
+        self.stream.write_all(&message.to_bytes()?)?;
+        Ok(self.stream.flush()?)
+    }
+
+    /// Sends a ping if one is due and the last one was answered. Fails with
+    /// [`BitcoinMessageError::Timeout`] once the ping in flight has waited too long for
+    /// its pong.
+    pub fn ping_if_due(&mut self) -> Result<(), BitcoinMessageError> {
+        match self.pings.due()? {
+            Some(nonce) => self.send(Command::Ping, Payload::Ping(nonce)),
+            None => Ok(()),
+        }
+    }
+
+    /// Waits for the next message, answering pings and timing pongs on the way; both are
+    /// returned as well. Garbage and messages with commands we don't know are skipped.
+    ///
+    /// Returns `None` when the stream's read timeout expired before a whole message came
+    /// in, so the caller gets a chance to do other work. Without a read timeout this
+    /// blocks, and no pings are sent or timed out while waiting.
+    pub fn next_message(&mut self) -> Result<Option<BtcMessage>, BitcoinMessageError> {
+        let mut chunk = [0u8; READ_CHUNK_SIZE];
+        loop {
+            self.ping_if_due()?;
+            if let Some(message) = self.codec.decode(&mut self.buffer)? {
+                self.handle(&message)?;
+                return Ok(Some(message));
+            }
+            match self.stream.read(&mut chunk) {
+                Ok(0) => return Err(std::io::Error::from(ErrorKind::UnexpectedEof).into()),
+                Ok(n) => self.buffer.extend_from_slice(&chunk[..n]),
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    self.ping_if_due()?;
+                    return Ok(None);
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    fn handle(&mut self, message: &BtcMessage) -> Result<(), BitcoinMessageError> {
+        match message.payload() {
+            Payload::Ping(nonce) => self.send(Command::Pong, Payload::Pong(*nonce)),
+            Payload::Pong(nonce) => {
+                self.pings.pong(*nonce);
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{TcpListener, TcpStream};
+    use std::thread::{self, JoinHandle};
+
+    use super::*;
+    use crate::utils::checksum;
+
+    const NETWORK: Network = Network::Regtest;
+
+    /// Connects to a peer on localhost that runs `peer` on its end of the connection.
+    fn mock_peer(
+        peer: impl FnOnce(TcpStream) + Send + 'static,
+    ) -> (Connection<TcpStream>, JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let handle = thread::spawn(move || peer(listener.accept().unwrap().0));
+        let stream = TcpStream::connect(addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_millis(10)))
+            .unwrap();
+        (Connection::new(stream, NETWORK), handle)
+    }
+
+    fn send(stream: &mut TcpStream, command: Command, payload: Payload) {
+        let message = BtcMessage::with_payload(NETWORK, command, payload).unwrap();
+        stream.write_all(&message.to_bytes().unwrap()).unwrap();
+    }
+
+    fn wait_for_message(connection: &mut Connection<TcpStream>) -> BtcMessage {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while Instant::now() < deadline {
+            if let Some(message) = connection.next_message().unwrap() {
+                return message;
+            }
+        }
+        panic!("no message from the mock peer");
+    }
+
+    #[test]
+    fn answers_ping() {
+        let (mut connection, peer) = mock_peer(|mut stream| {
+            send(&mut stream, Command::Ping, Payload::Ping(42));
+            let pong = BtcMessage::decode(&mut stream, NETWORK).unwrap();
+            assert!(matches!(pong.payload(), Payload::Pong(42)));
+        });
+        let message = wait_for_message(&mut connection);
+        assert!(matches!(message.payload(), Payload::Ping(42)));
+        peer.join().unwrap();
+    }
+
+    #[test]
+    fn pings_periodically_and_measures_latency() {
+        let (mut connection, peer) = mock_peer(|mut stream| {
+            for _ in 0..2 {
+                let ping = BtcMessage::decode(&mut stream, NETWORK).unwrap();
+                let Payload::Ping(nonce) = *ping.payload() else {
+                    panic!("expected a ping, got {:?}", ping);
+                };
+                thread::sleep(Duration::from_millis(20));
+                send(
+                    &mut stream,
+                    Command::Pong,
+                    Payload::Pong(nonce.wrapping_add(1)),
+                );
+                send(&mut stream, Command::Pong, Payload::Pong(nonce));
+            }
+        });
+        connection.set_ping_interval(Duration::from_millis(30));
+        while connection.latency().samples() < 2 {
+            wait_for_message(&mut connection);
+        }
+        peer.join().unwrap();
+
+        let latency = connection.latency();
+        assert!(latency.min().unwrap() >= Duration::from_millis(20));
+        assert!(latency.average().unwrap() >= latency.min().unwrap());
+        assert!(connection.pings.in_flight.is_none());
+    }
+
+    #[test]
+    fn ping_timeout() {
+        let (mut connection, peer) = mock_peer(|mut stream| {
+            // reads the ping, but never answers it
+            BtcMessage::decode(&mut stream, NETWORK).unwrap();
+            thread::sleep(Duration::from_millis(200));
+        });
+        connection.set_ping_interval(Duration::ZERO);
+        connection.set_ping_timeout(Duration::from_millis(50));
+        let started = Instant::now();
+        let err = loop {
+            match connection.next_message() {
+                Err(e) => break e,
+                Ok(_) => assert!(started.elapsed() < Duration::from_secs(5)),
+            }
+        };
+        assert!(matches!(err, BitcoinMessageError::Timeout(Command::Pong)));
+        assert!(started.elapsed() >= Duration::from_millis(50));
+        peer.join().unwrap();
+    }
+
+    #[test]
+    fn skips_garbage_and_unknown_commands() {
+        let (mut connection, peer) = mock_peer(|mut stream| {
+            stream.write_all(&[0xaa; 5]).unwrap();
+            let mut sendcmpct = NETWORK.magic().to_le_bytes().to_vec();
+            sendcmpct.extend_from_slice(b"sendcmpct\0\0\0");
+            sendcmpct.extend_from_slice(&9u32.to_le_bytes());
+            sendcmpct.extend_from_slice(&checksum(&[0; 9]));
+            sendcmpct.extend_from_slice(&[0; 9]);
+            stream.write_all(&sendcmpct).unwrap();
+            send(&mut stream, Command::Ping, Payload::Ping(7));
+            BtcMessage::decode(&mut stream, NETWORK).unwrap();
+        });
+        let message = wait_for_message(&mut connection);
+        assert!(matches!(message.payload(), Payload::Ping(7)));
+        peer.join().unwrap();
+    }
+
+    #[test]
+    fn closed_by_peer() {
+        let (mut connection, peer) = mock_peer(drop);
+        peer.join().unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let err = loop {
+            match connection.next_message() {
+                Err(e) => break e,
+                Ok(_) if Instant::now() < deadline => continue,
+                Ok(_) => panic!("connection still open"),
+            }
+        };
+        assert!(matches!(err, BitcoinMessageError::SerializationError(_)));
+    }
+}
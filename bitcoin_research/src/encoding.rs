@@ -0,0 +1,361 @@
+//! Streaming (de)serialization of the wire format.
+//!
+//! Variable length fields start with a CompactSize
+//! (https://en.bitcoin.it/wiki/Protocol_documentation#Variable_length_integer):
+//!
+//! value          | size | format
+//! -----          | ---- | ------
+//! < 0xFD         | 1    | u8
+//! <= 0xFFFF      | 3    | 0xFD followed by the length as u16
+//! <= 0xFFFF FFFF | 5    | 0xFE followed by the length as u32
+//! larger         | 9    | 0xFF followed by the length as u64
+//!
+//! Only the shortest encoding of a value is accepted, as Bitcoin Core does.
+
+use std::io::{Read, Write};
+
+use crate::{errors::BitcoinMessageError, utils::FromEndian};
+
+/// A type that can be written in its wire format.
+pub trait Encodable {
+    fn encode<W: Write>(&self, w: &mut W) -> Result<(), BitcoinMessageError>;
+}
+
+/// A type that can be read from its wire format, consuming only its own bytes.
+pub trait Decodable: Sized {
+    fn decode<R: Read>(r: &mut R) -> Result<Self, BitcoinMessageError>;
+}
+
+// integers are little endian on the wire, unless stated otherwise
+macro_rules! impl_int_encodable {
+    ($($t:ty),*) => {$(
+        impl Encodable for $t {
+            fn encode<W: Write>(&self, w: &mut W) -> Result<(), BitcoinMessageError> {
+                Ok(w.write_all(&self.to_le_bytes())?)
+            }
+        }
+
+        impl Decodable for $t {
+            fn decode<R: Read>(r: &mut R) -> Result<Self, BitcoinMessageError> {
+                let bytes = <[u8; core::mem::size_of::<$t>()]>::decode(r)?;
+                Ok(FromEndian::from_le(&bytes))
+            }
+        }
+    )*};
+}
+
+impl_int_encodable!(u16, u32, u64, i32, i64);
+
+impl Encodable for u8 {
+    fn encode<W: Write>(&self, w: &mut W) -> Result<(), BitcoinMessageError> {
+        Ok(w.write_all(&[*self])?)
+    }
+}
+
+impl Decodable for u8 {
+    fn decode<R: Read>(r: &mut R) -> Result<Self, BitcoinMessageError> {
+        Ok(<[u8; 1]>::decode(r)?[0])
+    }
+}
+
+impl Encodable for bool {
+    fn encode<W: Write>(&self, w: &mut W) -> Result<(), BitcoinMessageError> {
+        (*self as u8).encode(w)
+    }
+}
+
+impl Decodable for bool {
+    fn decode<R: Read>(r: &mut R) -> Result<Self, BitcoinMessageError> {
+        Ok(u8::decode(r)? != 0)
+    }
+}
+
+impl<const N: usize> Encodable for [u8; N] {
+    fn encode<W: Write>(&self, w: &mut W) -> Result<(), BitcoinMessageError> {
+        Ok(w.write_all(self)?)
+    }
+}
+
+impl<const N: usize> Decodable for [u8; N] {
+    fn decode<R: Read>(r: &mut R) -> Result<Self, BitcoinMessageError> {
+        let mut bytes = [0u8; N];
+        r.read_exact(&mut bytes)?;
+        Ok(bytes)
+    }
+}
+
+/// Reads a big endian integer of `N` bytes, as used for ports.
+pub fn decode_be<T: FromEndian, const N: usize, R: Read>(
+    r: &mut R,
+) -> Result<T, BitcoinMessageError> {
+    Ok(T::from_be(&<[u8; N]>::decode(r)?))
+}
+
+/// A CompactSize unsigned integer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompactSize(pub u64);
+
+impl Encodable for CompactSize {
+    fn encode<W: Write>(&self, w: &mut W) -> Result<(), BitcoinMessageError> {
+        match self.0 {
+            0..=0xFC => (self.0 as u8).encode(w),
+            0xFD..=0xFFFF => {
+                0xFDu8.encode(w)?;
+                (self.0 as u16).encode(w)
+            }
+            0x1_0000..=0xFFFF_FFFF => {
+                0xFEu8.encode(w)?;
+                (self.0 as u32).encode(w)
+            }
+            _ => {
+                0xFFu8.encode(w)?;
+                self.0.encode(w)
+            }
+        }
+    }
+}
+
+impl Decodable for CompactSize {
+    fn decode<R: Read>(r: &mut R) -> Result<Self, BitcoinMessageError> {
+        let (n, min) = match u8::decode(r)? {
+            0xFD => (u16::decode(r)? as u64, 0xFD),
+            0xFE => (u32::decode(r)? as u64, 0x1_0000),
+            0xFF => (u64::decode(r)?, 0x1_0000_0000),
+            n => return Ok(CompactSize(n as u64)),
+        };
+        if n < min {
+            return Err(BitcoinMessageError::NonCanonicalCompactSize(n));
+        }
+        Ok(CompactSize(n))
+    }
+}
+
+/// Reads a CompactSize length, failing if it's larger than `max`.
+pub fn read_length<R: Read>(r: &mut R, max: usize) -> Result<usize, BitcoinMessageError> {
+    let CompactSize(len) = CompactSize::decode(r)?;
+    if len > max as u64 {
+        return Err(BitcoinMessageError::LengthTooBig { len, max });
+    }
+    Ok(len as usize)
+}
+
+/// Writes a `var_str`: the length as CompactSize, then the UTF-8 bytes.
+pub fn write_var_str<W: Write>(w: &mut W, s: &str) -> Result<(), BitcoinMessageError> {
+    CompactSize(s.len() as u64).encode(w)?;
+    Ok(w.write_all(s.as_bytes())?)
+}
+
+/// Reads a `var_str` of at most `max` bytes.
+pub fn read_var_str<R: Read>(r: &mut R, max: usize) -> Result<String, BitcoinMessageError> {
+    let mut bytes = vec![0u8; read_length(r, max)?];
+    r.read_exact(&mut bytes)?;
+    Ok(String::from_utf8(bytes)?)
+}
+
+/// Writes a byte vector, such as a script: the length as CompactSize, then the bytes.
+pub fn write_var_bytes<W: Write>(w: &mut W, bytes: &[u8]) -> Result<(), BitcoinMessageError> {
+    CompactSize(bytes.len() as u64).encode(w)?;
+    Ok(w.write_all(bytes)?)
+}
+
+/// Reads a byte vector of at most `max` bytes.
+pub fn read_var_bytes<R: Read>(r: &mut R, max: usize) -> Result<Vec<u8>, BitcoinMessageError> {
+    let len = read_length(r, max)?;
+    // like the items of a list, the bytes may not be there
+    let mut bytes = Vec::new();
+    r.take(len as u64).read_to_end(&mut bytes)?;
+    if bytes.len() < len {
+        return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+    }
+    Ok(bytes)
+}
+
+/// Writes a list as its item count as CompactSize followed by the items.
+pub fn write_var_array<W: Write, T: Encodable>(
+    w: &mut W,
+    items: &[T],
+) -> Result<(), BitcoinMessageError> {
+    CompactSize(items.len() as u64).encode(w)?;
+    items.iter().try_for_each(|item| item.encode(w))
+}
+
+/// Reads a list of at most `max` items.
+pub fn read_var_array<R: Read, T: Decodable>(
+    r: &mut R,
+    max: usize,
+) -> Result<Vec<T>, BitcoinMessageError> {
+    let len = read_length(r, max)?;
+    // the count isn't trusted for preallocation, the items may not be there
+    let mut items = Vec::new();
+    for _ in 0..len {
+        items.push(T::decode(r)?);
+    }
+    Ok(items)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::raw_message::RawMessage;
+
+    #[test]
+    fn compact_size_boundaries() {
+        let cases: [(u64, &[u8]); 10] = [
+            (0, &[0x00]),
+            (0xFC, &[0xFC]),
+            (0xFD, &[0xFD, 0xFD, 0x00]),
+            (0xFE, &[0xFD, 0xFE, 0x00]),
+            (0xFFFF, &[0xFD, 0xFF, 0xFF]),
+            (0x1_0000, &[0xFE, 0x00, 0x00, 0x01, 0x00]),
+            (0xFFFF_FFFF, &[0xFE, 0xFF, 0xFF, 0xFF, 0xFF]),
+            (0x1_0000_0000, &[0xFF, 0, 0, 0, 0, 1, 0, 0, 0]),
+            (
+                u64::MAX,
+                &[0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF],
+            ),
+            (
+                0x0123_4567_89AB,
+                &[0xFF, 0xAB, 0x89, 0x67, 0x45, 0x23, 0x01, 0, 0],
+            ),
+        ];
+        for (n, bytes) in cases {
+            assert_eq!(
+                CompactSize(n).to_bytes().unwrap(),
+                bytes,
+                "encoding {:#x}",
+                n
+            );
+            let mut r = bytes;
+            assert_eq!(CompactSize::decode(&mut r).unwrap(), CompactSize(n));
+            assert!(r.is_empty());
+        }
+    }
+
+    #[test]
+    fn compact_size_non_canonical() {
+        let cases: [&[u8]; 6] = [
+            &[0xFD, 0x00, 0x00],
+            &[0xFD, 0xFC, 0x00],
+            &[0xFE, 0xFF, 0xFF, 0x00, 0x00],
+            &[0xFE, 0x00, 0x00, 0x00, 0x00],
+            &[0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0, 0, 0, 0],
+            &[0xFF, 0, 0, 0, 0, 0, 0, 0, 0],
+        ];
+        for bytes in cases {
+            assert!(
+                matches!(
+                    CompactSize::from_bytes(bytes),
+                    Err(BitcoinMessageError::NonCanonicalCompactSize(_))
+                ),
+                "{:x?}",
+                bytes
+            );
+        }
+    }
+
+    #[test]
+    fn compact_size_truncated() {
+        let cases: [&[u8]; 4] = [
+            &[],
+            &[0xFD, 0xFF],
+            &[0xFE, 0xFF, 0xFF, 0xFF],
+            &[0xFF, 0, 0, 0, 0, 1, 0, 0],
+        ];
+        for bytes in cases {
+            assert!(matches!(
+                CompactSize::from_bytes(bytes),
+                Err(BitcoinMessageError::SerializationError(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn compact_size_keeps_rest() {
+        let mut r: &[u8] = &[0xFD, 0x00, 0x01, 0xAA];
+        assert_eq!(CompactSize::decode(&mut r).unwrap(), CompactSize(0x100));
+        assert_eq!(r, [0xAA]);
+    }
+
+    #[test]
+    fn var_str_round_trip() {
+        for s in ["", "/Satoshi:27.0.0/", &"a".repeat(0xFD)] {
+            let mut bytes = Vec::new();
+            write_var_str(&mut bytes, s).unwrap();
+            let mut r = &bytes[..];
+            assert_eq!(read_var_str(&mut r, 0xFD).unwrap(), s);
+            assert!(r.is_empty());
+        }
+        let mut bytes = Vec::new();
+        write_var_str(&mut bytes, "ab").unwrap();
+        assert_eq!(bytes, [2, b'a', b'b']);
+    }
+
+    #[test]
+    fn var_str_errors() {
+        assert!(matches!(
+            read_var_str(&mut &[3, b'a', b'b', b'c'][..], 2),
+            Err(BitcoinMessageError::LengthTooBig { len: 3, max: 2 })
+        ));
+        assert!(matches!(
+            read_var_str(&mut &[3, b'a', b'b'][..], 3),
+            Err(BitcoinMessageError::SerializationError(_))
+        ));
+        assert!(matches!(
+            read_var_str(&mut &[1, 0xFF][..], 3),
+            Err(BitcoinMessageError::Utf8DeserializationError(_))
+        ));
+    }
+
+    #[test]
+    fn var_bytes() {
+        let mut bytes = Vec::new();
+        write_var_bytes(&mut bytes, &[0xAB; 0xFD]).unwrap();
+        assert_eq!(&bytes[..4], &[0xFD, 0xFD, 0x00, 0xAB]);
+        let mut r = &bytes[..];
+        assert_eq!(read_var_bytes(&mut r, 0xFD).unwrap(), [0xAB; 0xFD]);
+        assert!(r.is_empty());
+
+        assert!(matches!(
+            read_var_bytes(&mut &bytes[..], 0xFC),
+            Err(BitcoinMessageError::LengthTooBig { len: 0xFD, .. })
+        ));
+        assert!(matches!(
+            read_var_bytes(&mut &bytes[..100], 0xFD),
+            Err(BitcoinMessageError::SerializationError(_))
+        ));
+    }
+
+    #[test]
+    fn var_array_round_trip() {
+        let heights: Vec<u32> = vec![0, 0xFD, 840_000];
+        let mut bytes = Vec::new();
+        write_var_array(&mut bytes, &heights).unwrap();
+        assert_eq!(
+            bytes,
+            [3, 0, 0, 0, 0, 0xFD, 0, 0, 0, 0x40, 0xD1, 0x0C, 0x00]
+        );
+
+        let mut r = &bytes[..];
+        assert_eq!(read_var_array::<_, u32>(&mut r, 3).unwrap(), heights);
+        assert!(r.is_empty());
+
+        assert!(matches!(
+            read_var_array::<_, u32>(&mut &bytes[..], 2),
+            Err(BitcoinMessageError::LengthTooBig { len: 3, max: 2 })
+        ));
+        assert!(matches!(
+            read_var_array::<_, u32>(&mut &bytes[..12], 3),
+            Err(BitcoinMessageError::SerializationError(_))
+        ));
+    }
+
+    #[test]
+    fn integers_are_little_endian() {
+        assert_eq!(0x0102_0304u32.to_bytes().unwrap(), [4, 3, 2, 1]);
+        assert_eq!(*i32::from_bytes(&[0xFF, 0xFF, 0xFF, 0xFF]).unwrap(), -1);
+        assert_eq!(
+            decode_be::<u16, 2, _>(&mut &[0x20, 0x8D][..]).unwrap(),
+            8333
+        );
+    }
+}
@@ -1,5 +1,7 @@
 use thiserror::Error;
 
+use crate::{command::Command, network::Network, peer::DisconnectReason};
+
 #[derive(Error, Debug)]
 pub enum BitcoinMessageError {
     #[error("command name too long")]
@@ -20,6 +22,69 @@ pub enum BitcoinMessageError {
     #[error("unknown command name: {0}")]
     CommandNameUnknown(String),
 
+    #[error("length {len} is larger than the limit of {max}")]
+    LengthTooBig { len: u64, max: usize },
+
+    #[error("CompactSize {0} is not in its shortest encoding")]
+    NonCanonicalCompactSize(u64),
+
+    #[error("address of {len} bytes for network id {network_id}")]
+    AddressLength { network_id: u8, len: usize },
+
+    #[error("unknown network: {0}")]
+    NetworkUnknown(String),
+
+    #[error("magic {0:#010x} is not the one of {1}")]
+    WrongNetwork(u32, Network),
+
     #[error("checksum mismatch")]
     ChecksumMismatch,
+
+    #[error("bits {0:#010x} are not a valid target")]
+    TargetInvalid(u32),
+
+    #[error("block hash is above its target")]
+    ProofOfWorkInvalid,
+
+    #[error("previous block {0} is unknown")]
+    PrevBlockUnknown(String),
+
+    #[error("bits {got:#010x} instead of the required {expected:#010x}")]
+    DifficultyUnexpected { expected: u32, got: u32 },
+
+    #[error("block time {0} is not after the median time of the last 11 blocks")]
+    TimeTooOld(u32),
+
+    #[error("block time {0} is too far in the future")]
+    TimeTooNew(u32),
+
+    #[error("transaction has the witness flag but no witnesses")]
+    WitnessEmpty,
+
+    #[error("unknown transaction flags {0:#04x}")]
+    TxFlagsUnknown(u8),
+
+    #[error("merkle root doesn't match the transactions")]
+    MerkleRootMismatch,
+
+    #[error("transaction list is mutated, its merkle root pairs a hash with itself")]
+    MerkleRootMutated,
+
+    #[error("the first transaction, and only the first, has to be a coinbase")]
+    CoinbaseInvalid,
+
+    #[error("witnesses don't match the witness commitment")]
+    WitnessCommitmentMismatch,
+
+    #[error("witnesses in a block without a witness commitment")]
+    WitnessUnexpected,
+
+    #[error("a full batch of headers from the peer added nothing new")]
+    HeadersUnhelpful,
+
+    #[error("no {0} from the peer in time")]
+    Timeout(Command),
+
+    #[error("disconnected: {0}")]
+    Disconnected(DisconnectReason),
 }
@@ -0,0 +1,133 @@
+use std::io::{Read, Write};
+
+use crate::{
+    block_header::BlockHeader,
+    encoding::{read_length, read_var_array, write_var_array, CompactSize, Decodable, Encodable},
+    errors::BitcoinMessageError,
+    payload::PROTOCOL_VERSION,
+};
+
+/// Most headers in one `headers` message. A full one means the peer has more.
+pub const MAX_HEADERS_RESULTS: usize = 2000;
+/// Most hashes in a block locator, same as Bitcoin Core's `MAX_LOCATOR_SZ`.
+pub const MAX_LOCATOR_SIZE: usize = 101;
+
+/// https://en.bitcoin.it/wiki/Protocol_documentation#getheaders
+///
+/// size | field                | type       | description
+/// ---  | -----                | ----       | ------------
+/// 4    | version              | i32        | the protocol version
+/// ?    | block locator hashes | [[u8; 32]] | hashes of our best chain, newest first
+/// 32   | hash_stop            | [u8; 32]   | last header wanted, all zero for as many as possible
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GetHeadersMessage {
+    pub version: i32,
+    pub locator: Vec<[u8; 32]>,
+    pub stop_hash: [u8; 32],
+}
+
+impl GetHeadersMessage {
+    /// Asks for up to [`MAX_HEADERS_RESULTS`] headers after the first locator hash the
+    /// peer knows.
+    pub fn new(locator: Vec<[u8; 32]>) -> Self {
+        GetHeadersMessage {
+            version: PROTOCOL_VERSION,
+            locator,
+            stop_hash: [0; 32],
+        }
+    }
+}
+
+impl Encodable for GetHeadersMessage {
+    fn encode<W: Write>(&self, w: &mut W) -> Result<(), BitcoinMessageError> {
+        self.version.encode(w)?;
+        write_var_array(w, &self.locator)?;
+        self.stop_hash.encode(w)
+    }
+}
+
+impl Decodable for GetHeadersMessage {
+    fn decode<R: Read>(r: &mut R) -> Result<Self, BitcoinMessageError> {
+        Ok(GetHeadersMessage {
+            version: i32::decode(r)?,
+            locator: read_var_array(r, MAX_LOCATOR_SIZE)?,
+            stop_hash: <[u8; 32]>::decode(r)?,
+        })
+    }
+}
+
+/// A header in a `headers` message, followed by a transaction count that is always 0.
+struct HeadersEntry(BlockHeader);
+
+impl Encodable for HeadersEntry {
+    fn encode<W: Write>(&self, w: &mut W) -> Result<(), BitcoinMessageError> {
+        self.0.encode(w)?;
+        CompactSize(0).encode(w)
+    }
+}
+
+impl Decodable for HeadersEntry {
+    fn decode<R: Read>(r: &mut R) -> Result<Self, BitcoinMessageError> {
+        let header = BlockHeader::decode(r)?;
+        read_length(r, 0)?;
+        Ok(HeadersEntry(header))
+    }
+}
+
+/// Writes the payload of a `headers` message.
+pub fn write_headers<W: Write>(
+    w: &mut W,
+    headers: &[BlockHeader],
+) -> Result<(), BitcoinMessageError> {
+    CompactSize(headers.len() as u64).encode(w)?;
+    headers
+        .iter()
+        .try_for_each(|header| HeadersEntry(*header).encode(w))
+}
+
+/// Reads the payload of a `headers` message.
+pub fn read_headers<R: Read>(r: &mut R) -> Result<Vec<BlockHeader>, BitcoinMessageError> {
+    let entries: Vec<HeadersEntry> = read_var_array(r, MAX_HEADERS_RESULTS)?;
+    Ok(entries.into_iter().map(|entry| entry.0).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{network::Network, raw_message::RawMessage};
+
+    #[test]
+    fn getheaders_layout() {
+        let genesis = Network::Mainnet.params().genesis_hash;
+        let message = GetHeadersMessage::new(vec![genesis]);
+        let bytes = message.to_bytes().unwrap();
+        assert_eq!(bytes.len(), 4 + 1 + 32 + 32);
+        assert_eq!(&bytes[..5], &[0x80, 0x11, 0x01, 0x00, 0x01]);
+        assert_eq!(&bytes[5..37], &genesis);
+        assert_eq!(*GetHeadersMessage::from_bytes(&bytes).unwrap(), message);
+
+        let mut bytes = 70016i32.to_le_bytes().to_vec();
+        bytes.push(MAX_LOCATOR_SIZE as u8 + 1);
+        assert!(matches!(
+            GetHeadersMessage::from_bytes(&bytes),
+            Err(BitcoinMessageError::LengthTooBig { len: 102, .. })
+        ));
+    }
+
+    #[test]
+    fn headers_round_trip() {
+        let genesis = Network::Mainnet.params().genesis;
+        let mut bytes = Vec::new();
+        write_headers(&mut bytes, &[genesis, genesis]).unwrap();
+        assert_eq!(bytes.len(), 1 + 2 * 81);
+        assert_eq!(bytes[81], 0);
+        assert_eq!(read_headers(&mut &bytes[..]).unwrap(), [genesis, genesis]);
+
+        // headers don't come with transactions
+        bytes[81] = 1;
+        assert!(matches!(
+            read_headers(&mut &bytes[..]),
+            Err(BitcoinMessageError::LengthTooBig { len: 1, max: 0 })
+        ));
+    }
+}
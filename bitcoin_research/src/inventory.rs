@@ -0,0 +1,134 @@
+use std::io::{Read, Write};
+
+use crate::{
+    encoding::{Decodable, Encodable},
+    errors::BitcoinMessageError,
+};
+
+/// Most entries in one `inv`, `getdata` or `notfound` message.
+pub const MAX_INV_SIZE: usize = 50_000;
+
+/// Flag set on the inventory types asking for witness data (BIP 144).
+const WITNESS_FLAG: u32 = 1 << 30;
+
+/// https://en.bitcoin.it/wiki/Protocol_documentation#Inventory_Vectors
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum InvType {
+    /// Any data of this type may be ignored.
+    Error,
+    Tx,
+    Block,
+    /// A `merkleblock` instead of the block (BIP 37).
+    FilteredBlock,
+    /// A `cmpctblock` instead of the block (BIP 152).
+    CompactBlock,
+    /// A transaction with its witness (BIP 144).
+    WitnessTx,
+    /// A block with its witnesses (BIP 144).
+    WitnessBlock,
+    /// Reserved by BIP 144, not used by Bitcoin Core.
+    FilteredWitnessBlock,
+    /// A type we don't know, kept so it encodes back the same.
+    Unknown(u32),
+}
+
+impl From<u32> for InvType {
+    fn from(n: u32) -> Self {
+        match n {
+            0 => InvType::Error,
+            1 => InvType::Tx,
+            2 => InvType::Block,
+            3 => InvType::FilteredBlock,
+            4 => InvType::CompactBlock,
+            n if n == WITNESS_FLAG | 1 => InvType::WitnessTx,
+            n if n == WITNESS_FLAG | 2 => InvType::WitnessBlock,
+            n if n == WITNESS_FLAG | 3 => InvType::FilteredWitnessBlock,
+            n => InvType::Unknown(n),
+        }
+    }
+}
+
+impl From<InvType> for u32 {
+    fn from(inv_type: InvType) -> Self {
+        match inv_type {
+            InvType::Error => 0,
+            InvType::Tx => 1,
+            InvType::Block => 2,
+            InvType::FilteredBlock => 3,
+            InvType::CompactBlock => 4,
+            InvType::WitnessTx => WITNESS_FLAG | 1,
+            InvType::WitnessBlock => WITNESS_FLAG | 2,
+            InvType::FilteredWitnessBlock => WITNESS_FLAG | 3,
+            InvType::Unknown(n) => n,
+        }
+    }
+}
+
+/// size | field | type     | description
+/// ---  | ----- | ----     | ------------
+/// 4    | type  | u32      | type of the object, see [`InvType`]
+/// 32   | hash  | [u8; 32] | hash of the object, in internal byte order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Inventory {
+    pub inv_type: InvType,
+    pub hash: [u8; 32],
+}
+
+impl Inventory {
+    pub fn new(inv_type: InvType, hash: [u8; 32]) -> Self {
+        Inventory { inv_type, hash }
+    }
+}
+
+impl Encodable for Inventory {
+    fn encode<W: Write>(&self, w: &mut W) -> Result<(), BitcoinMessageError> {
+        u32::from(self.inv_type).encode(w)?;
+        self.hash.encode(w)
+    }
+}
+
+impl Decodable for Inventory {
+    fn decode<R: Read>(r: &mut R) -> Result<Self, BitcoinMessageError> {
+        Ok(Inventory {
+            inv_type: InvType::from(u32::decode(r)?),
+            hash: <[u8; 32]>::decode(r)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::raw_message::RawMessage;
+
+    #[test]
+    fn inv_type_values() {
+        let cases = [
+            (InvType::Error, 0),
+            (InvType::Tx, 1),
+            (InvType::Block, 2),
+            (InvType::FilteredBlock, 3),
+            (InvType::CompactBlock, 4),
+            (InvType::WitnessTx, 0x40000001),
+            (InvType::WitnessBlock, 0x40000002),
+            (InvType::FilteredWitnessBlock, 0x40000003),
+            (InvType::Unknown(0x40000004), 0x40000004),
+        ];
+        for (inv_type, n) in cases {
+            assert_eq!(u32::from(inv_type), n);
+            assert_eq!(InvType::from(n), inv_type);
+        }
+    }
+
+    #[test]
+    fn inventory_layout() {
+        let mut hash = [0u8; 32];
+        hash[0] = 0x6f;
+        let inv = Inventory::new(InvType::WitnessBlock, hash);
+        let bytes = inv.to_bytes().unwrap();
+        assert_eq!(bytes.len(), 36);
+        assert_eq!(&bytes[..5], &[0x02, 0x00, 0x00, 0x40, 0x6f]);
+        assert_eq!(*Inventory::from_bytes(&bytes).unwrap(), inv);
+        assert!(Inventory::from_bytes(&bytes[..35]).is_err());
+    }
+}
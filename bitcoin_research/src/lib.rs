@@ -1,7 +1,21 @@
+pub mod addr;
+pub mod block;
+pub mod block_header;
 pub mod btc_message;
+pub mod chain;
+pub mod codec;
 pub mod command;
+pub mod connection;
+pub mod encoding;
 pub mod errors;
+pub mod headers;
+pub mod inventory;
+pub mod net_addr;
+pub mod network;
 pub mod payload;
+pub mod peer;
 pub mod raw_message;
+pub mod transaction;
+pub mod uint;
 pub mod utils;
 // pub mod version_data;
@@ -1,72 +1,116 @@
-use std::io::{Read, Write};
-use std::net::{Ipv4Addr, SocketAddrV4};
+use std::error::Error;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::process::ExitCode;
+use std::time::Duration;
 
-use bitcoin_research::btc_message::BtcMessage;
-use bitcoin_research::command::Command;
-use bitcoin_research::payload::{Payload, VersionMessage};
-use bitcoin_research::raw_message::RawMessage;
-use bitcoin_research::utils::{checksum, VERSION_COMMAND, VER_ACK_COMMAND};
-
-fn handshake() {
-    // let address = SocketAddrV4::new(Ipv4Addr::new(127, 0,0, 1), 18445);
-    // 162.120.69.182
-    let address = SocketAddrV4::new(Ipv4Addr::new(162, 120,69, 182), 8333);
-    let mut stream = std::net::TcpStream::connect(address).unwrap();
+use tokio::net::TcpStream;
+use tokio::time;
 
-    let version_message = VersionMessage::new(address);
-    let version_message_hash = checksum(version_message.to_bytes().unwrap());
-    // let vers_check : [u8; 4] = version_message_hash[..4].try_into().unwrap();
-    let checksum = u32::from_ne_bytes(version_message_hash);
-    let payload = Payload::Version(version_message);
-    let btc_message = BtcMessage::new(VERSION_COMMAND, payload, checksum);
-    println!("SENDING: {:?}", &btc_message.to_bytes().unwrap());
+use bitcoin_research::chain::HeaderChain;
+use bitcoin_research::command::Command;
+use bitcoin_research::errors::BitcoinMessageError;
+use bitcoin_research::network::Network;
+use bitcoin_research::payload::Payload;
+use bitcoin_research::peer::Peer;
+use bitcoin_research::utils::hash_to_hex;
 
-    // Send version message
-    stream.write_all(&btc_message.to_bytes().unwrap()).unwrap();
-    stream.flush().unwrap();
+/// How long to wait for the connection, and for each batch of headers.
+const TIMEOUT: Duration = Duration::from_secs(60);
 
-    let mut rec_buff = [0; 24];
-    // Receive version payload.
-    println!("Waiting for version answer...");
-    let _ = stream.read_exact(&mut rec_buff); // return the size of the buffer (don't need it now)
+/// Downloads the peer's headers, then asks for new blocks to be announced with theirs.
+async fn sync_headers(
+    peer: &mut Peer<TcpStream>,
+    chain: &mut HeaderChain,
+) -> Result<(), BitcoinMessageError> {
+    let accepted = chain.sync(peer, TIMEOUT).await?;
+    let tip = chain.tip();
+    println!(
+        "synced {} headers, tip {} at height {}",
+        accepted,
+        hash_to_hex(&tip.hash),
+        tip.height
+    );
+    peer.send(Command::SendHeaders, Payload::Empty).await
+}
 
-    
-    let v_answer = BtcMessage::from_bytes(&mut rec_buff).unwrap();
-    println!("RECEIVED: {:?}", v_answer);
-    if v_answer.command != VERSION_COMMAND {
-        println!("Command: {:?}", v_answer.command);
-        println!("Expected: {:?}", Command::Version);
-        println!("ERROR: Wrong command");
-    } else {
-        println!(
-            "connection established. Msg received {:?}",
-            v_answer.command
-        );
+/// Keeps the connection alive, printing what the peer sends, the ping times and the
+/// peers it knows about, until the peer is disconnected.
+async fn listen(peer: &mut Peer<TcpStream>) -> Result<(), BitcoinMessageError> {
+    peer.send(Command::GetAddr, Payload::Empty).await?;
+    loop {
+        let message = peer.next_message().await?;
+        match message.payload() {
+            Payload::Pong(_) => println!("latency: {:?}", peer.latency().last()),
+            Payload::Addr(entries) => {
+                println!("RECEIVED: {} addresses", entries.len());
+                for entry in entries {
+                    println!("  {}", entry.addr.addr);
+                }
+            }
+            Payload::AddrV2(entries) => {
+                println!("RECEIVED: {} addresses", entries.len());
+                for entry in entries {
+                    match entry.socket_addr() {
+                        Some(addr) => println!("  {}", addr),
+                        None => println!("  {:?}:{}", entry.addr, entry.port),
+                    }
+                }
+            }
+            _ => println!("RECEIVED: {:?}", message),
+        }
     }
+}
 
-    stream.flush().unwrap();
-
-    let mut rec_buff = [0; 24];
-    // let mut rec_buff = Vec::new();
-    // Receive version payload.
-    println!("Waiting for verack answer...");
-    let _ = stream.read_exact(&mut rec_buff); // return the size of the buffer (don't need it now)
-    // println!("RECEIVED: {:?} bytes", how_many);
-    let v_answer = BtcMessage::from_bytes(&mut rec_buff).unwrap();
-    println!("RECEIVED: {:?}", v_answer);
-    if v_answer.command != VER_ACK_COMMAND {
-        println!("Command: {:?}", v_answer.command);
-        println!("Expected: {:?}", Command::VerAck.to_bytes());
-        println!("ERROR: Wrong command");
-    } else {
-        println!(
-            "connection established. Msg received {:?}",
-            v_answer.command
-        );
+// const BITCOIN_PROTOCOL_VERSION: i32 = 70016; // matches bitcoin core v24
+/// `bitcoin_research [<network> [<ip>]]`, connecting to a mainnet node by default.
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> ExitCode {
+    match run().await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
     }
 }
 
-// const BITCOIN_PROTOCOL_VERSION: i32 = 70016; // matches bitcoin core v24
-fn main() {
-    handshake();
+async fn run() -> Result<(), Box<dyn Error>> {
+    let mut args = std::env::args().skip(1);
+    let network = match args.next() {
+        Some(arg) => arg.parse::<Network>()?,
+        None => Network::Mainnet,
+    };
+    let ip = match args.next() {
+        Some(arg) => arg
+            .parse::<IpAddr>()
+            .map_err(|e| format!("invalid IP address {:?}: {}", arg, e))?,
+        None => match network {
+            Network::Mainnet => IpAddr::V4(Ipv4Addr::new(162, 120, 69, 182)),
+            _ => IpAddr::V4(Ipv4Addr::LOCALHOST),
+        },
+    };
+    let address = SocketAddr::new(ip, network.default_port());
+    let stream = match time::timeout(TIMEOUT, TcpStream::connect(address)).await {
+        Ok(stream) => stream.map_err(|e| format!("connecting to {} failed: {}", address, e))?,
+        Err(_) => return Err(format!("connecting to {} timed out", address).into()),
+    };
+    let mut peer = Peer::new(stream, network, address);
+    peer.handshake()
+        .await
+        .map_err(|e| format!("handshake with {} failed: {}", address, e))?;
+    let version = peer
+        .version()
+        .expect("established peers sent their version");
+    println!(
+        "connection established with {} ({}, protocol {})",
+        address, version.user_agent, version.protocol_version
+    );
+    let mut chain = HeaderChain::new(network);
+    sync_headers(&mut peer, &mut chain)
+        .await
+        .map_err(|e| format!("header sync with {} failed: {}", address, e))?;
+    listen(&mut peer)
+        .await
+        .map_err(|e| format!("{}: {}", address, e))?;
+    Ok(())
 }
@@ -0,0 +1,110 @@
+use std::io::{Read, Write};
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+
+use crate::{
+    encoding::{decode_be, Decodable, Encodable},
+    errors::BitcoinMessageError,
+};
+
+/// Size of a `net_addr` without the timestamp, as used in `version`.
+pub const NET_ADDR_SIZE: usize = 26;
+
+/// https://en.bitcoin.it/wiki/Protocol_documentation#Network_address
+///
+/// size | field    | type     | description
+/// ---  | -----    | ----     | ------------
+/// 8    | services | u64      | same service(s) listed in version
+/// 16   | IP       | [u8; 16] | IPv6 address, IPv4 addresses are IPv4-mapped (::ffff:a.b.c.d)
+/// 2    | port     | u16      | port number, big endian
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NetAddr {
+    pub services: u64,
+    pub addr: SocketAddr,
+}
+
+impl NetAddr {
+    pub fn new(services: u64, addr: SocketAddr) -> Self {
+        NetAddr { services, addr }
+    }
+
+    /// The all-zero address nodes send when they don't know or don't want to tell.
+    pub fn unspecified(services: u64) -> Self {
+        Self::new(
+            services,
+            SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 0),
+        )
+    }
+}
+
+impl Encodable for NetAddr {
+    fn encode<W: Write>(&self, w: &mut W) -> Result<(), BitcoinMessageError> {
+        let ip = match self.addr.ip() {
+            IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+            IpAddr::V6(ip) => ip,
+        };
+        self.services.encode(w)?;
+        ip.octets().encode(w)?;
+        Ok(w.write_all(&self.addr.port().to_be_bytes())?)
+    }
+}
+
+impl Decodable for NetAddr {
+    fn decode<R: Read>(r: &mut R) -> Result<Self, BitcoinMessageError> {
+        let services = u64::decode(r)?;
+        let ip = Ipv6Addr::from(<[u8; 16]>::decode(r)?);
+        let port = decode_be::<u16, 2, _>(r)?;
+        // only IPv4-mapped addresses are IPv4, "::" stays IPv6 so it encodes back the same
+        let ip = match ip.to_ipv4_mapped() {
+            Some(ip) => IpAddr::V4(ip),
+            None => IpAddr::V6(ip),
+        };
+        Ok(Self::new(services, SocketAddr::new(ip, port)))
+    }
+}
+
+impl From<SocketAddr> for NetAddr {
+    fn from(addr: SocketAddr) -> Self {
+        Self::new(0, addr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::raw_message::RawMessage;
+
+    #[test]
+    fn ipv4_is_mapped() {
+        let addr = NetAddr::new(1, "192.168.0.1:8333".parse().unwrap());
+        let bytes = addr.to_bytes().unwrap();
+        assert_eq!(bytes.len(), NET_ADDR_SIZE);
+        assert_eq!(&bytes[..8], &[1, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(
+            &bytes[8..24],
+            &[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, 192, 168, 0, 1]
+        );
+        assert_eq!(&bytes[24..], &[0x20, 0x8d]);
+
+        assert_eq!(*NetAddr::from_bytes(&bytes).unwrap(), addr);
+    }
+
+    #[test]
+    fn ipv6_round_trip() {
+        for addr in [
+            NetAddr::new(0x409, "[2001:db8::1]:18333".parse().unwrap()),
+            NetAddr::unspecified(0),
+        ] {
+            let decoded = NetAddr::from_bytes(&addr.to_bytes().unwrap()).unwrap();
+            assert_eq!(*decoded, addr);
+        }
+        assert_eq!(
+            NetAddr::unspecified(0).to_bytes().unwrap(),
+            [0; NET_ADDR_SIZE]
+        );
+    }
+
+    #[test]
+    fn too_short() {
+        assert!(NetAddr::from_bytes(&[0; NET_ADDR_SIZE - 1]).is_err());
+    }
+}
@@ -0,0 +1,214 @@
+use std::fmt::Display;
+use std::str::FromStr;
+
+use crate::{block_header::BlockHeader, errors::BitcoinMessageError, utils::hash_from_hex};
+
+/// A Bitcoin network. Messages of one network are rejected by nodes of the others.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Network {
+    Mainnet,
+    Testnet3,
+    Testnet4,
+    Signet,
+    Regtest,
+}
+
+/// Constants of a [`Network`], as in Bitcoin Core's `chainparams.cpp`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NetworkParams {
+    /// Start of every message, in wire order.
+    pub magic: [u8; 4],
+    pub default_port: u16,
+    /// Hash of the genesis block header, in internal byte order (reversed from how it's displayed).
+    pub genesis_hash: [u8; 32],
+    pub genesis: BlockHeader,
+    /// Easiest allowed target, in compact `bits` form.
+    pub pow_limit_bits: u32,
+    /// Blocks more than 20 minutes apart may use the easiest target.
+    pub allow_min_difficulty_blocks: bool,
+    /// The target never changes.
+    pub no_retargeting: bool,
+    /// Retargeting starts from the first block of the period instead of the last (BIP 94).
+    pub enforce_bip94: bool,
+}
+
+/// Merkle root of the genesis coinbase, "The Times 03/Jan/2009 Chancellor on brink of
+/// second bailout for banks".
+const GENESIS_MERKLE_ROOT: [u8; 32] =
+    hash_from_hex("4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b");
+
+const fn genesis(merkle_root: [u8; 32], time: u32, bits: u32, nonce: u32) -> BlockHeader {
+    BlockHeader {
+        version: 1,
+        prev_block: [0; 32],
+        merkle_root,
+        time,
+        bits,
+        nonce,
+    }
+}
+
+const MAINNET: NetworkParams = NetworkParams {
+    magic: [0xf9, 0xbe, 0xb4, 0xd9],
+    default_port: 8333,
+    genesis_hash: hash_from_hex("000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f"),
+    genesis: genesis(GENESIS_MERKLE_ROOT, 1231006505, 0x1d00ffff, 2083236893),
+    pow_limit_bits: 0x1d00ffff,
+    allow_min_difficulty_blocks: false,
+    no_retargeting: false,
+    enforce_bip94: false,
+};
+
+const TESTNET3: NetworkParams = NetworkParams {
+    magic: [0x0b, 0x11, 0x09, 0x07],
+    default_port: 18333,
+    genesis_hash: hash_from_hex("000000000933ea01ad0ee984209779baaec3ced90fa3f408719526f8d77f4943"),
+    genesis: genesis(GENESIS_MERKLE_ROOT, 1296688602, 0x1d00ffff, 414098458),
+    pow_limit_bits: 0x1d00ffff,
+    allow_min_difficulty_blocks: true,
+    no_retargeting: false,
+    enforce_bip94: false,
+};
+
+const TESTNET4: NetworkParams = NetworkParams {
+    magic: [0x1c, 0x16, 0x3f, 0x28],
+    default_port: 48333,
+    genesis_hash: hash_from_hex("00000000da84f2bafbbc53dee25a72ae507ff4914b867c565be350b0da8bf043"),
+    genesis: genesis(
+        // its coinbase quotes a mainnet block hash instead
+        hash_from_hex("7aa0a7ae1e223414cb807e40cd57e667b718e42aaf9306db9102fe28912b7b4e"),
+        1714777860,
+        0x1d00ffff,
+        393743547,
+    ),
+    pow_limit_bits: 0x1d00ffff,
+    allow_min_difficulty_blocks: true,
+    no_retargeting: false,
+    enforce_bip94: true,
+};
+
+// the default signet, custom signets have their own challenge and magic
+const SIGNET: NetworkParams = NetworkParams {
+    magic: [0x0a, 0x03, 0xcf, 0x40],
+    default_port: 38333,
+    genesis_hash: hash_from_hex("00000008819873e925422c1ff0f99f7cc9bbb232af63a077a480a3633bee1ef6"),
+    genesis: genesis(GENESIS_MERKLE_ROOT, 1598918400, 0x1e0377ae, 52613770),
+    pow_limit_bits: 0x1e0377ae,
+    allow_min_difficulty_blocks: false,
+    no_retargeting: false,
+    enforce_bip94: false,
+};
+
+const REGTEST: NetworkParams = NetworkParams {
+    magic: [0xfa, 0xbf, 0xb5, 0xda],
+    default_port: 18444,
+    genesis_hash: hash_from_hex("0f9188f13cb7b2c71f2a335e3a4fc328bf5beb436012afca590b1a11466e2206"),
+    genesis: genesis(GENESIS_MERKLE_ROOT, 1296688602, 0x207fffff, 2),
+    pow_limit_bits: 0x207fffff,
+    allow_min_difficulty_blocks: true,
+    no_retargeting: true,
+    enforce_bip94: false,
+};
+
+impl Network {
+    pub const ALL: [Network; 5] = [
+        Network::Mainnet,
+        Network::Testnet3,
+        Network::Testnet4,
+        Network::Signet,
+        Network::Regtest,
+    ];
+
+    pub fn params(&self) -> &'static NetworkParams {
+        match self {
+            Network::Mainnet => &MAINNET,
+            Network::Testnet3 => &TESTNET3,
+            Network::Testnet4 => &TESTNET4,
+            Network::Signet => &SIGNET,
+            Network::Regtest => &REGTEST,
+        }
+    }
+
+    /// The magic as the little endian `u32` at the start of a message.
+    pub fn magic(&self) -> u32 {
+        u32::from_le_bytes(self.params().magic)
+    }
+
+    pub fn default_port(&self) -> u16 {
+        self.params().default_port
+    }
+
+    pub fn from_magic(magic: u32) -> Option<Network> {
+        Self::ALL
+            .into_iter()
+            .find(|network| network.magic() == magic)
+    }
+}
+
+impl Display for Network {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // the names Bitcoin Core's -chain option takes
+        let s = match self {
+            Network::Mainnet => "main",
+            Network::Testnet3 => "test",
+            Network::Testnet4 => "testnet4",
+            Network::Signet => "signet",
+            Network::Regtest => "regtest",
+        };
+
+        write!(f, "{}", s)
+    }
+}
+
+impl FromStr for Network {
+    type Err = BitcoinMessageError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "main" | "mainnet" | "bitcoin" => Ok(Network::Mainnet),
+            "test" | "testnet" | "testnet3" => Ok(Network::Testnet3),
+            "testnet4" => Ok(Network::Testnet4),
+            "signet" => Ok(Network::Signet),
+            "regtest" => Ok(Network::Regtest),
+            x => Err(BitcoinMessageError::NetworkUnknown(x.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn magic_round_trip() {
+        for network in Network::ALL {
+            assert_eq!(Network::from_magic(network.magic()), Some(network));
+            assert_eq!(network.to_string().parse::<Network>().unwrap(), network);
+        }
+        assert_eq!(Network::Mainnet.magic(), 0xD9B4BEF9);
+        assert_eq!(Network::from_magic(0), None);
+        assert!("bogus".parse::<Network>().is_err());
+    }
+
+    #[test]
+    fn genesis_hash_byte_order() {
+        let hash = Network::Mainnet.params().genesis_hash;
+        assert_eq!(hash[0], 0x6f);
+        assert_eq!(hash[31], 0x00);
+        assert_eq!(&hash[26..], &[0x19, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn genesis_headers() {
+        for network in Network::ALL {
+            let params = network.params();
+            assert_eq!(
+                params.genesis.block_hash(),
+                params.genesis_hash,
+                "{}",
+                network
+            );
+            assert_eq!(params.genesis.bits, params.pow_limit_bits, "{}", network);
+        }
+    }
+}
@@ -1,55 +1,142 @@
-use crate::{errors::BitcoinMessageError, raw_message::RawMessage, utils::*};
-use std::io::Error;
-use std::{
-    net::{Ipv4Addr, SocketAddrV4},
-    time::UNIX_EPOCH,
+use crate::{
+    addr::{AddrEntry, AddrV2Entry, MAX_ADDR},
+    block::Block,
+    block_header::BlockHeader,
+    command::Command,
+    encoding::{
+        read_var_array, read_var_str, write_var_array, write_var_str, Decodable, Encodable,
+    },
+    errors::BitcoinMessageError,
+    headers::{read_headers, write_headers, GetHeadersMessage},
+    inventory::{Inventory, MAX_INV_SIZE},
+    net_addr::NetAddr,
+    transaction::Transaction,
 };
+use std::io::{Read, Write};
+use std::{net::SocketAddr, time::UNIX_EPOCH};
 // use bitflags::bitflags;
 // use byteorder::{BigEndian, LittleEndian, ReadBytesExt, WriteBytesExt};
 
-use core::ops::BitAnd;
-
-const PROTOCOL_VERSION: i32 = 70016;
+pub const PROTOCOL_VERSION: i32 = 70016;
+/// Oldest protocol version of a peer we stay connected to, as Bitcoin Core's
+/// `MIN_PEER_PROTO_VERSION`.
+pub const MIN_PEER_PROTO_VERSION: i32 = 31800;
+/// First protocol version with the `relay` field (BIP 37).
+pub const RELAY_VERSION: i32 = 70001;
+/// First protocol version that negotiates `wtxidrelay` (BIP 339).
+pub const WTXID_RELAY_VERSION: i32 = 70016;
+/// Longest user agent accepted, same as Bitcoin Core's `MAX_SUBVERSION_LENGTH`.
+pub const MAX_USER_AGENT_SIZE: usize = 256;
+pub const USER_AGENT: &str = concat!("/bitcoin_research:", env!("CARGO_PKG_VERSION"), "/");
+/// `NODE_NETWORK` service bit.
+pub const NODE_NETWORK: u64 = 0x1;
 /// Max payload size, as per Bitcoin protocol docs
-const MAX_SIZE: usize = 32 * 1024 * 1024;
+pub const MAX_SIZE: usize = 32 * 1024 * 1024;
 
 #[derive(Debug, Clone)]
 /// Bitcoin's Message payload.
 pub enum Payload {
-    /// An empty payload.
+    /// An empty payload, as in `verack`, `getaddr`, `sendaddrv2`, `wtxidrelay` and
+    /// `sendheaders`.
     Empty,
 
     /// Payload of `version` command
     Version(VersionMessage),
+
+    /// Payload of `ping` command, a nonce the `pong` has to echo
+    Ping(u64),
+
+    /// Payload of `pong` command, the nonce of the `ping` it answers
+    Pong(u64),
+
+    /// Payload of `addr` command
+    Addr(Vec<AddrEntry>),
+
+    /// Payload of `addrv2` command (BIP 155)
+    AddrV2(Vec<AddrV2Entry>),
+
+    /// Payload of `inv` command, objects the peer has
+    Inv(Vec<Inventory>),
+
+    /// Payload of `getdata` command, objects asked for
+    GetData(Vec<Inventory>),
+
+    /// Payload of `notfound` command, objects asked for that the peer doesn't have
+    NotFound(Vec<Inventory>),
+
+    /// Payload of `getheaders` command
+    GetHeaders(GetHeadersMessage),
+
+    /// Payload of `headers` command
+    Headers(Vec<BlockHeader>),
+
+    /// Payload of `tx` command
+    Tx(Transaction),
+
+    /// Payload of `block` command
+    Block(Block),
 }
 
 impl Payload {
-    //   // special case as it needs to know the command name
-    //   /// Deserializes [`Payload`] from buffer of bytes.
-    //   pub fn from_bytes(
-    //       data: &mut impl Read,
-    //       command: &Command,
-    //   ) -> Result<Self, BitcoinMessageError> {
-    //       match command {
-    //           Command::Version => Ok(Payload::Version(VersionMessage::from_bytes(data)?)),
-    //           Command::VerAck => Ok(Payload::Empty),
-    //       }
-    //   }
-    // }
-
-    // impl BitcoinSerialize for Payload {
+    // special case as it needs to know the command name
+    /// Deserializes [`Payload`] from buffer of bytes.
+    pub fn from_bytes(data: &[u8], command: &Command) -> Result<Self, BitcoinMessageError> {
+        if data.len() > MAX_SIZE {
+            return Err(BitcoinMessageError::PayloadTooBig);
+        }
+        Self::decode(&mut &data[..], command)
+    }
+
+    /// Reads the payload of a `command` message.
+    pub fn decode<R: Read>(r: &mut R, command: &Command) -> Result<Self, BitcoinMessageError> {
+        match command {
+            Command::Version => Ok(Payload::Version(VersionMessage::decode(r)?)),
+            Command::VerAck
+            | Command::GetAddr
+            | Command::SendAddrV2
+            | Command::WtxidRelay
+            | Command::SendHeaders => Ok(Payload::Empty),
+            Command::Ping => Ok(Payload::Ping(u64::decode(r)?)),
+            Command::Pong => Ok(Payload::Pong(u64::decode(r)?)),
+            Command::Addr => Ok(Payload::Addr(read_var_array(r, MAX_ADDR)?)),
+            Command::AddrV2 => Ok(Payload::AddrV2(read_var_array(r, MAX_ADDR)?)),
+            Command::Inv => Ok(Payload::Inv(read_var_array(r, MAX_INV_SIZE)?)),
+            Command::GetData => Ok(Payload::GetData(read_var_array(r, MAX_INV_SIZE)?)),
+            Command::NotFound => Ok(Payload::NotFound(read_var_array(r, MAX_INV_SIZE)?)),
+            Command::GetHeaders => Ok(Payload::GetHeaders(GetHeadersMessage::decode(r)?)),
+            Command::Headers => Ok(Payload::Headers(read_headers(r)?)),
+            Command::Tx => Ok(Payload::Tx(Transaction::decode(r)?)),
+            Command::Block => Ok(Payload::Block(Block::decode(r)?)),
+        }
+    }
+
     pub fn to_bytes(&self) -> Result<Vec<u8>, BitcoinMessageError> {
-        let data = match self {
-            Payload::Empty => Ok(vec![]),
-            Payload::Version(data) => data.to_bytes(),
-        };
-        if let Ok(ref d) = data {
-            if d.len() > MAX_SIZE {
-                return Err(BitcoinMessageError::PayloadTooBig);
-            }
+        let mut data = Vec::new();
+        self.encode(&mut data)?;
+        if data.len() > MAX_SIZE {
+            return Err(BitcoinMessageError::PayloadTooBig);
         }
 
-        data
+        Ok(data)
+    }
+}
+
+impl Encodable for Payload {
+    fn encode<W: Write>(&self, w: &mut W) -> Result<(), BitcoinMessageError> {
+        match self {
+            Payload::Empty => Ok(()),
+            Payload::Version(data) => data.encode(w),
+            Payload::Ping(nonce) | Payload::Pong(nonce) => nonce.encode(w),
+            Payload::Addr(entries) => write_var_array(w, entries),
+            Payload::AddrV2(entries) => write_var_array(w, entries),
+            Payload::Inv(inventory)
+            | Payload::GetData(inventory)
+            | Payload::NotFound(inventory) => write_var_array(w, inventory),
+            Payload::GetHeaders(data) => data.encode(w),
+            Payload::Headers(headers) => write_headers(w, headers),
+            Payload::Tx(tx) => tx.encode(w),
+            Payload::Block(block) => block.encode(w),
+        }
     }
 }
 
@@ -66,37 +153,41 @@ impl Payload {
 /// ?    | user_agent   | var_str  | User Agent (0x00 if string is 0 bytes long)
 /// 4    | start_height | i32      | The last block received by the emitting node
 /// 1    | relay        | bool     | Whether the remote peer should announce relayed transactions or not, see BIP 0037
+///
+/// `relay` is only sent from protocol version 70001 ([`RELAY_VERSION`]) on.
 /// *********************************************************
 /// Almost all integers are encoded in little endian. Only IP or port number are encoded big endian.
 /// *********************************************************
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct VersionMessage {
     pub protocol_version: i32,
     pub service: u64,
     pub timestamp: i64,
-    pub addr_recv: SocketAddrV4,
-    pub addr_from: SocketAddrV4,
+    pub addr_recv: NetAddr,
+    pub addr_from: NetAddr,
     pub nonce: u64,
     pub user_agent: String,
     pub start_height: i32,
+    /// Not on the wire before [`RELAY_VERSION`], where it reads as `true`.
+    pub relay: bool,
 }
 
 impl VersionMessage {
-    pub fn new(addr_recv: SocketAddrV4) -> Self {
-      let timestamp = Self::calculate_timestamp();
-      VersionMessage {
-          protocol_version: PROTOCOL_VERSION,
-          service: 0x1,
-          timestamp,
-          addr_recv,
-          //45.154.254.133:8333
-          // addr_recv: SocketAddrV4::new(Ipv4Addr::new(45, 154, 254, 133), 8333),
-          addr_from: SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), 8080),
-          nonce: 0,
-          user_agent: "".to_string(),
-          start_height: 1,
-      }
+    pub fn new(addr_recv: SocketAddr) -> Self {
+        let timestamp = Self::calculate_timestamp();
+        VersionMessage {
+            protocol_version: PROTOCOL_VERSION,
+            service: NODE_NETWORK,
+            timestamp,
+            addr_recv: NetAddr::from(addr_recv),
+            // like Bitcoin Core, we don't tell our own address
+            addr_from: NetAddr::unspecified(NODE_NETWORK),
+            nonce: 0,
+            user_agent: USER_AGENT.to_string(),
+            start_height: 0,
+            relay: false,
+        }
     }
 
     fn calculate_timestamp() -> i64 {
@@ -105,71 +196,254 @@ impl VersionMessage {
             .unwrap()
             .as_secs() as i64
     }
+}
+
+impl Encodable for VersionMessage {
+    fn encode<W: Write>(&self, w: &mut W) -> Result<(), BitcoinMessageError> {
+        if self.user_agent.len() > MAX_USER_AGENT_SIZE {
+            return Err(BitcoinMessageError::LengthTooBig {
+                len: self.user_agent.len() as u64,
+                max: MAX_USER_AGENT_SIZE,
+            });
+        }
+        self.protocol_version.encode(w)?;
+        self.service.encode(w)?;
+        self.timestamp.encode(w)?;
+        self.addr_recv.encode(w)?;
+        self.addr_from.encode(w)?;
+        self.nonce.encode(w)?;
+        write_var_str(w, &self.user_agent)?;
+        self.start_height.encode(w)?;
+        if self.protocol_version >= RELAY_VERSION {
+            self.relay.encode(w)?;
+        }
+        Ok(())
+    }
+}
+
+impl Decodable for VersionMessage {
+    fn decode<R: Read>(r: &mut R) -> Result<Self, BitcoinMessageError> {
+        let protocol_version = i32::decode(r)?;
+        Ok(VersionMessage {
+            protocol_version,
+            service: u64::decode(r)?,
+            timestamp: i64::decode(r)?,
+            addr_recv: NetAddr::decode(r)?,
+            addr_from: NetAddr::decode(r)?,
+            nonce: u64::decode(r)?,
+            user_agent: read_var_str(r, MAX_USER_AGENT_SIZE)?,
+            start_height: i32::decode(r)?,
+            relay: protocol_version < RELAY_VERSION || bool::decode(r)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 
-    fn nodenetwork_bitmask(node_net: &i32) -> u64 {
-        let mut buffer: u64 = 0x0;
-        buffer = buffer.bitand(*node_net as u64);
-        buffer
+    use super::*;
+    use crate::{inventory::InvType, network::Network, raw_message::RawMessage, utils::hex};
+
+    /// `version` payload of a Satoshi 0.7.2 node (protocol 60002, so no relay field),
+    /// from https://en.bitcoin.it/wiki/Protocol_documentation#version
+    const SATOSHI_0_7_2: &str = "62ea0000010000000000000011b2d05000000000\
+        010000000000000000000000000000000000ffff000000000000\
+        010000000000000000000000000000000000ffff000000000000\
+        3b2eb35d8ce61765\
+        0f2f5361746f7368693a302e372e322f\
+        c03e0300";
+
+    /// `version` payload of a mainnet Satoshi 0.17.1 node (protocol 70015, with the relay
+    /// flag), captured in January 2019 and used as a fixture by rust-bitcoin.
+    const SATOSHI_0_17_1: &str = "7f1101000d04000000000000f00f4d5c00000000\
+        000000000000000000000000000000000000ffff5bf08c80b4bd\
+        0d04000000000000000000000000000000000000000000000000\
+        faa99559cc68a1c1\
+        102f5361746f7368693a302e31372e312f\
+        938c0800\
+        01";
+
+    #[test]
+    fn ping_pong_round_trip() {
+        let nonce = 0x0123_4567_89ab_cdef;
+        for (command, payload) in [
+            (Command::Ping, Payload::Ping(nonce)),
+            (Command::Pong, Payload::Pong(nonce)),
+        ] {
+            let data = payload.to_bytes().unwrap();
+            assert_eq!(data, nonce.to_le_bytes());
+            let decoded = Payload::from_bytes(&data, &command).unwrap();
+            assert_eq!(decoded.to_bytes().unwrap(), data);
+        }
+        assert!(Payload::from_bytes(&[0; 7], &Command::Ping).is_err());
     }
 
-    // supporting only Ipv4 address here ...
-    fn netaddr_as_bytes(node_bitmask: &u64, address: &SocketAddrV4) -> Vec<u8> {
-        let mut buffer: Vec<u8> = Vec::new();
-        buffer.extend_from_slice(&node_bitmask.to_le_bytes());
-        let ip_addr_bytes = address.ip().to_ipv6_compatible().octets();
+    #[test]
+    fn addr_round_trip() {
+        let entry = AddrEntry {
+            time: 1_700_000_000,
+            addr: NetAddr::new(NODE_NETWORK, "10.0.0.1:8333".parse().unwrap()),
+        };
+        for (command, payload) in [
+            (Command::Addr, Payload::Addr(vec![entry; 3])),
+            (
+                Command::AddrV2,
+                Payload::AddrV2(vec![AddrV2Entry::from(entry); 3]),
+            ),
+        ] {
+            let data = payload.to_bytes().unwrap();
+            assert_eq!(data[0], 3);
+            let decoded = Payload::from_bytes(&data, &command).unwrap();
+            assert_eq!(decoded.to_bytes().unwrap(), data);
+        }
+    }
 
-        buffer.extend_from_slice(&ip_addr_bytes);
-        buffer.extend_from_slice(&address.port().to_be_bytes());
+    #[test]
+    fn too_many_addresses() {
+        let entry = AddrEntry {
+            time: 0,
+            addr: NetAddr::new(0, "10.0.0.1:8333".parse().unwrap()),
+        };
+        let data = Payload::Addr(vec![entry; MAX_ADDR + 1]).to_bytes().unwrap();
+        assert!(matches!(
+            Payload::from_bytes(&data, &Command::Addr),
+            Err(BitcoinMessageError::LengthTooBig { len: 1001, .. })
+        ));
+    }
 
-        buffer
+    #[test]
+    fn inventory_round_trip() {
+        let inventory = vec![
+            Inventory::new(InvType::WitnessTx, [0x11; 32]),
+            Inventory::new(InvType::Block, [0x22; 32]),
+            Inventory::new(InvType::CompactBlock, [0x33; 32]),
+        ];
+        for (command, payload) in [
+            (Command::Inv, Payload::Inv(inventory.clone())),
+            (Command::GetData, Payload::GetData(inventory.clone())),
+            (Command::NotFound, Payload::NotFound(inventory.clone())),
+        ] {
+            let data = payload.to_bytes().unwrap();
+            assert_eq!(data.len(), 1 + 3 * 36);
+            let decoded = Payload::from_bytes(&data, &command).unwrap();
+            assert_eq!(decoded.to_bytes().unwrap(), data);
+        }
     }
 
-    fn netaddr_from_bytes(buff: &mut Vec<u8>) -> Result<SocketAddrV4, Error> {
-        let (_, buff) = parse_frombytes_le::<u64>(buff)?; // node service field
-        let (ip_addr, buff) = read_drop_slice(&buff, 16)?;
-        let (port_addr, _) = parse_frombytes_be::<u16>(&buff)?;
-        let array_ip = <[u8; 4]>::try_from(ip_addr).unwrap();
-        Ok(SocketAddrV4::new(Ipv4Addr::from(array_ip), port_addr))
+    #[test]
+    fn too_much_inventory() {
+        let inventory = vec![Inventory::new(InvType::Tx, [0; 32]); MAX_INV_SIZE + 1];
+        let data = Payload::Inv(inventory).to_bytes().unwrap();
+        for command in [Command::Inv, Command::GetData, Command::NotFound] {
+            assert!(matches!(
+                Payload::from_bytes(&data, &command),
+                Err(BitcoinMessageError::LengthTooBig { len: 50_001, .. })
+            ));
+        }
     }
-}
 
-impl RawMessage for VersionMessage {
-    fn to_bytes(&self) -> Result<Vec<u8>, BitcoinMessageError> {
-        let svc_bitmask = Self::nodenetwork_bitmask(&0x1);
-        let mut address_bytes = Self::netaddr_as_bytes(&svc_bitmask, &self.addr_recv);
-        let mut buffer: Vec<u8> = vec![];
-        buffer.extend_from_slice(&self.protocol_version.to_le_bytes());
-        buffer.extend_from_slice(&svc_bitmask.to_le_bytes());
-        buffer.extend_from_slice(&self.timestamp.to_le_bytes());
-        buffer.append(&mut address_bytes);
-        buffer.extend_from_slice(&[0x0_u8; 26]); // addr_from
-        buffer.extend_from_slice(&self.nonce.to_le_bytes());
-        buffer.extend_from_slice(&[self.user_agent.len() as u8]);
-        buffer.extend_from_slice(&self.start_height.to_le_bytes());
-        buffer.extend_from_slice(&[0]); // relay
-
-        Ok(buffer)
-    }
-
-    fn from_bytes(msg: &Vec<u8>) -> Result<Box<Self>, BitcoinMessageError> {
-        let (protocol_version, buff) = parse_frombytes_le::<i32>(msg)?;
-        let (service, buff) = parse_frombytes_le::<u64>(&buff)?;
-        let (timestamp, buff) = parse_frombytes_le::<i64>(&buff)?;
-
-        let address = Self::netaddr_from_bytes(&mut buff.to_vec())?;
-        let add_from = Self::netaddr_from_bytes(&mut buff.to_vec())?;
-        let (nonce, _) = parse_frombytes_le::<u64>(&buff)?;
-        // dropping the remaining fields ...
-
-        Ok(Box::new(VersionMessage {
-            protocol_version,
-            service,
-            timestamp,
-            addr_recv: address,
-            addr_from: add_from,
-            nonce,
-            user_agent: "".to_string(), // TODO let user_agent = parser.read_var_string()?;
-            start_height: 1,            // TODO let start_height = parser.read_i32_le()?;
-        }))
+    #[test]
+    fn headers_round_trip() {
+        let genesis = Network::Mainnet.params().genesis;
+        for (command, payload) in [
+            (
+                Command::GetHeaders,
+                Payload::GetHeaders(GetHeadersMessage::new(vec![genesis.block_hash()])),
+            ),
+            (Command::Headers, Payload::Headers(vec![genesis; 2])),
+            (Command::SendHeaders, Payload::Empty),
+        ] {
+            let data = payload.to_bytes().unwrap();
+            let decoded = Payload::from_bytes(&data, &command).unwrap();
+            assert_eq!(decoded.to_bytes().unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn version_without_relay() {
+        let data = hex(SATOSHI_0_7_2);
+        let msg = VersionMessage::from_bytes(&data).unwrap();
+        assert_eq!(msg.protocol_version, 60002);
+        assert_eq!(msg.service, NODE_NETWORK);
+        assert_eq!(msg.timestamp, 0x50d0b211);
+        assert_eq!(
+            msg.addr_recv,
+            NetAddr::new(1, SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0))
+        );
+        assert_eq!(msg.addr_from, msg.addr_recv);
+        assert_eq!(msg.nonce, 0x6517e68c5db32e3b);
+        assert_eq!(msg.user_agent, "/Satoshi:0.7.2/");
+        assert_eq!(msg.start_height, 212672);
+        assert!(msg.relay);
+        assert_eq!(msg.to_bytes().unwrap(), data);
+    }
+
+    #[test]
+    fn version_with_relay() {
+        let data = hex(SATOSHI_0_17_1);
+        let msg = VersionMessage::from_bytes(&data).unwrap();
+        assert_eq!(msg.protocol_version, 70015);
+        // network, bloom, witness and network limited
+        assert_eq!(msg.service, 0x40d);
+        assert_eq!(msg.timestamp, 1548554224);
+        assert_eq!(
+            msg.addr_recv,
+            NetAddr::new(0, "91.240.140.128:46269".parse().unwrap())
+        );
+        assert_eq!(
+            msg.addr_from,
+            NetAddr::new(0x40d, SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 0))
+        );
+        assert_eq!(msg.nonce, 13952548347456104954);
+        assert_eq!(msg.user_agent, "/Satoshi:0.17.1/");
+        assert_eq!(msg.start_height, 560275);
+        assert!(msg.relay);
+        assert_eq!(msg.to_bytes().unwrap(), data);
+    }
+
+    #[test]
+    fn new_round_trip() {
+        let msg = VersionMessage::new("10.0.0.1:8333".parse().unwrap());
+        let data = msg.to_bytes().unwrap();
+        assert_eq!(data.len(), 86 + USER_AGENT.len());
+        assert_eq!(*VersionMessage::from_bytes(&data).unwrap(), msg);
+    }
+
+    #[test]
+    fn missing_relay() {
+        let data = hex(SATOSHI_0_17_1);
+        assert!(matches!(
+            VersionMessage::from_bytes(&data[..data.len() - 1]),
+            Err(BitcoinMessageError::SerializationError(_))
+        ));
+    }
+
+    #[test]
+    fn user_agent_too_long() {
+        let mut msg = VersionMessage::new("10.0.0.1:8333".parse().unwrap());
+        msg.user_agent = "a".repeat(MAX_USER_AGENT_SIZE + 1);
+        assert!(matches!(
+            msg.to_bytes(),
+            Err(BitcoinMessageError::LengthTooBig { len: 257, .. })
+        ));
+
+        let mut data = hex(SATOSHI_0_7_2);
+        // user agent length at offset 80, as a 0xFD prefixed u16
+        data.splice(80..81, [0xfd, 0x01, 0x01]);
+        assert!(matches!(
+            VersionMessage::from_bytes(&data),
+            Err(BitcoinMessageError::LengthTooBig { len: 257, max: 256 })
+        ));
+    }
+
+    #[test]
+    fn user_agent_not_utf8() {
+        let mut data = hex(SATOSHI_0_7_2);
+        data[81] = 0xff;
+        assert!(matches!(
+            VersionMessage::from_bytes(&data),
+            Err(BitcoinMessageError::Utf8DeserializationError(_))
+        ));
     }
 }
@@ -0,0 +1,621 @@
+use std::collections::VecDeque;
+use std::future::poll_fn;
+use std::io::ErrorKind;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::{SinkExt, StreamExt};
+use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::time;
+use tokio_util::codec::Framed;
+
+use crate::{
+    btc_message::BtcMessage,
+    codec::{framed, MessageCodec},
+    command::Command,
+    connection::{Latency, Pings},
+    errors::BitcoinMessageError,
+    network::Network,
+    payload::{Payload, VersionMessage, MIN_PEER_PROTO_VERSION, WTXID_RELAY_VERSION},
+};
+
+/// How long a peer has to finish the handshake, same as Bitcoin Core's `-peertimeout`.
+pub const DEFAULT_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Where a [`Peer`] is in the life of its connection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PeerState {
+    /// Connected, our `version` not sent yet.
+    Connecting,
+    /// Our `version` is sent, the peer's hasn't come.
+    VersionSent,
+    /// The peer's `version` came and we sent our `verack`, theirs hasn't come.
+    VersionReceived,
+    /// Both sides sent `version` and `verack`.
+    Established,
+    /// The peer is given up on, nothing more is sent or read.
+    Disconnecting(DisconnectReason),
+}
+
+/// Why a [`Peer`] is disconnecting.
+#[derive(Error, Debug, Clone)]
+pub enum DisconnectReason {
+    #[error("handshake not finished in time")]
+    HandshakeTimeout,
+
+    #[error("no pong to our ping in time")]
+    PingTimeout,
+
+    #[error("connection closed by the peer")]
+    ConnectionClosed,
+
+    #[error("connection failed: {0}")]
+    Io(ErrorKind),
+
+    #[error("protocol version {0} is too old")]
+    ProtocolVersionTooOld(i32),
+
+    /// `wtxidrelay` and `sendaddrv2` have to come before `verack`.
+    #[error("{0} after verack")]
+    NegotiationAfterVerack(Command),
+
+    /// The peer sent a message that doesn't decode.
+    #[error("{0}")]
+    Protocol(Arc<BitcoinMessageError>),
+}
+
+impl PartialEq for DisconnectReason {
+    fn eq(&self, other: &Self) -> bool {
+        use DisconnectReason::*;
+        match (self, other) {
+            (HandshakeTimeout, HandshakeTimeout)
+            | (PingTimeout, PingTimeout)
+            | (ConnectionClosed, ConnectionClosed) => true,
+            (Io(a), Io(b)) => a == b,
+            (ProtocolVersionTooOld(a), ProtocolVersionTooOld(b)) => a == b,
+            (NegotiationAfterVerack(a), NegotiationAfterVerack(b)) => a == b,
+            // errors don't compare, but a peer's reason is shared by every call failing with it
+            (Protocol(a), Protocol(b)) => Arc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+impl Eq for DisconnectReason {}
+
+impl From<BitcoinMessageError> for DisconnectReason {
+    fn from(e: BitcoinMessageError) -> Self {
+        match e {
+            BitcoinMessageError::Disconnected(reason) => reason,
+            BitcoinMessageError::Timeout(Command::Pong) => DisconnectReason::PingTimeout,
+            BitcoinMessageError::SerializationError(e) => match e.kind() {
+                ErrorKind::BrokenPipe
+                | ErrorKind::ConnectionReset
+                | ErrorKind::ConnectionAborted => DisconnectReason::ConnectionClosed,
+                // a payload cut short or malformed, the stream ending is caught before
+                ErrorKind::UnexpectedEof | ErrorKind::InvalidData => {
+                    DisconnectReason::Protocol(Arc::new(e.into()))
+                }
+                kind => DisconnectReason::Io(kind),
+            },
+            e => DisconnectReason::Protocol(Arc::new(e)),
+        }
+    }
+}
+
+/// A peer we connected to, through the `version`/`verack` handshake and after.
+///
+/// Messages are framed by [`MessageCodec`] on an async stream. The peer's `version` and
+/// `verack` are taken in either order. Before our `verack`, `wtxidrelay` (BIP 339) and
+/// `sendaddrv2` (BIP 155) are sent, and the peer's are recorded up to its `verack`.
+/// Pings are answered and sent the way [`Connection`](crate::connection::Connection)
+/// does. Anything going wrong leaves the peer [`PeerState::Disconnecting`], and that call
+/// and every one after it fail with [`BitcoinMessageError::Disconnected`].
+///
+/// The calls are cancel safe: a handshake that is dropped half way is picked up by the
+/// next call, and messages are queued before they are sent, so none is lost or sent twice.
+pub struct Peer<S> {
+    framed: Framed<S, MessageCodec>,
+    address: SocketAddr,
+    state: PeerState,
+    handshake_timeout: Duration,
+    /// Set by the first call to [`Peer::handshake`].
+    handshake_deadline: Option<time::Instant>,
+    /// Messages waiting to be handed to `framed`.
+    outbox: VecDeque<BtcMessage>,
+    pings: Pings,
+    /// The peer's `version`.
+    version: Option<VersionMessage>,
+    verack_received: bool,
+    wtxid_relay_received: bool,
+    sendaddrv2_received: bool,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Peer<S> {
+    /// A peer at `address`, connected through `stream`.
+    pub fn new(stream: S, network: Network, address: SocketAddr) -> Self {
+        Peer {
+            framed: framed(stream, network),
+            address,
+            state: PeerState::Connecting,
+            handshake_timeout: DEFAULT_HANDSHAKE_TIMEOUT,
+            handshake_deadline: None,
+            outbox: VecDeque::new(),
+            pings: Pings::new(),
+            version: None,
+            verack_received: false,
+            wtxid_relay_received: false,
+            sendaddrv2_received: false,
+        }
+    }
+
+    pub fn set_handshake_timeout(&mut self, timeout: Duration) {
+        self.handshake_timeout = timeout;
+    }
+
+    /// Sets the time between pings, counting from now.
+    pub fn set_ping_interval(&mut self, interval: Duration) {
+        self.pings.set_interval(interval);
+    }
+
+    pub fn set_ping_timeout(&mut self, timeout: Duration) {
+        self.pings.set_timeout(timeout);
+    }
+
+    pub fn network(&self) -> Network {
+        self.framed.codec().network()
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    pub fn state(&self) -> &PeerState {
+        &self.state
+    }
+
+    /// The peer's `version`, once it came.
+    pub fn version(&self) -> Option<&VersionMessage> {
+        self.version.as_ref()
+    }
+
+    /// Whether transactions are announced by wtxid, both sides having sent `wtxidrelay`.
+    pub fn wtxid_relay(&self) -> bool {
+        self.wtxid_relay_received
+            && self
+                .version
+                .as_ref()
+                .is_some_and(|version| version.protocol_version >= WTXID_RELAY_VERSION)
+    }
+
+    /// Whether the peer takes `addrv2` instead of `addr`.
+    pub fn addrv2(&self) -> bool {
+        self.sendaddrv2_received
+    }
+
+    pub fn latency(&self) -> &Latency {
+        self.pings.latency()
+    }
+
+    /// Sends our `version` and waits until the connection is established, carrying on
+    /// from where an earlier, cancelled call stopped.
+    ///
+    /// Fails with [`DisconnectReason::HandshakeTimeout`] if that takes longer than the
+    /// handshake timeout, counted from the first call.
+    pub async fn handshake(&mut self) -> Result<(), BitcoinMessageError> {
+        match &self.state {
+            PeerState::Established => return Ok(()),
+            PeerState::Disconnecting(reason) => {
+                return Err(BitcoinMessageError::Disconnected(reason.clone()))
+            }
+            PeerState::Connecting | PeerState::VersionSent | PeerState::VersionReceived => {}
+        }
+        let deadline = *self
+            .handshake_deadline
+            .get_or_insert_with(|| time::Instant::now() + self.handshake_timeout);
+        let established = time::timeout_at(deadline, self.establish())
+            .await
+            .unwrap_or(Err(BitcoinMessageError::Disconnected(
+                DisconnectReason::HandshakeTimeout,
+            )));
+        self.disconnect_on_error(established)
+    }
+
+    /// Sends a message, doing the handshake first if it isn't done yet.
+    pub async fn send(
+        &mut self,
+        command: Command,
+        payload: Payload,
+    ) -> Result<(), BitcoinMessageError> {
+        self.ready().await?;
+        let sent = self.send_message(command, payload).await;
+        self.disconnect_on_error(sent)
+    }
+
+    /// Waits for the next message, doing the handshake first if it isn't done yet. Pings
+    /// are answered and pongs timed on the way; both are returned as well.
+    pub async fn next_message(&mut self) -> Result<BtcMessage, BitcoinMessageError> {
+        self.ready().await?;
+        let message = self.receive().await;
+        self.disconnect_on_error(message)
+    }
+
+    async fn ready(&mut self) -> Result<(), BitcoinMessageError> {
+        match &self.state {
+            PeerState::Established => Ok(()),
+            PeerState::Disconnecting(reason) => {
+                Err(BitcoinMessageError::Disconnected(reason.clone()))
+            }
+            _ => self.handshake().await,
+        }
+    }
+
+    async fn establish(&mut self) -> Result<(), BitcoinMessageError> {
+        if self.state == PeerState::Connecting {
+            let version = VersionMessage::new(self.address);
+            self.queue(Command::Version, Payload::Version(version))?;
+            self.state = PeerState::VersionSent;
+        }
+        // receiving sends what is queued first
+        while self.state != PeerState::Established {
+            self.receive().await?;
+        }
+        Ok(())
+    }
+
+    async fn send_message(
+        &mut self,
+        command: Command,
+        payload: Payload,
+    ) -> Result<(), BitcoinMessageError> {
+        self.queue(command, payload)?;
+        self.flush_outbox().await
+    }
+
+    fn queue(&mut self, command: Command, payload: Payload) -> Result<(), BitcoinMessageError> {
+        let message = BtcMessage::with_payload(self.network(), command, payload)?;
+        self.outbox.push_back(message);
+        Ok(())
+    }
+
+    /// Sends the queued messages. A message leaves the queue only once `framed` has
+    /// taken it, so cancelling this loses nothing.
+    async fn flush_outbox(&mut self) -> Result<(), BitcoinMessageError> {
+        while !self.outbox.is_empty() {
+            poll_fn(|cx| self.framed.poll_ready_unpin(cx)).await?;
+            let message = self.outbox.pop_front().expect("the outbox isn't empty");
+            self.framed.start_send_unpin(message)?;
+        }
+        self.framed.flush().await
+    }
+
+    /// Waits for a message and handles it, sending pings as they come due.
+    async fn receive(&mut self) -> Result<BtcMessage, BitcoinMessageError> {
+        loop {
+            if let Some(nonce) = self.pings.due()? {
+                self.queue(Command::Ping, Payload::Ping(nonce))?;
+            }
+            self.flush_outbox().await?;
+            let deadline = time::Instant::from_std(self.pings.deadline());
+            // reading is cancel safe, whatever came in so far stays buffered
+            let Ok(next) = time::timeout_at(deadline, self.framed.next()).await else {
+                continue;
+            };
+            let message = match next {
+                Some(message) => message?,
+                None => {
+                    return Err(BitcoinMessageError::Disconnected(
+                        DisconnectReason::ConnectionClosed,
+                    ))
+                }
+            };
+            self.handle(&message)?;
+            self.flush_outbox().await?;
+            return Ok(message);
+        }
+    }
+
+    /// Takes `message` into account and queues the replies to it.
+    fn handle(&mut self, message: &BtcMessage) -> Result<(), BitcoinMessageError> {
+        match (Command::from_bytes(&message.command)?, message.payload()) {
+            (_, Payload::Ping(nonce)) => self.queue(Command::Pong, Payload::Pong(*nonce))?,
+            (_, Payload::Pong(nonce)) => self.pings.pong(*nonce),
+            // later ones are ignored, like Bitcoin Core does
+            (Command::Version, Payload::Version(version)) if self.version.is_none() => {
+                self.handle_version(version)?;
+            }
+            (Command::VerAck, _) => self.verack_received = true,
+            (command @ (Command::WtxidRelay | Command::SendAddrV2), _) => {
+                if self.verack_received {
+                    return Err(BitcoinMessageError::Disconnected(
+                        DisconnectReason::NegotiationAfterVerack(command),
+                    ));
+                }
+                if command == Command::WtxidRelay {
+                    self.wtxid_relay_received = true;
+                } else {
+                    self.sendaddrv2_received = true;
+                }
+            }
+            _ => {}
+        }
+        self.state = match (&self.version, self.verack_received) {
+            (None, _) => PeerState::VersionSent,
+            (Some(_), false) => PeerState::VersionReceived,
+            (Some(_), true) => PeerState::Established,
+        };
+        Ok(())
+    }
+
+    fn handle_version(&mut self, version: &VersionMessage) -> Result<(), BitcoinMessageError> {
+        if version.protocol_version < MIN_PEER_PROTO_VERSION {
+            return Err(BitcoinMessageError::Disconnected(
+                DisconnectReason::ProtocolVersionTooOld(version.protocol_version),
+            ));
+        }
+        if version.protocol_version >= WTXID_RELAY_VERSION {
+            self.queue(Command::WtxidRelay, Payload::Empty)?;
+        }
+        self.queue(Command::SendAddrV2, Payload::Empty)?;
+        self.queue(Command::VerAck, Payload::Empty)?;
+        self.version = Some(version.clone());
+        Ok(())
+    }
+
+    /// Moves to [`PeerState::Disconnecting`] if `result` is an error, which becomes a
+    /// [`BitcoinMessageError::Disconnected`] with the reason.
+    fn disconnect_on_error<T>(
+        &mut self,
+        result: Result<T, BitcoinMessageError>,
+    ) -> Result<T, BitcoinMessageError> {
+        result.map_err(|e| {
+            let reason = DisconnectReason::from(e);
+            self.state = PeerState::Disconnecting(reason.clone());
+            BitcoinMessageError::Disconnected(reason)
+        })
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use tokio::io::DuplexStream;
+
+    use super::*;
+
+    const NETWORK: Network = Network::Regtest;
+
+    /// The test's end of a [`mock_peer`], where it plays the remote node.
+    pub(crate) type Theirs = Framed<DuplexStream, MessageCodec>;
+
+    /// A peer whose remote node is played by the test through the returned end.
+    pub(crate) fn mock_peer(network: Network) -> (Peer<DuplexStream>, Theirs) {
+        let (ours, theirs) = tokio::io::duplex(64 * 1024);
+        let mut peer = Peer::new(ours, network, "127.0.0.1:18444".parse().unwrap());
+        peer.set_handshake_timeout(Duration::from_secs(5));
+        (peer, framed(theirs, network))
+    }
+
+    /// Plays the remote node of a handshake, as a node on the latest protocol.
+    pub(crate) async fn accept_handshake(theirs: &mut Theirs) {
+        assert_eq!(receive(theirs).await, Command::Version);
+        send_version(theirs, 70016).await;
+        send(theirs, Command::VerAck, Payload::Empty).await;
+        while receive(theirs).await != Command::VerAck {}
+    }
+
+    async fn send(theirs: &mut Theirs, command: Command, payload: Payload) {
+        let network = theirs.codec().network();
+        let message = BtcMessage::with_payload(network, command, payload).unwrap();
+        theirs.send(message).await.unwrap();
+    }
+
+    async fn send_version(theirs: &mut Theirs, protocol_version: i32) {
+        let mut version = VersionMessage::new("127.0.0.1:18444".parse().unwrap());
+        version.protocol_version = protocol_version;
+        send(theirs, Command::Version, Payload::Version(version)).await;
+    }
+
+    async fn receive(theirs: &mut Theirs) -> Command {
+        let message = theirs.next().await.unwrap().unwrap();
+        Command::from_bytes(&message.command).unwrap()
+    }
+
+    #[tokio::test]
+    async fn handshake() {
+        let (mut peer, mut theirs) = mock_peer(NETWORK);
+        assert_eq!(*peer.state(), PeerState::Connecting);
+        let (result, ()) = tokio::join!(peer.handshake(), async {
+            assert_eq!(receive(&mut theirs).await, Command::Version);
+            send_version(&mut theirs, 70016).await;
+            send(&mut theirs, Command::WtxidRelay, Payload::Empty).await;
+            send(&mut theirs, Command::SendAddrV2, Payload::Empty).await;
+            send(&mut theirs, Command::VerAck, Payload::Empty).await;
+            assert_eq!(receive(&mut theirs).await, Command::WtxidRelay);
+            assert_eq!(receive(&mut theirs).await, Command::SendAddrV2);
+            assert_eq!(receive(&mut theirs).await, Command::VerAck);
+        });
+        result.unwrap();
+
+        assert_eq!(*peer.state(), PeerState::Established);
+        assert_eq!(peer.version().unwrap().protocol_version, 70016);
+        assert!(peer.wtxid_relay());
+        assert!(peer.addrv2());
+    }
+
+    #[tokio::test]
+    async fn verack_before_version() {
+        let (mut peer, mut theirs) = mock_peer(NETWORK);
+        let (result, ()) = tokio::join!(peer.handshake(), async {
+            assert_eq!(receive(&mut theirs).await, Command::Version);
+            send(&mut theirs, Command::VerAck, Payload::Empty).await;
+            send_version(&mut theirs, 70015).await;
+            // too old for wtxidrelay
+            assert_eq!(receive(&mut theirs).await, Command::SendAddrV2);
+            assert_eq!(receive(&mut theirs).await, Command::VerAck);
+        });
+        result.unwrap();
+
+        assert_eq!(*peer.state(), PeerState::Established);
+        assert!(!peer.wtxid_relay());
+        assert!(!peer.addrv2());
+    }
+
+    #[tokio::test]
+    async fn handshake_timeout() {
+        let (mut peer, mut theirs) = mock_peer(NETWORK);
+        peer.set_handshake_timeout(Duration::from_millis(50));
+        // the peer stays connected, but never sends its verack
+        let (result, ()) = tokio::join!(peer.handshake(), async {
+            assert_eq!(receive(&mut theirs).await, Command::Version);
+            send_version(&mut theirs, 70016).await;
+        });
+        let reason = DisconnectReason::HandshakeTimeout;
+        assert!(matches!(
+            result,
+            Err(BitcoinMessageError::Disconnected(r)) if r == reason
+        ));
+        assert_eq!(*peer.state(), PeerState::Disconnecting(reason.clone()));
+        assert!(matches!(
+            peer.next_message().await,
+            Err(BitcoinMessageError::Disconnected(r)) if r == reason
+        ));
+    }
+
+    #[tokio::test]
+    async fn version_too_old() {
+        let (mut peer, mut theirs) = mock_peer(NETWORK);
+        let (result, ()) = tokio::join!(peer.handshake(), async {
+            assert_eq!(receive(&mut theirs).await, Command::Version);
+            send_version(&mut theirs, MIN_PEER_PROTO_VERSION - 1).await;
+        });
+        assert!(matches!(
+            result,
+            Err(BitcoinMessageError::Disconnected(
+                DisconnectReason::ProtocolVersionTooOld(31799)
+            ))
+        ));
+    }
+
+    #[tokio::test]
+    async fn negotiation_after_verack() {
+        let (mut peer, mut theirs) = mock_peer(NETWORK);
+        let (result, ()) = tokio::join!(peer.handshake(), async {
+            accept_handshake(&mut theirs).await;
+            send(&mut theirs, Command::WtxidRelay, Payload::Empty).await;
+        });
+        result.unwrap();
+        assert!(matches!(
+            peer.next_message().await,
+            Err(BitcoinMessageError::Disconnected(
+                DisconnectReason::NegotiationAfterVerack(Command::WtxidRelay)
+            ))
+        ));
+        assert!(!peer.wtxid_relay());
+    }
+
+    #[tokio::test]
+    async fn answers_ping() {
+        let (mut peer, mut theirs) = mock_peer(NETWORK);
+        let (result, ()) = tokio::join!(peer.handshake(), accept_handshake(&mut theirs));
+        result.unwrap();
+
+        send(&mut theirs, Command::Ping, Payload::Ping(42)).await;
+        let message = peer.next_message().await.unwrap();
+        assert!(matches!(message.payload(), Payload::Ping(42)));
+        let pong = theirs.next().await.unwrap().unwrap();
+        assert!(matches!(pong.payload(), Payload::Pong(42)));
+    }
+
+    #[tokio::test]
+    async fn ping_timeout() {
+        let (mut peer, mut theirs) = mock_peer(NETWORK);
+        let (result, ()) = tokio::join!(peer.handshake(), accept_handshake(&mut theirs));
+        result.unwrap();
+
+        // the ping goes out, but no pong comes back
+        peer.set_ping_interval(Duration::ZERO);
+        peer.set_ping_timeout(Duration::from_millis(50));
+        assert!(matches!(
+            peer.next_message().await,
+            Err(BitcoinMessageError::Disconnected(
+                DisconnectReason::PingTimeout
+            ))
+        ));
+        assert_eq!(receive(&mut theirs).await, Command::Ping);
+        assert_eq!(
+            *peer.state(),
+            PeerState::Disconnecting(DisconnectReason::PingTimeout)
+        );
+    }
+
+    #[tokio::test]
+    async fn closed_by_peer() {
+        let (mut peer, theirs) = mock_peer(NETWORK);
+        drop(theirs);
+        assert!(matches!(
+            peer.handshake().await,
+            Err(BitcoinMessageError::Disconnected(
+                DisconnectReason::ConnectionClosed
+            ))
+        ));
+        assert_eq!(
+            *peer.state(),
+            PeerState::Disconnecting(DisconnectReason::ConnectionClosed)
+        );
+    }
+
+    #[tokio::test]
+    async fn closed_after_handshake() {
+        let (mut peer, mut theirs) = mock_peer(NETWORK);
+        let (result, ()) = tokio::join!(peer.handshake(), accept_handshake(&mut theirs));
+        result.unwrap();
+
+        drop(theirs);
+        assert!(matches!(
+            peer.next_message().await,
+            Err(BitcoinMessageError::Disconnected(
+                DisconnectReason::ConnectionClosed
+            ))
+        ));
+    }
+
+    #[tokio::test]
+    async fn undecodable_message() {
+        let (mut peer, mut theirs) = mock_peer(NETWORK);
+        let (result, ()) = tokio::join!(peer.handshake(), accept_handshake(&mut theirs));
+        result.unwrap();
+
+        // a ping without its nonce
+        send(&mut theirs, Command::Ping, Payload::Empty).await;
+        let Err(BitcoinMessageError::Disconnected(reason)) = peer.next_message().await else {
+            panic!("the peer isn't disconnected");
+        };
+        assert!(matches!(reason, DisconnectReason::Protocol(_)));
+        assert_eq!(*peer.state(), PeerState::Disconnecting(reason));
+    }
+
+    #[tokio::test]
+    async fn cancelled_handshake_is_resumed() {
+        let (mut peer, mut theirs) = mock_peer(NETWORK);
+        let (cancelled, ()) = tokio::join!(
+            time::timeout(Duration::from_millis(50), peer.handshake()),
+            async { assert_eq!(receive(&mut theirs).await, Command::Version) }
+        );
+        assert!(cancelled.is_err());
+        assert_eq!(*peer.state(), PeerState::VersionSent);
+
+        // sending finishes the handshake first, without a second version
+        let (result, ()) = tokio::join!(peer.send(Command::GetAddr, Payload::Empty), async {
+            send_version(&mut theirs, 70016).await;
+            send(&mut theirs, Command::VerAck, Payload::Empty).await;
+            assert_eq!(receive(&mut theirs).await, Command::WtxidRelay);
+            assert_eq!(receive(&mut theirs).await, Command::SendAddrV2);
+            assert_eq!(receive(&mut theirs).await, Command::VerAck);
+            assert_eq!(receive(&mut theirs).await, Command::GetAddr);
+        });
+        result.unwrap();
+        assert_eq!(*peer.state(), PeerState::Established);
+    }
+}
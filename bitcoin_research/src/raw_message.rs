@@ -1,4 +1,7 @@
-use crate::errors::BitcoinMessageError;
+use crate::{
+    encoding::{Decodable, Encodable},
+    errors::BitcoinMessageError,
+};
 
 /// Trait defining a data structure that can be serialized to bitcoin protocol "wire" data without any outside input.
 pub trait RawMessage {
@@ -6,7 +9,21 @@ pub trait RawMessage {
     fn to_bytes(&self) -> Result<Vec<u8>, BitcoinMessageError>;
 
     /// Constructs `Self` from binary data.
-    fn from_bytes(data: &Vec<u8>) -> Result<Box<Self>, BitcoinMessageError>
+    fn from_bytes(data: &[u8]) -> Result<Box<Self>, BitcoinMessageError>
     where
         Self: std::marker::Sized;
 }
+
+/// Byte buffer conversions for everything that can be streamed. Bytes after the
+/// decoded value are ignored.
+impl<T: Encodable + Decodable> RawMessage for T {
+    fn to_bytes(&self) -> Result<Vec<u8>, BitcoinMessageError> {
+        let mut buffer = Vec::new();
+        self.encode(&mut buffer)?;
+        Ok(buffer)
+    }
+
+    fn from_bytes(mut data: &[u8]) -> Result<Box<Self>, BitcoinMessageError> {
+        Ok(Box::new(T::decode(&mut data)?))
+    }
+}
@@ -0,0 +1,366 @@
+use std::io::{Read, Write};
+
+use crate::{
+    encoding::{
+        read_length, read_var_array, read_var_bytes, write_var_array, write_var_bytes, CompactSize,
+        Decodable, Encodable,
+    },
+    errors::BitcoinMessageError,
+    payload::MAX_SIZE,
+    utils::sha256d,
+};
+
+/// Takes the place of the input count in the witness serialization (BIP 144).
+const SEGWIT_MARKER: u8 = 0x00;
+/// Flag after the marker telling the inputs have witnesses.
+const SEGWIT_FLAG: u8 = 0x01;
+
+/// An output of a previous transaction.
+///
+/// size | field | type     | description
+/// ---  | ----- | ----     | ------------
+/// 32   | hash  | [u8; 32] | txid of the transaction, in internal byte order
+/// 4    | index | u32      | index of the output in it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct OutPoint {
+    pub txid: [u8; 32],
+    pub vout: u32,
+}
+
+impl OutPoint {
+    /// What the single input of a coinbase transaction spends.
+    pub const NULL: OutPoint = OutPoint {
+        txid: [0; 32],
+        vout: u32::MAX,
+    };
+
+    pub fn is_null(&self) -> bool {
+        *self == Self::NULL
+    }
+}
+
+impl Encodable for OutPoint {
+    fn encode<W: Write>(&self, w: &mut W) -> Result<(), BitcoinMessageError> {
+        self.txid.encode(w)?;
+        self.vout.encode(w)
+    }
+}
+
+impl Decodable for OutPoint {
+    fn decode<R: Read>(r: &mut R) -> Result<Self, BitcoinMessageError> {
+        Ok(OutPoint {
+            txid: <[u8; 32]>::decode(r)?,
+            vout: u32::decode(r)?,
+        })
+    }
+}
+
+/// size | field            | type     | description
+/// ---  | -----            | ----     | ------------
+/// 36   | previous_output  | outpoint | the output spent
+/// ?    | signature script | var_str  | script satisfying the output's conditions
+/// 4    | sequence         | u32      | relative lock time (BIP 68), 0xFFFFFFFF for none
+///
+/// The witness isn't part of this, it comes after all outputs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TxIn {
+    pub previous_output: OutPoint,
+    pub script_sig: Vec<u8>,
+    pub sequence: u32,
+    /// Stack items of the witness, empty for inputs spending non-SegWit outputs.
+    pub witness: Vec<Vec<u8>>,
+}
+
+impl Encodable for TxIn {
+    fn encode<W: Write>(&self, w: &mut W) -> Result<(), BitcoinMessageError> {
+        self.previous_output.encode(w)?;
+        write_var_bytes(w, &self.script_sig)?;
+        self.sequence.encode(w)
+    }
+}
+
+impl Decodable for TxIn {
+    fn decode<R: Read>(r: &mut R) -> Result<Self, BitcoinMessageError> {
+        Ok(TxIn {
+            previous_output: OutPoint::decode(r)?,
+            script_sig: read_var_bytes(r, MAX_SIZE)?,
+            sequence: u32::decode(r)?,
+            witness: Vec::new(),
+        })
+    }
+}
+
+/// size | field         | type    | description
+/// ---  | -----         | ----    | ------------
+/// 8    | value         | i64     | amount in satoshis
+/// ?    | pk_script     | var_str | conditions to spend the output
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TxOut {
+    pub value: i64,
+    pub script_pubkey: Vec<u8>,
+}
+
+impl Encodable for TxOut {
+    fn encode<W: Write>(&self, w: &mut W) -> Result<(), BitcoinMessageError> {
+        self.value.encode(w)?;
+        write_var_bytes(w, &self.script_pubkey)
+    }
+}
+
+impl Decodable for TxOut {
+    fn decode<R: Read>(r: &mut R) -> Result<Self, BitcoinMessageError> {
+        Ok(TxOut {
+            value: i64::decode(r)?,
+            script_pubkey: read_var_bytes(r, MAX_SIZE)?,
+        })
+    }
+}
+
+/// https://en.bitcoin.it/wiki/Protocol_documentation#tx
+///
+/// size | field        | type       | description
+/// ---  | -----        | ----       | ------------
+/// 4    | version      | i32        | transaction format version
+/// 0/2  | flag         | [u8; 2]    | 0x00 0x01 if there are witnesses (BIP 144)
+/// ?    | tx_in        | [TxIn]     | inputs
+/// ?    | tx_out       | [TxOut]    | outputs
+/// ?    | tx_witnesses | [[[u8]]]   | witness of each input, only with the flag
+/// 4    | lock_time    | u32        | block height or time the transaction is valid from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transaction {
+    pub version: i32,
+    pub inputs: Vec<TxIn>,
+    pub outputs: Vec<TxOut>,
+    pub lock_time: u32,
+}
+
+impl Transaction {
+    /// Whether any input has a witness, in which case it's serialized with them.
+    pub fn has_witness(&self) -> bool {
+        self.inputs.iter().any(|input| !input.witness.is_empty())
+    }
+
+    pub fn is_coinbase(&self) -> bool {
+        self.inputs.len() == 1 && self.inputs[0].previous_output.is_null()
+    }
+
+    /// Hash of the transaction without witnesses, how it's referred to by inputs.
+    pub fn txid(&self) -> [u8; 32] {
+        let mut data = Vec::new();
+        // writing to a Vec doesn't fail
+        self.encode_without_witness(&mut data).unwrap();
+        sha256d(&data)
+    }
+
+    /// Hash of the transaction with witnesses (BIP 141), the txid if there are none.
+    pub fn wtxid(&self) -> [u8; 32] {
+        let mut data = Vec::new();
+        self.encode(&mut data).unwrap();
+        sha256d(&data)
+    }
+
+    /// Writes the serialization from before SegWit, the one old nodes understand.
+    pub fn encode_without_witness<W: Write>(&self, w: &mut W) -> Result<(), BitcoinMessageError> {
+        self.version.encode(w)?;
+        write_var_array(w, &self.inputs)?;
+        write_var_array(w, &self.outputs)?;
+        self.lock_time.encode(w)
+    }
+}
+
+impl Encodable for Transaction {
+    fn encode<W: Write>(&self, w: &mut W) -> Result<(), BitcoinMessageError> {
+        if !self.has_witness() {
+            return self.encode_without_witness(w);
+        }
+        self.version.encode(w)?;
+        SEGWIT_MARKER.encode(w)?;
+        SEGWIT_FLAG.encode(w)?;
+        write_var_array(w, &self.inputs)?;
+        write_var_array(w, &self.outputs)?;
+        for input in &self.inputs {
+            CompactSize(input.witness.len() as u64).encode(w)?;
+            for item in &input.witness {
+                write_var_bytes(w, item)?;
+            }
+        }
+        self.lock_time.encode(w)
+    }
+}
+
+impl Decodable for Transaction {
+    /// Reads either serialization, the way Bitcoin Core's `UnserializeTransaction` does.
+    fn decode<R: Read>(r: &mut R) -> Result<Self, BitcoinMessageError> {
+        let version = i32::decode(r)?;
+        let mut inputs: Vec<TxIn> = read_var_array(r, MAX_SIZE)?;
+        let mut flags = 0;
+        let mut outputs = Vec::new();
+        if inputs.is_empty() {
+            // no inputs is the marker, unless it's a transaction without inputs
+            flags = u8::decode(r)?;
+            if flags != 0 {
+                inputs = read_var_array(r, MAX_SIZE)?;
+                outputs = read_var_array(r, MAX_SIZE)?;
+            }
+        } else {
+            outputs = read_var_array(r, MAX_SIZE)?;
+        }
+        if flags & SEGWIT_FLAG != 0 {
+            flags ^= SEGWIT_FLAG;
+            for input in &mut inputs {
+                let items = read_length(r, MAX_SIZE)?;
+                for _ in 0..items {
+                    input.witness.push(read_var_bytes(r, MAX_SIZE)?);
+                }
+            }
+            // it would have been serialized without the flag
+            if inputs.iter().all(|input| input.witness.is_empty()) {
+                return Err(BitcoinMessageError::WitnessEmpty);
+            }
+        }
+        if flags != 0 {
+            return Err(BitcoinMessageError::TxFlagsUnknown(flags));
+        }
+        Ok(Transaction {
+            version,
+            inputs,
+            outputs,
+            lock_time: u32::decode(r)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        raw_message::RawMessage,
+        utils::{hash_to_hex, hex},
+    };
+
+    /// Coinbase of the genesis block, whose txid is its merkle root.
+    const GENESIS_COINBASE: &str = "01000000010000000000000000000000000000000000000000000000000000\
+        000000000000ffffffff4d04ffff001d0104455468652054696d65732030332f4a616e2f3230303920\
+        4368616e63656c6c6f72206f6e206272696e6b206f66207365636f6e64206261696c6f757420666f72\
+        2062616e6b73ffffffff0100f2052a01000000434104678afdb0fe5548271967f1a67130b7105cd6a8\
+        28e03909a67962e0ea1f61deb649f6bc3f4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c702b\
+        6bf11d5fac00000000";
+
+    /// Mainnet transaction f4184fc5...1e9e16 in block 170, the first one between two
+    /// people: 10 BTC from Satoshi Nakamoto to Hal Finney.
+    const BLOCK_170_TX: &str = "0100000001c997a5e56e104102fa209c6a852dd90660a20b2d9c352423edce25\
+        857fcd3704000000004847304402204e45e16932b8af514961a1d3a1a25fdf3f4f7732e9d624c6c615\
+        48ab5fb8cd410220181522ec8eca07de4860a4acdd12909d831cc56cbbac4622082221a8768d1d0901\
+        ffffffff0200ca9a3b00000000434104ae1a62fe09c5f51b13905f07f06b99a2f7159b2225f374cd37\
+        8d71302fa28414e7aab37397f554a7df5f142c21c1b7303b8a0626f1baded5c72a704f7e6cd84cac00\
+        286bee0000000043410411db93e1dcdb8a016b49840f8c53bc1eb68a382e97b1482ecad7b148a6909a\
+        5cb2e0eaddfb84ccf9744464f82e160bfa9b8b64f9d4c03f999b8643f656b412a3ac00000000";
+
+    /// Signed native P2WPKH example of BIP 143: a P2PK input and a P2WPKH one.
+    const BIP143_P2WPKH: &str = "01000000000102fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf4\
+        33541db4e4ad969f00000000494830450221008b9d1dc26ba6a9cb62127b02742fa9d754cd3bebf337\
+        f7a55d114c8e5cdd30be022040529b194ba3f9281a99f2b1c0a19c0489bc22ede944ccf4ecbab4cc61\
+        8ef3ed01eeffffffef51e1b804cc89d182d279655c3aa89e815b1b309fe287d9b2b55d57b90ec68a01\
+        00000000ffffffff02202cb206000000001976a9148280b37df378db99f66f85c95a783a76ac7a6d59\
+        88ac9093510d000000001976a9143bde42dbee7e4dbe6a21b2d50ce2f0167faa815988ac0002473044\
+        02203609e17b84f6a7d30c80bfa610b5b4542f32a8a0d5447a12fb1366d7f01cc44a0220573a954c45\
+        18331561406f90300e8f3358f51928d43c212a8caed02de67eebee0121025476c2e83188368da1ff3e\
+        292e7acafcdb3566bb0ad253f62fc70f07aeee635711000000";
+
+    #[test]
+    fn genesis_coinbase() {
+        let data = hex(GENESIS_COINBASE);
+        let tx = Transaction::from_bytes(&data).unwrap();
+        assert!(tx.is_coinbase());
+        assert!(!tx.has_witness());
+        assert_eq!(tx.inputs[0].script_sig.len(), 77);
+        assert_eq!(tx.outputs.len(), 1);
+        assert_eq!(tx.outputs[0].value, 50 * 100_000_000);
+        assert_eq!(
+            hash_to_hex(&tx.txid()),
+            "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b"
+        );
+        assert_eq!(tx.wtxid(), tx.txid());
+        assert_eq!(tx.to_bytes().unwrap(), data);
+    }
+
+    #[test]
+    fn legacy_transaction() {
+        let data = hex(BLOCK_170_TX);
+        let tx = Transaction::from_bytes(&data).unwrap();
+        assert!(!tx.is_coinbase());
+        assert_eq!(tx.version, 1);
+        assert_eq!(tx.inputs.len(), 1);
+        assert_eq!(
+            hash_to_hex(&tx.inputs[0].previous_output.txid),
+            "0437cd7f8525ceed2324359c2d0ba26006d92d856a9c20fa0241106ee5a597c9"
+        );
+        assert_eq!(tx.inputs[0].previous_output.vout, 0);
+        assert_eq!(tx.inputs[0].sequence, u32::MAX);
+        let values: Vec<i64> = tx.outputs.iter().map(|output| output.value).collect();
+        assert_eq!(values, [10 * 100_000_000, 40 * 100_000_000]);
+        assert_eq!(
+            hash_to_hex(&tx.txid()),
+            "f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16"
+        );
+        assert_eq!(tx.to_bytes().unwrap(), data);
+    }
+
+    #[test]
+    fn segwit_transaction() {
+        let data = hex(BIP143_P2WPKH);
+        let tx = Transaction::from_bytes(&data).unwrap();
+        assert!(tx.has_witness());
+        assert_eq!(tx.inputs.len(), 2);
+        assert!(tx.inputs[0].witness.is_empty());
+        assert_eq!(tx.inputs[1].witness.len(), 2);
+        assert_eq!(tx.inputs[1].witness[0].len(), 71);
+        assert_eq!(tx.inputs[1].witness[1].len(), 33);
+        assert!(tx.inputs[1].script_sig.is_empty());
+        assert_eq!(tx.outputs[0].value, 112_340_000);
+        assert_eq!(tx.outputs[1].value, 223_450_000);
+        assert_eq!(tx.lock_time, 17);
+        assert_eq!(tx.to_bytes().unwrap(), data);
+
+        // the txid covers all but the marker, the flag and the witnesses
+        let witness_start = data.len() - 4 - (1 + 1 + 72 + 34);
+        let mut legacy = data[..4].to_vec();
+        legacy.extend_from_slice(&data[6..witness_start]);
+        legacy.extend_from_slice(&data[data.len() - 4..]);
+        let mut without_witness = Vec::new();
+        tx.encode_without_witness(&mut without_witness).unwrap();
+        assert_eq!(without_witness, legacy);
+        assert_eq!(tx.txid(), sha256d(&legacy));
+        assert_eq!(tx.wtxid(), sha256d(&data));
+        assert_ne!(tx.txid(), tx.wtxid());
+    }
+
+    #[test]
+    fn witness_flag_without_witness() {
+        let mut tx = *Transaction::from_bytes(&hex(BIP143_P2WPKH)).unwrap();
+        tx.inputs[1].witness.clear();
+        let mut data = Vec::new();
+        tx.encode_without_witness(&mut data).unwrap();
+        // flag the legacy serialization and add empty witnesses
+        data.splice(4..4, [SEGWIT_MARKER, SEGWIT_FLAG]);
+        data.splice(data.len() - 4..data.len() - 4, [0, 0]);
+        assert!(matches!(
+            Transaction::from_bytes(&data),
+            Err(BitcoinMessageError::WitnessEmpty)
+        ));
+
+        data[5] = 0x02;
+        assert!(matches!(
+            Transaction::from_bytes(&data),
+            Err(BitcoinMessageError::TxFlagsUnknown(0x02))
+        ));
+    }
+
+    #[test]
+    fn truncated() {
+        let data = hex(BIP143_P2WPKH);
+        for len in [0, 5, 100, data.len() - 40, data.len() - 1] {
+            assert!(Transaction::from_bytes(&data[..len]).is_err(), "{}", len);
+        }
+    }
+}
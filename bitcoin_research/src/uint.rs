@@ -0,0 +1,303 @@
+//! 256 bit unsigned integers, for targets and hashes compared against them.
+
+use std::cmp::Ordering;
+use std::ops::{Add, Div, Mul, Not, Shl, Shr, Sub};
+
+/// A 256 bit unsigned integer, as Bitcoin Core's `arith_uint256`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct U256([u64; 4]);
+
+impl U256 {
+    pub const ZERO: U256 = U256([0; 4]);
+    pub const MAX: U256 = U256([u64::MAX; 4]);
+
+    /// Reads a hash, whose internal byte order is little endian.
+    pub fn from_le_bytes(bytes: [u8; 32]) -> Self {
+        let mut limbs = [0u64; 4];
+        for (limb, chunk) in limbs.iter_mut().zip(bytes.chunks_exact(8)) {
+            *limb = u64::from_le_bytes(chunk.try_into().unwrap());
+        }
+        U256(limbs)
+    }
+
+    pub fn to_le_bytes(&self) -> [u8; 32] {
+        let mut bytes = [0u8; 32];
+        for (chunk, limb) in bytes.chunks_exact_mut(8).zip(self.0) {
+            chunk.copy_from_slice(&limb.to_le_bytes());
+        }
+        bytes
+    }
+
+    pub fn is_zero(&self) -> bool {
+        *self == Self::ZERO
+    }
+
+    /// The lowest 64 bits.
+    pub fn low_u64(&self) -> u64 {
+        self.0[0]
+    }
+
+    /// Number of bits needed to represent the value.
+    pub fn bits(&self) -> u32 {
+        match self.0.iter().rposition(|limb| *limb != 0) {
+            Some(i) => 64 * i as u32 + 64 - self.0[i].leading_zeros(),
+            None => 0,
+        }
+    }
+
+    /// Expands the compact `bits` form of a target: one byte of size in bytes, then a
+    /// three byte mantissa whose top bit is the sign.
+    ///
+    /// Returns `None` for negative targets and targets that don't fit 256 bits, which
+    /// no valid block can have.
+    pub fn from_compact(compact: u32) -> Option<Self> {
+        let size = compact >> 24;
+        let mut word = compact & 0x007f_ffff;
+        if size <= 3 {
+            word >>= 8 * (3 - size);
+        }
+        // the sign and the overflow only count once the size cut off the low bytes
+        let negative = word != 0 && compact & 0x0080_0000 != 0;
+        let overflow =
+            word != 0 && (size > 34 || (word > 0xff && size > 33) || (word > 0xffff && size > 32));
+        if negative || overflow {
+            return None;
+        }
+        Some(if size <= 3 {
+            U256::from(word as u64)
+        } else {
+            U256::from(word as u64) << (8 * (size - 3))
+        })
+    }
+
+    /// The compact form of the value, rounded down to its top three bytes.
+    pub fn to_compact(&self) -> u32 {
+        let mut size = self.bits().div_ceil(8);
+        let mut compact = if size <= 3 {
+            (self.low_u64() << (8 * (3 - size))) as u32
+        } else {
+            (*self >> (8 * (size - 3))).low_u64() as u32
+        };
+        // the top bit of the mantissa is the sign, move it to the next byte
+        if compact & 0x0080_0000 != 0 {
+            compact >>= 8;
+            size += 1;
+        }
+        compact | size << 24
+    }
+}
+
+impl From<u64> for U256 {
+    fn from(n: u64) -> Self {
+        U256([n, 0, 0, 0])
+    }
+}
+
+impl Ord for U256 {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.iter().rev().cmp(other.0.iter().rev())
+    }
+}
+
+impl PartialOrd for U256 {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Add for U256 {
+    type Output = U256;
+
+    /// Wraps around on overflow, as `arith_uint256` does.
+    fn add(self, other: U256) -> U256 {
+        let mut result = U256::ZERO;
+        let mut carry = false;
+        for i in 0..4 {
+            let (sum, c1) = self.0[i].overflowing_add(other.0[i]);
+            let (sum, c2) = sum.overflowing_add(carry as u64);
+            result.0[i] = sum;
+            carry = c1 || c2;
+        }
+        result
+    }
+}
+
+impl Sub for U256 {
+    type Output = U256;
+
+    /// Wraps around on underflow, as `arith_uint256` does.
+    fn sub(self, other: U256) -> U256 {
+        self + !other + U256::from(1)
+    }
+}
+
+impl Not for U256 {
+    type Output = U256;
+
+    fn not(self) -> U256 {
+        U256(self.0.map(|limb| !limb))
+    }
+}
+
+impl Mul<u64> for U256 {
+    type Output = U256;
+
+    /// Wraps around on overflow, as `arith_uint256` does.
+    fn mul(self, other: u64) -> U256 {
+        let mut result = U256::ZERO;
+        let mut carry = 0u128;
+        for i in 0..4 {
+            let product = self.0[i] as u128 * other as u128 + carry;
+            result.0[i] = product as u64;
+            carry = product >> 64;
+        }
+        result
+    }
+}
+
+impl Div for U256 {
+    type Output = U256;
+
+    /// Long division, one bit at a time.
+    ///
+    /// # Panics
+    ///
+    /// If `divisor` is zero.
+    fn div(self, divisor: U256) -> U256 {
+        assert!(!divisor.is_zero(), "division by zero");
+        let mut quotient = U256::ZERO;
+        let mut remainder = self;
+        let divisor_bits = divisor.bits();
+        while remainder >= divisor {
+            let mut shift = remainder.bits() - divisor_bits;
+            if divisor << shift > remainder {
+                shift -= 1;
+            }
+            remainder = remainder - (divisor << shift);
+            quotient.0[(shift / 64) as usize] |= 1 << (shift % 64);
+        }
+        quotient
+    }
+}
+
+impl Shl<u32> for U256 {
+    type Output = U256;
+
+    fn shl(self, shift: u32) -> U256 {
+        let mut result = U256::ZERO;
+        let (limbs, bits) = ((shift / 64) as usize, shift % 64);
+        for i in limbs..4 {
+            result.0[i] = self.0[i - limbs] << bits;
+            if bits > 0 && i > limbs {
+                result.0[i] |= self.0[i - limbs - 1] >> (64 - bits);
+            }
+        }
+        result
+    }
+}
+
+impl Shr<u32> for U256 {
+    type Output = U256;
+
+    fn shr(self, shift: u32) -> U256 {
+        let mut result = U256::ZERO;
+        let (limbs, bits) = ((shift / 64) as usize, shift % 64);
+        for i in 0..4usize.saturating_sub(limbs) {
+            result.0[i] = self.0[i + limbs] >> bits;
+            if bits > 0 && i + limbs < 3 {
+                result.0[i] |= self.0[i + limbs + 1] << (64 - bits);
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compact_round_trip() {
+        // from Bitcoin Core's arith_uint256_tests.cpp
+        let cases: [(u32, u64, u32); 13] = [
+            (0x00000000, 0, 0),
+            (0x00123456, 0, 0),
+            (0x01003456, 0, 0),
+            (0x02000056, 0, 0),
+            (0x03000000, 0, 0),
+            (0x04000000, 0, 0),
+            (0x00923456, 0, 0),
+            (0x01803456, 0, 0),
+            (0x04800000, 0, 0),
+            (0x01123456, 0x12, 0x01120000),
+            (0x02123456, 0x1234, 0x02123400),
+            (0x03123456, 0x123456, 0x03123456),
+            (0x04123456, 0x12345600, 0x04123456),
+        ];
+        for (compact, value, normalized) in cases {
+            let target = U256::from_compact(compact).unwrap();
+            assert_eq!(target, U256::from(value), "{:#010x}", compact);
+            assert_eq!(target.to_compact(), normalized, "{:#010x}", compact);
+        }
+        // 0x92340000 needs a leading zero byte to stay positive
+        assert_eq!(U256::from(0x9234_0000).to_compact(), 0x05009234);
+        assert_eq!(U256::from(0x80).to_compact(), 0x02008000);
+
+        let big = U256::from_compact(0x20123456).unwrap();
+        assert_eq!(big, U256::from(0x123456) << (8 * 29));
+        assert_eq!(big.to_compact(), 0x20123456);
+    }
+
+    #[test]
+    fn compact_invalid() {
+        assert_eq!(U256::from_compact(0x01fedcba), None);
+        assert_eq!(U256::from_compact(0x04923456), None);
+        assert_eq!(U256::from_compact(0xff123456), None);
+        assert_eq!(U256::from_compact(0x22000100), None);
+        assert_eq!(U256::from_compact(0x22000001), Some(U256::from(1) << 248));
+    }
+
+    #[test]
+    fn shifts_and_order() {
+        let one = U256::from(1);
+        assert_eq!((one << 255) >> 255, one);
+        assert_eq!((one << 200).bits(), 201);
+        assert_eq!(one << 256, U256::ZERO);
+        assert_eq!(U256::MAX >> 192, U256::from(u64::MAX));
+        assert_eq!((U256::from(0xabcd) << 60) >> 60, U256::from(0xabcd));
+        assert!(one << 64 > U256::from(u64::MAX));
+        assert!(U256::MAX > one << 255);
+        assert_eq!(U256::ZERO.bits(), 0);
+    }
+
+    #[test]
+    fn arithmetic() {
+        let one = U256::from(1);
+        assert_eq!(U256::from(u64::MAX) + one, one << 64);
+        assert_eq!(U256::MAX + one, U256::ZERO);
+        assert_eq!((one << 64) - one, U256::from(u64::MAX));
+        assert_eq!(U256::ZERO - one, U256::MAX);
+        assert_eq!(!U256::ZERO, U256::MAX);
+        assert_eq!(
+            U256::from(u64::MAX) * u64::MAX,
+            (one << 128) - (one << 65) + one
+        );
+        assert_eq!(U256::from(1000) / U256::from(7), U256::from(142));
+        assert_eq!(U256::MAX / U256::MAX, one);
+        assert_eq!((one << 200) / (one << 100), one << 100);
+        assert_eq!(U256::from(6) / U256::from(7), U256::ZERO);
+        let n = (U256::from(0x1234_5678) << 150) + U256::from(99);
+        assert_eq!(n / U256::from(0x1234_5678), one << 150);
+    }
+
+    #[test]
+    fn byte_order() {
+        let mut bytes = [0u8; 32];
+        bytes[0] = 1;
+        bytes[31] = 0x80;
+        let n = U256::from_le_bytes(bytes);
+        assert_eq!(n >> 255, U256::from(1));
+        assert_eq!(n.low_u64(), 1);
+        assert_eq!(n.to_le_bytes(), bytes);
+    }
+}
@@ -5,49 +5,51 @@ pub const VERSION_COMMAND: [u8; 12] = *b"version\0\0\0\0\0";
 pub const VER_ACK_COMMAND: [u8; 12] = *b"verack\0\0\0\0\0\0";
 
 /// Computes Bitcoin checksum for given data
-pub fn checksum(data: Vec<u8>) -> [u8; 4] {
-    let h1 = Sha256::new().chain_update(data).finalize();
-    let h2 = Sha256::new().chain_update(h1).finalize();
-
+pub fn checksum(data: &[u8]) -> [u8; 4] {
     let mut buf = [0u8; CHECKSUM_SIZE];
-    buf.clone_from_slice(&h2[..CHECKSUM_SIZE]);
+    buf.clone_from_slice(&sha256d(data)[..CHECKSUM_SIZE]);
     buf
 }
 
-// Generic parser using the FromBytes trait from the num (num_traits) crate
-// could be a trait and maybe better to put a constrait on the type
-pub fn parse_frombytes_be<T>(buff: &Vec<u8>) -> Result<(T, Vec<u8>), std::io::Error>
-where
-    T: FromEndian + Sized,
-{
-    let size = core::mem::size_of::<T>();
-    match read_drop_slice(buff, size) {
-        Ok((res, remaining)) => Ok((FromEndian::from_be(res), remaining)),
-        Err(e) => Err(e),
-    }
+/// sha256(sha256(data)), the hash of block headers and transactions.
+pub fn sha256d(data: &[u8]) -> [u8; 32] {
+    let h1 = Sha256::new().chain_update(data).finalize();
+    Sha256::new().chain_update(h1).finalize().into()
 }
 
-pub fn parse_frombytes_le<T>(buff: &Vec<u8>) -> Result<(T, Vec<u8>), std::io::Error>
-where
-    T: FromEndian + Sized,
-{
-    let size = core::mem::size_of::<T>();
-    match read_drop_slice(buff, size) {
-        Ok((res, remaining)) => Ok((FromEndian::from_le(res), remaining)),
-        Err(e) => Err(e),
+/// Parses a hash as displayed (big endian hex) into internal byte order.
+pub const fn hash_from_hex(hex: &str) -> [u8; 32] {
+    const fn nibble(c: u8) -> u8 {
+        match c {
+            b'0'..=b'9' => c - b'0',
+            b'a'..=b'f' => c - b'a' + 10,
+            _ => panic!("not a lowercase hex digit"),
+        }
     }
-}
-// if correct, return the parsed value and the new vector without it , similar to Parsec
-// pub fn read_drop_slice<'a>(buff: &Vec<u8>, size: usize) -> Result<(&'a [u8], Vec<u8>), std::io::Error> {
-pub fn read_drop_slice(buff: &Vec<u8>, size: usize) -> Result<(&[u8], Vec<u8>), std::io::Error> {
-    if buff.len() >= size {
-        Ok((&buff[0..size], buff[size..].to_vec()))
-    } else {
-        Err(std::io::Error::new(
-            std::io::ErrorKind::UnexpectedEof,
-            "Buffer too small",
-        ))
+    let hex = hex.as_bytes();
+    assert!(hex.len() == 64);
+    let mut hash = [0u8; 32];
+    let mut i = 0;
+    while i < 32 {
+        hash[31 - i] = nibble(hex[2 * i]) << 4 | nibble(hex[2 * i + 1]);
+        i += 1;
     }
+    hash
+}
+
+/// Formats a hash in internal byte order the way it's displayed.
+pub fn hash_to_hex(hash: &[u8; 32]) -> String {
+    hash.iter().rev().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Parses hex, ignoring whitespace, for test fixtures.
+#[cfg(test)]
+pub(crate) fn hex(s: &str) -> Vec<u8> {
+    let s: String = s.split_whitespace().collect();
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+        .collect()
 }
 
 // An attempt of a generic BigEndian/LittleEndian parser for numeric types
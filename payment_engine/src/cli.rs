@@ -0,0 +1,347 @@
+use std::error::Error;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::processor::{
+    credit_limit::CreditLimits, events::EventSink, rules::RuleEngine, tx_processor::RunOptions,
+    validation::ValidationMode,
+};
+
+/// What the binary was asked to do.
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    /// Process a transactions file.
+    Process(Args),
+    /// Compare a produced accounts file against an expected one.
+    Reconcile(ReconcileArgs),
+}
+
+impl Command {
+    pub fn from_env() -> Result<Self, Box<dyn Error>> {
+        Self::parse(std::env::args_os().skip(1))
+    }
+
+    pub fn parse<I>(args: I) -> Result<Self, Box<dyn Error>>
+    where
+        I: IntoIterator<Item = OsString>,
+    {
+        let mut args = args.into_iter().peekable();
+        if args.peek().and_then(|arg| arg.to_str()) == Some("reconcile") {
+            args.next();
+            Ok(Command::Reconcile(ReconcileArgs::parse(args)?))
+        } else {
+            Ok(Command::Process(Args::parse(args)?))
+        }
+    }
+}
+
+/// Arguments of `reconcile <actual.csv> <expected.csv> [--tolerance <decimal>]`.
+#[derive(Debug, Default, PartialEq)]
+pub struct ReconcileArgs {
+    pub actual: PathBuf,
+    pub expected: PathBuf,
+    /// Largest difference between two balances that still counts as equal.
+    pub tolerance: f64,
+}
+
+impl ReconcileArgs {
+    pub fn parse<I>(args: I) -> Result<Self, Box<dyn Error>>
+    where
+        I: IntoIterator<Item = OsString>,
+    {
+        let mut files: Vec<PathBuf> = Vec::new();
+        let mut tolerance = 0.0;
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.to_str() {
+                Some("--tolerance") => {
+                    let value = next_path(&mut args, arg)?;
+                    tolerance = value
+                        .to_str()
+                        .and_then(|v| v.parse::<f64>().ok())
+                        .filter(|v| v.is_finite() && *v >= 0.0)
+                        .ok_or_else(|| format!("invalid tolerance {:?}", value))?;
+                }
+                _ if files.len() < 2 => files.push(PathBuf::from(arg)),
+                _ => return Err(From::from(format!("unexpected argument {:?}", arg))),
+            }
+        }
+        match <[PathBuf; 2]>::try_from(files) {
+            Ok([actual, expected]) => Ok(ReconcileArgs {
+                actual,
+                expected,
+                tolerance,
+            }),
+            Err(_) => Err(From::from(
+                "expected the produced and the expected accounts files",
+            )),
+        }
+    }
+}
+
+/// Command line arguments:
+/// `<input.csv> [--output <file>] [--trial-balance <file>] [--credit-limits <file>]
+/// [--rules <file>] [--rejections <file>] [--events <file>] [--mode strict|lenient]
+/// [--invalid-rows <file>] [--pipeline] [--log-dir <dir>] [--metrics <file>]`, where
+/// `--events -` writes events to stdout, which needs `--output`, and `--metrics -` prints a
+/// summary to stderr instead of Prometheus metrics to a file.
+#[derive(Debug, Default, PartialEq)]
+pub struct Args {
+    pub input: PathBuf,
+    /// Where the accounts CSV goes instead of stdout.
+    pub output: Option<PathBuf>,
+    pub trial_balance: Option<PathBuf>,
+    pub credit_limits: Option<PathBuf>,
+    pub rules: Option<PathBuf>,
+    pub rejections: Option<PathBuf>,
+    pub events: Option<PathBuf>,
+    pub mode: ValidationMode,
+    pub invalid_rows: Option<PathBuf>,
+    /// Parse and validate on separate threads while the storage is being written.
+    pub pipeline: bool,
+    /// Keep state in an append-only log in this directory instead of SQLite.
+    pub log_dir: Option<PathBuf>,
+    pub metrics: Option<PathBuf>,
+}
+
+impl Args {
+    pub fn parse<I>(args: I) -> Result<Self, Box<dyn Error>>
+    where
+        I: IntoIterator<Item = OsString>,
+    {
+        let mut input: Option<PathBuf> = None;
+        let mut output: Option<PathBuf> = None;
+        let mut trial_balance: Option<PathBuf> = None;
+        let mut credit_limits: Option<PathBuf> = None;
+        let mut rules: Option<PathBuf> = None;
+        let mut rejections: Option<PathBuf> = None;
+        let mut events: Option<PathBuf> = None;
+        let mut mode = ValidationMode::default();
+        let mut invalid_rows: Option<PathBuf> = None;
+        let mut pipeline = false;
+        let mut log_dir: Option<PathBuf> = None;
+        let mut metrics: Option<PathBuf> = None;
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.to_str() {
+                Some("--output") => output = Some(next_path(&mut args, arg)?),
+                Some("--trial-balance") => trial_balance = Some(next_path(&mut args, arg)?),
+                Some("--credit-limits") => credit_limits = Some(next_path(&mut args, arg)?),
+                Some("--rules") => rules = Some(next_path(&mut args, arg)?),
+                Some("--rejections") => rejections = Some(next_path(&mut args, arg)?),
+                Some("--events") => events = Some(next_path(&mut args, arg)?),
+                Some("--mode") => {
+                    let value = next_path(&mut args, arg)?;
+                    mode = value
+                        .to_str()
+                        .and_then(|v| ValidationMode::from_str(v).ok())
+                        .ok_or_else(|| format!("invalid mode {:?}", value))?;
+                }
+                Some("--invalid-rows") => invalid_rows = Some(next_path(&mut args, arg)?),
+                Some("--pipeline") => pipeline = true,
+                Some("--log-dir") => log_dir = Some(next_path(&mut args, arg)?),
+                Some("--metrics") => metrics = Some(next_path(&mut args, arg)?),
+                _ if input.is_none() => input = Some(PathBuf::from(arg)),
+                _ => return Err(From::from(format!("unexpected argument {:?}", arg))),
+            }
+        }
+        if events.as_deref() == Some(Path::new("-")) && output.is_none() {
+            return Err(From::from(
+                "--events - writes to stdout, which needs --output for the accounts",
+            ));
+        }
+        match input {
+            None => Err(From::from("expected a file name, but got none")),
+            Some(input) => Ok(Args {
+                input,
+                output,
+                trial_balance,
+                credit_limits,
+                rules,
+                rejections,
+                events,
+                mode,
+                invalid_rows,
+                pipeline,
+                log_dir,
+                metrics,
+            }),
+        }
+    }
+
+    /// Loads the files the options point to.
+    pub fn run_options(&self) -> Result<RunOptions, Box<dyn Error>> {
+        let mut options = RunOptions {
+            validation: self.mode,
+            ..Default::default()
+        };
+        if let Some(path) = &self.credit_limits {
+            options.credit_limits = CreditLimits::from_path(path)?;
+        }
+        if let Some(path) = &self.rules {
+            options.rules = RuleEngine::from_path(path)?;
+        }
+        if let Some(path) = &self.events {
+            options.events = Some(if path.as_os_str() == "-" {
+                EventSink::to_stdout()
+            } else {
+                EventSink::to_path(path)?
+            });
+        }
+        Ok(options)
+    }
+}
+
+fn next_path(
+    args: &mut impl Iterator<Item = OsString>,
+    flag: OsString,
+) -> Result<PathBuf, Box<dyn Error>> {
+    args.next()
+        .map(PathBuf::from)
+        .ok_or_else(|| From::from(format!("expected a value after {:?}", flag)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ffi::OsString, path::PathBuf};
+
+    use super::{Args, Command, ReconcileArgs};
+    use crate::processor::validation::ValidationMode;
+
+    #[test]
+    fn test_parse_input_only() {
+        let args = Args::parse(to_args(&["test.csv"])).unwrap();
+        assert_eq!(PathBuf::from("test.csv"), args.input);
+        assert_eq!(None, args.trial_balance);
+    }
+
+    #[test]
+    fn test_parse_trial_balance() {
+        let args = Args::parse(to_args(&["--trial-balance", "tb.csv", "test.csv"])).unwrap();
+        assert_eq!(PathBuf::from("test.csv"), args.input);
+        assert_eq!(Some(PathBuf::from("tb.csv")), args.trial_balance);
+    }
+
+    #[test]
+    fn test_parse_credit_limits() {
+        let args = Args::parse(to_args(&["test.csv", "--credit-limits", "limits.csv"])).unwrap();
+        assert_eq!(Some(PathBuf::from("limits.csv")), args.credit_limits);
+    }
+
+    #[test]
+    fn test_parse_rules() {
+        let args = Args::parse(to_args(&[
+            "test.csv",
+            "--rules",
+            "rules.csv",
+            "--rejections",
+            "rejections.csv",
+        ]))
+        .unwrap();
+        assert_eq!(Some(PathBuf::from("rules.csv")), args.rules);
+        assert_eq!(Some(PathBuf::from("rejections.csv")), args.rejections);
+    }
+
+    #[test]
+    fn test_parse_events() {
+        let args = Args::parse(to_args(&["test.csv", "--events", "events.jsonl"])).unwrap();
+        assert_eq!(Some(PathBuf::from("events.jsonl")), args.events);
+        let args = Args::parse(to_args(&[
+            "test.csv",
+            "--events",
+            "-",
+            "--output",
+            "accounts.csv",
+        ]))
+        .unwrap();
+        assert_eq!(Some(PathBuf::from("-")), args.events);
+        assert_eq!(Some(PathBuf::from("accounts.csv")), args.output);
+        // stdout can't take both
+        assert!(Args::parse(to_args(&["test.csv", "--events", "-"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_mode() {
+        let args = Args::parse(to_args(&["test.csv"])).unwrap();
+        assert_eq!(ValidationMode::Strict, args.mode);
+        let args = Args::parse(to_args(&[
+            "test.csv",
+            "--mode",
+            "lenient",
+            "--invalid-rows",
+            "invalid.csv",
+        ]))
+        .unwrap();
+        assert_eq!(ValidationMode::Lenient, args.mode);
+        assert_eq!(Some(PathBuf::from("invalid.csv")), args.invalid_rows);
+        assert!(Args::parse(to_args(&["test.csv", "--mode", "relaxed"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_pipeline() {
+        assert!(!Args::parse(to_args(&["test.csv"])).unwrap().pipeline);
+        assert!(
+            Args::parse(to_args(&["--pipeline", "test.csv"]))
+                .unwrap()
+                .pipeline
+        );
+    }
+
+    #[test]
+    fn test_parse_log_dir() {
+        let args = Args::parse(to_args(&["test.csv", "--log-dir", "state"])).unwrap();
+        assert_eq!(Some(PathBuf::from("state")), args.log_dir);
+    }
+
+    #[test]
+    fn test_parse_metrics() {
+        let args = Args::parse(to_args(&["test.csv", "--metrics", "metrics.prom"])).unwrap();
+        assert_eq!(Some(PathBuf::from("metrics.prom")), args.metrics);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(Args::parse(to_args(&[])).is_err());
+        assert!(Args::parse(to_args(&["test.csv", "--trial-balance"])).is_err());
+        assert!(Args::parse(to_args(&["a.csv", "b.csv"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(
+            Command::Process(Args {
+                input: PathBuf::from("test.csv"),
+                ..Default::default()
+            }),
+            Command::parse(to_args(&["test.csv"])).unwrap()
+        );
+        assert_eq!(
+            Command::Reconcile(ReconcileArgs {
+                actual: PathBuf::from("a.csv"),
+                expected: PathBuf::from("b.csv"),
+                tolerance: 0.0001,
+            }),
+            Command::parse(to_args(&[
+                "reconcile",
+                "a.csv",
+                "--tolerance",
+                "0.0001",
+                "b.csv"
+            ]))
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_parse_reconcile_errors() {
+        assert!(ReconcileArgs::parse(to_args(&["a.csv"])).is_err());
+        assert!(ReconcileArgs::parse(to_args(&["a.csv", "b.csv", "c.csv"])).is_err());
+        assert!(ReconcileArgs::parse(to_args(&["a.csv", "b.csv", "--tolerance", "x"])).is_err());
+        assert!(ReconcileArgs::parse(to_args(&["a.csv", "b.csv", "--tolerance", "-1"])).is_err());
+    }
+
+    fn to_args(args: &[&str]) -> Vec<OsString> {
+        args.iter().map(OsString::from).collect()
+    }
+}
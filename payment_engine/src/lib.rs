@@ -1,2 +1,4 @@
+pub mod cli;
 pub mod processor;
+pub mod reconcile;
 pub mod storage;
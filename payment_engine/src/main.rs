@@ -1,19 +1,113 @@
-use std::{io, process};
+use std::{error::Error, fs::File, io, path::Path, process};
 
-use payment_engine::processor;
-use processor::{tx_processor::run_with_db, utils::get_file_reader};
+use payment_engine::{
+    cli::{Args, Command, ReconcileArgs},
+    processor,
+    processor::metrics::Metrics,
+    reconcile::{read_accounts, reconcile},
+    storage::{db_storage::DbStorage, log_storage::LogStorage, record_storage::RecordStorage},
+};
+use processor::{
+    pipeline::run_pipelined,
+    report::{write_invalid_rows, write_rejections, RunAborted},
+    tx_processor::{run_with_options, RunOptions, RunReport},
+    utils::{create_pool, get_file_reader},
+};
 
 fn main() {
-    let result = get_file_reader();
-    let wtr = csv::Writer::from_writer(io::stdout());
-    if let Err(err) = result {
-        println!("{}", err);
-        process::exit(1);
-    } else {
-        let reader = result.ok().unwrap();
-        if let Err(err) = run_with_db(reader, wtr) {
+    match try_main() {
+        Ok(true) => {}
+        Ok(false) => process::exit(1),
+        Err(err) => {
             println!("{}", err);
             process::exit(1);
         }
     }
 }
+
+/// Returns whether the command succeeded.
+fn try_main() -> Result<bool, Box<dyn Error>> {
+    match Command::from_env()? {
+        Command::Process(args) => process_transactions(args).map(|_| true),
+        Command::Reconcile(args) => reconcile_accounts(args),
+    }
+}
+
+fn process_transactions(args: Args) -> Result<(), Box<dyn Error>> {
+    let reader = get_file_reader(&args.input)?;
+    let options = args.run_options()?;
+    let wtr: csv::Writer<Box<dyn io::Write>> = csv::Writer::from_writer(match &args.output {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(io::stdout()),
+    });
+    let report = match &args.log_dir {
+        Some(dir) => run_on(reader, wtr, LogStorage::open(dir)?, options, args.pipeline),
+        None => run_on(
+            reader,
+            wtr,
+            DbStorage::new(create_pool()?),
+            options,
+            args.pipeline,
+        ),
+    };
+    let report = match report {
+        Ok(report) => report,
+        Err(err) => {
+            // a strict run stopped by an invalid row still gets its metrics
+            if let (Some(aborted), Some(path)) = (err.downcast_ref::<RunAborted>(), &args.metrics) {
+                write_metrics(&aborted.metrics, path)?;
+            }
+            return Err(err);
+        }
+    };
+    if let Some(path) = args.trial_balance {
+        report
+            .journal
+            .write_trial_balance(csv::Writer::from_path(path)?)?;
+    }
+    if let Some(path) = args.rejections {
+        write_rejections(&report.rejections, csv::Writer::from_path(path)?)?;
+    }
+    if let Some(path) = args.invalid_rows {
+        write_invalid_rows(&report.invalid_rows, csv::Writer::from_path(path)?)?;
+    }
+    if let Some(path) = args.metrics {
+        write_metrics(&report.metrics, &path)?;
+    }
+    Ok(())
+}
+
+/// Writes the summary to stderr for `-`, otherwise the Prometheus format to `path`.
+fn write_metrics(metrics: &Metrics, path: &Path) -> io::Result<()> {
+    if path.as_os_str() == "-" {
+        metrics.write_summary(io::stderr())
+    } else {
+        metrics.write_prometheus_to_path(path)
+    }
+}
+
+/// Processes `reader` against `storage` and writes the client records to `wtr`.
+fn run_on(
+    reader: csv::Reader<File>,
+    wtr: csv::Writer<Box<dyn io::Write>>,
+    storage: impl RecordStorage,
+    options: RunOptions,
+    pipeline: bool,
+) -> Result<RunReport, Box<dyn Error>> {
+    if pipeline {
+        tokio::runtime::Runtime::new()?.block_on(run_pipelined(reader, wtr, storage, options))
+    } else {
+        run_with_options(reader, wtr, storage, options)
+    }
+}
+
+/// Prints every difference between the two accounts files and returns whether they match.
+fn reconcile_accounts(args: ReconcileArgs) -> Result<bool, Box<dyn Error>> {
+    let actual = read_accounts(&args.actual)?;
+    let expected = read_accounts(&args.expected)?;
+    let differences = reconcile(&actual, &expected, args.tolerance);
+    for difference in differences.iter() {
+        println!("{}", difference);
+    }
+    Ok(differences.is_empty())
+}
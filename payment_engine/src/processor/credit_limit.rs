@@ -0,0 +1,92 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::{error::Error, io};
+
+use csv::{Reader, Trim};
+use serde::Deserialize;
+
+use super::record::Record;
+
+#[derive(Debug, Deserialize)]
+struct CreditLimitRow {
+    client: u16,
+    limit: f64,
+}
+
+/// Approved overdraft limits per client, loaded from a `client, limit` CSV.
+/// Clients that aren't listed have no overdraft.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct CreditLimits {
+    limits: HashMap<u16, f64>,
+}
+
+impl CreditLimits {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let rdr = csv::ReaderBuilder::new().trim(Trim::All).from_path(path)?;
+        Self::from_reader(rdr)
+    }
+
+    pub fn from_reader<R: io::Read>(mut rdr: Reader<R>) -> Result<Self, Box<dyn Error>> {
+        let mut limits = Self::new();
+        for row in rdr.deserialize() {
+            let row: CreditLimitRow = row?;
+            limits.set(row.client, row.limit)?;
+        }
+        Ok(limits)
+    }
+
+    pub fn set(&mut self, client_id: u16, limit: f64) -> Result<(), Box<dyn Error>> {
+        if !limit.is_finite() || limit < 0.0 {
+            return Err(From::from(format!(
+                "invalid credit limit {} for client {}",
+                limit, client_id
+            )));
+        }
+        self.limits.insert(client_id, limit);
+        Ok(())
+    }
+
+    pub fn limit_for(&self, client_id: u16) -> f64 {
+        self.limits.get(&client_id).copied().unwrap_or_default()
+    }
+
+    /// Returns the record with its client's configured limit.
+    pub fn apply(&self, rec: Record) -> Record {
+        Record {
+            credit_limit: self.limit_for(rec.client),
+            ..rec
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use csv::Trim;
+
+    use super::CreditLimits;
+    use crate::processor::record::Record;
+
+    #[test]
+    fn test_from_reader() {
+        let data = "client, limit\n1, 100.0\n3, 0.5\n";
+        let rdr = csv::ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(data.as_bytes());
+        let limits = CreditLimits::from_reader(rdr).unwrap();
+
+        assert_eq!(100.0, limits.limit_for(1));
+        assert_eq!(0.0, limits.limit_for(2));
+        assert_eq!(0.5, limits.apply(Record::new(3)).credit_limit);
+    }
+
+    #[test]
+    fn test_rejects_negative_limit() {
+        let mut limits = CreditLimits::new();
+        assert!(limits.set(1, -1.0).is_err());
+        assert!(limits.set(1, f64::NAN).is_err());
+    }
+}
@@ -0,0 +1,144 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::{error::Error, fmt};
+
+use serde::Serialize;
+
+use super::record::Record;
+use super::transaction::{Transaction, TransactionType};
+
+/// Balances and lock state of a client account at one point in time.
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+pub struct AccountState {
+    pub available: f64,
+    pub held: f64,
+    pub total: f64,
+    pub locked: bool,
+}
+
+impl From<&Record> for AccountState {
+    fn from(rec: &Record) -> Self {
+        AccountState {
+            available: rec.available,
+            held: rec.held,
+            total: rec.total,
+            locked: rec.is_locked(),
+        }
+    }
+}
+
+/// Emitted for every client record changed by an applied transaction.
+/// Transfers change two records and so emit two events, while disputing or
+/// resolving a transfer only changes the destination's record.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct BalanceEvent {
+    pub client: u16,
+    pub tx: u32,
+    #[serde(rename = "type")]
+    pub tx_type: TransactionType,
+    pub before: AccountState,
+    pub after: AccountState,
+}
+
+impl BalanceEvent {
+    pub fn new(txn: &Transaction, before: &Record, after: &Record) -> Self {
+        BalanceEvent {
+            client: after.client,
+            tx: txn.tx,
+            tx_type: txn.tx_type,
+            before: AccountState::from(before),
+            after: AccountState::from(after),
+        }
+    }
+}
+
+/// Writes [`BalanceEvent`]s as JSON lines.
+pub struct EventSink {
+    wtr: Box<dyn Write>,
+}
+
+impl EventSink {
+    pub fn new(wtr: impl Write + 'static) -> Self {
+        EventSink { wtr: Box::new(wtr) }
+    }
+
+    /// Events go to stdout, for runs that write the accounts CSV to a file.
+    pub fn to_stdout() -> Self {
+        Self::new(BufWriter::new(io::stdout()))
+    }
+
+    pub fn to_path<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        Ok(Self::new(BufWriter::new(File::create(path)?)))
+    }
+
+    pub fn emit(&mut self, event: &BalanceEvent) -> Result<(), Box<dyn Error>> {
+        serde_json::to_writer(&mut self.wtr, event)?;
+        self.wtr.write_all(b"\n")?;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), Box<dyn Error>> {
+        self.wtr.flush()?;
+        Ok(())
+    }
+}
+
+impl fmt::Debug for EventSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EventSink").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, io::Write, rc::Rc};
+
+    use super::{BalanceEvent, EventSink};
+    use crate::processor::{
+        record::Record,
+        transaction::{DisputeStatus, Transaction, TransactionType},
+    };
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_emit_json_lines() {
+        let buffer = SharedBuffer::default();
+        let mut sink = EventSink::new(buffer.clone());
+        let txn = Transaction {
+            tx_type: TransactionType::Deposit,
+            client: 1,
+            tx: 7,
+            amount: Some(1.5),
+            destination: None,
+            dispute_status: DisputeStatus::None,
+        };
+        let before = Record::new(1);
+        let mut after = Record::new(1);
+        after.available = 1.5;
+        after.total = 1.5;
+
+        sink.emit(&BalanceEvent::new(&txn, &before, &after))
+            .unwrap();
+        sink.emit(&BalanceEvent::new(&txn, &after, &after)).unwrap();
+
+        let output = String::from_utf8(buffer.0.borrow().clone()).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(2, lines.len());
+        assert_eq!(
+            r#"{"client":1,"tx":7,"type":"deposit","before":{"available":0.0,"held":0.0,"total":0.0,"locked":false},"after":{"available":1.5,"held":0.0,"total":1.5,"locked":false}}"#,
+            lines[0]
+        );
+    }
+}
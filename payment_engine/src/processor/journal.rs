@@ -0,0 +1,400 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Display;
+use std::{error::Error, io};
+
+use csv::Writer;
+use serde::Serialize;
+
+use super::record::Record;
+use super::transaction::{Transaction, TransactionType};
+
+// sums below this are treated as zero when checking the ledger
+const BALANCE_TOLERANCE: f64 = 1e-9;
+
+/// Ledger accounts the engine posts to.
+///
+/// Amounts are signed: a positive posting increases the account balance and
+/// a negative one decreases it, so a balanced entry always sums to zero.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Account {
+    /// Funds the client can withdraw.
+    ClientAvailable(u16),
+    /// Funds frozen by an open dispute.
+    ClientHeld(u16),
+    /// Counterparty for money entering or leaving the system.
+    ExternalSettlement,
+    /// Funds lost to chargebacks.
+    ChargebackLoss,
+}
+
+impl Display for Account {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Account::ClientAvailable(client) => write!(f, "client:{}:available", client),
+            Account::ClientHeld(client) => write!(f, "client:{}:held", client),
+            Account::ExternalSettlement => write!(f, "external:settlement"),
+            Account::ChargebackLoss => write!(f, "loss:chargeback"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Posting {
+    pub account: Account,
+    pub amount: f64,
+}
+
+/// A set of postings produced by a single transaction.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JournalEntry {
+    pub tx: u32,
+    pub client: u16,
+    pub tx_type: TransactionType,
+    pub postings: Vec<Posting>,
+    /// Client whose account is frozen by applying the entry.
+    pub locked_client: Option<u16>,
+}
+
+impl JournalEntry {
+    fn new(txn: &Transaction, postings: Vec<Posting>) -> Self {
+        JournalEntry {
+            tx: txn.tx,
+            client: txn.client,
+            tx_type: txn.tx_type,
+            postings,
+            locked_client: None,
+        }
+    }
+
+    /// Moves `amount` from `from` to `to`.
+    fn between(txn: &Transaction, from: Account, to: Account, amount: f64) -> Self {
+        Self::new(
+            txn,
+            vec![
+                Posting {
+                    account: from,
+                    amount: -amount,
+                },
+                Posting {
+                    account: to,
+                    amount,
+                },
+            ],
+        )
+    }
+
+    pub fn deposit(txn: &Transaction, amount: f64) -> Self {
+        Self::between(
+            txn,
+            Account::ExternalSettlement,
+            Account::ClientAvailable(txn.client),
+            amount,
+        )
+    }
+
+    pub fn withdrawal(txn: &Transaction, amount: f64) -> Self {
+        Self::between(
+            txn,
+            Account::ClientAvailable(txn.client),
+            Account::ExternalSettlement,
+            amount,
+        )
+    }
+
+    pub fn dispute(txn: &Transaction, amount: f64) -> Self {
+        Self::between(
+            txn,
+            Account::ClientAvailable(txn.client),
+            Account::ClientHeld(txn.client),
+            amount,
+        )
+    }
+
+    pub fn resolve(txn: &Transaction, amount: f64) -> Self {
+        Self::between(
+            txn,
+            Account::ClientHeld(txn.client),
+            Account::ClientAvailable(txn.client),
+            amount,
+        )
+    }
+
+    pub fn chargeback(txn: &Transaction, amount: f64) -> Self {
+        let mut entry = Self::between(
+            txn,
+            Account::ClientHeld(txn.client),
+            Account::ChargebackLoss,
+            amount,
+        );
+        entry.locked_client = Some(txn.client);
+        entry
+    }
+
+    pub fn transfer(txn: &Transaction, destination: u16, amount: f64) -> Self {
+        Self::between(
+            txn,
+            Account::ClientAvailable(txn.client),
+            Account::ClientAvailable(destination),
+            amount,
+        )
+    }
+
+    /// Freezes the transferred funds on the receiving side.
+    pub fn transfer_dispute(txn: &Transaction, destination: u16, amount: f64) -> Self {
+        Self::between(
+            txn,
+            Account::ClientAvailable(destination),
+            Account::ClientHeld(destination),
+            amount,
+        )
+    }
+
+    pub fn transfer_resolve(txn: &Transaction, destination: u16, amount: f64) -> Self {
+        Self::between(
+            txn,
+            Account::ClientHeld(destination),
+            Account::ClientAvailable(destination),
+            amount,
+        )
+    }
+
+    /// Returns the held funds to the sender and freezes the receiving account.
+    pub fn transfer_chargeback(txn: &Transaction, destination: u16, amount: f64) -> Self {
+        let mut entry = Self::between(
+            txn,
+            Account::ClientHeld(destination),
+            Account::ClientAvailable(txn.client),
+            amount,
+        );
+        entry.locked_client = Some(destination);
+        entry
+    }
+
+    /// Sum of all postings; zero for a balanced entry.
+    pub fn sum(&self) -> f64 {
+        self.postings.iter().map(|p| p.amount).sum()
+    }
+
+    pub fn is_balanced(&self) -> bool {
+        !self.postings.is_empty() && self.sum().abs() < BALANCE_TOLERANCE
+    }
+
+    /// Net change this entry makes to the given account.
+    pub fn delta(&self, account: &Account) -> f64 {
+        self.postings
+            .iter()
+            .filter(|p| p.account == *account)
+            .map(|p| p.amount)
+            .sum()
+    }
+}
+
+/// One row of the trial balance export.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct TrialBalanceLine {
+    pub account: String,
+    pub debit: f64,
+    pub credit: f64,
+}
+
+/// Append-only list of balanced journal entries together with the running
+/// balance of every account they touched.
+#[derive(Debug, Default)]
+pub struct Journal {
+    entries: Vec<JournalEntry>,
+    balances: BTreeMap<Account, f64>,
+}
+
+impl Journal {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A journal with `entries` posted in order.
+    pub fn from_entries(entries: Vec<JournalEntry>) -> Result<Self, Box<dyn Error>> {
+        let mut journal = Self::new();
+        for entry in entries {
+            journal.post(entry)?;
+        }
+        Ok(journal)
+    }
+
+    /// Posts an entry, refusing it if its postings don't sum to zero.
+    pub fn post(&mut self, entry: JournalEntry) -> Result<(), Box<dyn Error>> {
+        if !entry.is_balanced() {
+            return Err(From::from(format!(
+                "unbalanced journal entry for tx {}: postings sum to {}",
+                entry.tx,
+                entry.sum()
+            )));
+        }
+        for posting in entry.postings.iter() {
+            *self.balances.entry(posting.account).or_insert(0.0) += posting.amount;
+        }
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    pub fn entries(&self) -> &[JournalEntry] {
+        &self.entries
+    }
+
+    pub fn balance(&self, account: &Account) -> f64 {
+        self.balances.get(account).copied().unwrap_or_default()
+    }
+
+    /// Clients with an account in the ledger.
+    pub fn clients(&self) -> BTreeSet<u16> {
+        self.balances
+            .keys()
+            .filter_map(|account| match account {
+                Account::ClientAvailable(client) | Account::ClientHeld(client) => Some(*client),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Verifies the ledger as a whole nets to zero and every record holds the balances of
+    /// its client's accounts.
+    pub fn check_invariants(&self, records: &[Record]) -> Result<(), Box<dyn Error>> {
+        // rounding grows with the size of the balances, so scale the tolerance with them
+        let total: f64 = self.balances.values().sum();
+        let scale = self.balances.values().fold(1.0_f64, |m, b| m.max(b.abs()));
+        if total.abs() >= BALANCE_TOLERANCE * scale {
+            return Err(From::from(format!(
+                "ledger does not balance: accounts sum to {}",
+                total
+            )));
+        }
+        for record in records {
+            let accounts = [
+                (Account::ClientAvailable(record.client), record.available),
+                (Account::ClientHeld(record.client), record.held),
+            ];
+            for (account, recorded) in accounts {
+                let balance = self.balance(&account);
+                if (balance - recorded).abs() >= BALANCE_TOLERANCE * scale {
+                    return Err(From::from(format!(
+                        "{} is {} in the journal, but {} in the client record",
+                        account, balance, recorded
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Per-account balances split into debit (positive) and credit (negative) columns.
+    pub fn trial_balance(&self) -> Vec<TrialBalanceLine> {
+        self.balances
+            .iter()
+            .map(|(account, balance)| {
+                // adding 0.0 turns a negative zero into a positive one
+                let (debit, credit) = if *balance > 0.0 {
+                    (*balance, 0.0)
+                } else {
+                    (0.0, -balance + 0.0)
+                };
+                TrialBalanceLine {
+                    account: account.to_string(),
+                    debit,
+                    credit,
+                }
+            })
+            .collect()
+    }
+
+    pub fn write_trial_balance<W>(&self, mut wtr: Writer<W>) -> Result<(), Box<dyn Error>>
+    where
+        W: io::Write,
+    {
+        for line in self.trial_balance() {
+            wtr.serialize(line)?;
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Account, Journal, JournalEntry, Posting};
+    use crate::processor::record::Record;
+    use crate::processor::transaction::{DisputeStatus, Transaction, TransactionType};
+
+    #[test]
+    fn test_entries_are_balanced() {
+        let txn = make_txn(TransactionType::Deposit);
+        let entries = [
+            JournalEntry::deposit(&txn, 1.5),
+            JournalEntry::withdrawal(&txn, 1.5),
+            JournalEntry::dispute(&txn, 1.5),
+            JournalEntry::resolve(&txn, 1.5),
+            JournalEntry::chargeback(&txn, 1.5),
+            JournalEntry::transfer(&txn, 2, 1.5),
+            JournalEntry::transfer_dispute(&txn, 2, 1.5),
+            JournalEntry::transfer_resolve(&txn, 2, 1.5),
+            JournalEntry::transfer_chargeback(&txn, 2, 1.5),
+        ];
+        entries
+            .iter()
+            .for_each(|entry| assert!(entry.is_balanced()));
+    }
+
+    #[test]
+    fn test_post_rejects_unbalanced_entry() {
+        let txn = make_txn(TransactionType::Deposit);
+        let mut entry = JournalEntry::deposit(&txn, 1.5);
+        entry.postings.push(Posting {
+            account: Account::ChargebackLoss,
+            amount: 0.5,
+        });
+
+        let mut journal = Journal::new();
+        assert!(journal.post(entry).is_err());
+        assert!(journal.entries().is_empty());
+    }
+
+    #[test]
+    fn test_balances_and_trial_balance() {
+        let txn = make_txn(TransactionType::Deposit);
+        let mut journal = Journal::new();
+        journal.post(JournalEntry::deposit(&txn, 3.0)).unwrap();
+        journal.post(JournalEntry::dispute(&txn, 1.0)).unwrap();
+        journal.post(JournalEntry::chargeback(&txn, 1.0)).unwrap();
+
+        assert_eq!(2.0, journal.balance(&Account::ClientAvailable(1)));
+        assert_eq!(0.0, journal.balance(&Account::ClientHeld(1)));
+        assert_eq!(-3.0, journal.balance(&Account::ExternalSettlement));
+        assert_eq!(1.0, journal.balance(&Account::ChargebackLoss));
+        let record = Record {
+            available: 2.0,
+            total: 2.0,
+            ..Record::new(1)
+        };
+        assert!(journal.check_invariants(&[record]).is_ok());
+        let stale = Record {
+            available: 3.0,
+            total: 3.0,
+            ..Record::new(1)
+        };
+        assert!(journal.check_invariants(&[stale]).is_err());
+
+        let trial_balance = journal.trial_balance();
+        let debits: f64 = trial_balance.iter().map(|l| l.debit).sum();
+        let credits: f64 = trial_balance.iter().map(|l| l.credit).sum();
+        assert_eq!(debits, credits);
+        assert_eq!("client:1:available", trial_balance[0].account);
+    }
+
+    fn make_txn(tx_type: TransactionType) -> Transaction {
+        Transaction {
+            tx_type,
+            client: 1,
+            tx: 1,
+            amount: Some(1.0),
+            destination: None,
+            dispute_status: DisputeStatus::None,
+        }
+    }
+}
@@ -0,0 +1,390 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::time::Duration;
+
+/// Reason counted for rows skipped by lenient validation.
+pub const INVALID_ROW: &str = "invalid_row";
+/// Reason counted for transactions processed without effect, e.g. for lack of funds.
+pub const DECLINED: &str = "declined";
+/// Reason counted for transactions whose id is already taken, e.g. by an earlier run.
+pub const DUPLICATE: &str = "duplicate";
+
+const QUANTILES: [f64; 3] = [0.5, 0.9, 0.99];
+
+/// Upper bounds of the latency buckets, from 1µs to 10s in 1-2.5-5 steps. Slower calls
+/// land in a last, unbounded bucket.
+pub const BUCKET_BOUNDS: [Duration; 22] = {
+    let mut bounds = [Duration::ZERO; 22];
+    let mut i = 0;
+    let mut decade = 1_000;
+    while i < bounds.len() {
+        let steps = [decade, decade * 5 / 2, decade * 5];
+        bounds[i] = Duration::from_nanos(steps[i % 3]);
+        if i % 3 == 2 {
+            decade *= 10;
+        }
+        i += 1;
+    }
+    bounds
+};
+
+/// Durations of one kind of storage call, counted in fixed buckets so memory stays
+/// the same however long the run.
+#[derive(Debug, Default, Clone)]
+pub struct Latencies {
+    /// Calls per bucket of [`BUCKET_BOUNDS`], plus the unbounded one.
+    buckets: [u64; BUCKET_BOUNDS.len() + 1],
+    sum: Duration,
+    max: Duration,
+}
+
+impl Latencies {
+    pub fn record(&mut self, duration: Duration) {
+        let bucket = BUCKET_BOUNDS.partition_point(|bound| *bound < duration);
+        self.buckets[bucket] += 1;
+        self.sum += duration;
+        self.max = self.max.max(duration);
+    }
+
+    /// Runs `f` and records how long it took.
+    pub fn time<T>(&mut self, f: impl FnOnce() -> T) -> T {
+        let started = std::time::Instant::now();
+        let result = f();
+        self.record(started.elapsed());
+        result
+    }
+
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().sum()
+    }
+
+    pub fn sum(&self) -> Duration {
+        self.sum
+    }
+
+    /// Nearest-rank percentiles, e.g. `0.99` for p99, rounded up to the bound of the
+    /// bucket they fall in and capped at the slowest call. Zero without samples.
+    pub fn percentiles(&self, quantiles: &[f64]) -> Vec<Duration> {
+        let count = self.count();
+        quantiles
+            .iter()
+            .map(|q| {
+                let rank = ((q * count as f64).ceil() as u64).clamp(1, count.max(1));
+                let mut seen = 0;
+                let bucket = self.buckets.iter().position(|n| {
+                    seen += n;
+                    seen >= rank
+                });
+                match bucket {
+                    Some(bucket) if count > 0 => BUCKET_BOUNDS
+                        .get(bucket)
+                        .map_or(self.max, |bound| (*bound).min(self.max)),
+                    _ => Duration::ZERO,
+                }
+            })
+            .collect()
+    }
+
+    /// Calls per bucket bound, counting every call at or below it, then the total.
+    fn cumulative_buckets(&self) -> impl Iterator<Item = (Option<Duration>, u64)> + '_ {
+        let bounds = BUCKET_BOUNDS.iter().copied().map(Some).chain([None]);
+        bounds.zip(self.buckets.iter().scan(0, |seen, n| {
+            *seen += n;
+            Some(*seen)
+        }))
+    }
+}
+
+/// Counters and timings collected during a run.
+#[derive(Debug, Default, Clone)]
+pub struct Metrics {
+    pub rows_read: u64,
+    /// Applied transactions by type.
+    pub applied: BTreeMap<String, u64>,
+    /// Transactions that weren't applied, by rule name, [`INVALID_ROW`] or [`DECLINED`].
+    pub rejected: BTreeMap<String, u64>,
+    pub storage_reads: Latencies,
+    pub storage_writes: Latencies,
+    /// Time spent parsing and validating rows.
+    pub validation_time: Duration,
+    /// Time spent applying transactions, storage included.
+    pub processing_time: Duration,
+    /// Time spent writing the client records.
+    pub output_time: Duration,
+    pub elapsed: Duration,
+}
+
+impl Metrics {
+    pub fn count_applied(&mut self, tx_type: impl ToString) {
+        *self
+            .applied
+            .entry(tx_type.to_string().to_lowercase())
+            .or_insert(0) += 1;
+    }
+
+    pub fn count_rejected(&mut self, reason: &str) {
+        *self.rejected.entry(reason.to_string()).or_insert(0) += 1;
+    }
+
+    /// Rows read per second over the whole run.
+    pub fn throughput(&self) -> f64 {
+        match self.elapsed.as_secs_f64() {
+            secs if secs > 0.0 => self.rows_read as f64 / secs,
+            _ => 0.0,
+        }
+    }
+
+    /// Writes a human readable summary.
+    pub fn write_summary<W: Write>(&self, mut wtr: W) -> io::Result<()> {
+        writeln!(wtr, "rows read: {}", self.rows_read)?;
+        for (tx_type, count) in self.applied.iter() {
+            writeln!(wtr, "applied {}: {}", tx_type, count)?;
+        }
+        for (reason, count) in self.rejected.iter() {
+            writeln!(wtr, "rejected {}: {}", reason, count)?;
+        }
+        for (op, latencies) in self.storage_latencies() {
+            let [p50, p90, p99] =
+                <[Duration; 3]>::try_from(latencies.percentiles(&QUANTILES)).unwrap_or_default();
+            writeln!(
+                wtr,
+                "storage {}s: {} (p50 {:?}, p90 {:?}, p99 {:?})",
+                op,
+                latencies.count(),
+                p50,
+                p90,
+                p99
+            )?;
+        }
+        for (stage, duration) in self.stage_durations() {
+            writeln!(wtr, "{} time: {:?}", stage, duration)?;
+        }
+        writeln!(
+            wtr,
+            "elapsed: {:?} ({:.0} rows/s)",
+            self.elapsed,
+            self.throughput()
+        )?;
+        wtr.flush()
+    }
+
+    /// Writes the metrics in the Prometheus text exposition format.
+    pub fn write_prometheus<W: Write>(&self, mut wtr: W) -> io::Result<()> {
+        write_header(&mut wtr, "rows_read_total", "counter", "Input rows read.")?;
+        writeln!(wtr, "payment_engine_rows_read_total {}", self.rows_read)?;
+
+        write_header(
+            &mut wtr,
+            "transactions_applied_total",
+            "counter",
+            "Applied transactions by type.",
+        )?;
+        for (tx_type, count) in self.applied.iter() {
+            writeln!(
+                wtr,
+                "payment_engine_transactions_applied_total{{type=\"{}\"}} {}",
+                tx_type, count
+            )?;
+        }
+
+        write_header(
+            &mut wtr,
+            "transactions_rejected_total",
+            "counter",
+            "Transactions not applied, by reason.",
+        )?;
+        for (reason, count) in self.rejected.iter() {
+            writeln!(
+                wtr,
+                "payment_engine_transactions_rejected_total{{reason=\"{}\"}} {}",
+                reason, count
+            )?;
+        }
+
+        write_header(
+            &mut wtr,
+            "storage_latency_seconds",
+            "histogram",
+            "Duration of storage calls.",
+        )?;
+        for (op, latencies) in self.storage_latencies() {
+            for (bound, count) in latencies.cumulative_buckets() {
+                let le = bound.map_or("+Inf".to_string(), |bound| bound.as_secs_f64().to_string());
+                writeln!(
+                    wtr,
+                    "payment_engine_storage_latency_seconds_bucket{{op=\"{}\",le=\"{}\"}} {}",
+                    op, le, count
+                )?;
+            }
+            writeln!(
+                wtr,
+                "payment_engine_storage_latency_seconds_sum{{op=\"{}\"}} {}",
+                op,
+                latencies.sum().as_secs_f64()
+            )?;
+            writeln!(
+                wtr,
+                "payment_engine_storage_latency_seconds_count{{op=\"{}\"}} {}",
+                op,
+                latencies.count()
+            )?;
+        }
+
+        write_header(
+            &mut wtr,
+            "stage_duration_seconds",
+            "gauge",
+            "Time spent in each stage of the run.",
+        )?;
+        for (stage, duration) in self.stage_durations() {
+            writeln!(
+                wtr,
+                "payment_engine_stage_duration_seconds{{stage=\"{}\"}} {}",
+                stage,
+                duration.as_secs_f64()
+            )?;
+        }
+
+        write_header(
+            &mut wtr,
+            "run_duration_seconds",
+            "gauge",
+            "Duration of the run.",
+        )?;
+        writeln!(
+            wtr,
+            "payment_engine_run_duration_seconds {}",
+            self.elapsed.as_secs_f64()
+        )?;
+        write_header(
+            &mut wtr,
+            "throughput_rows_per_second",
+            "gauge",
+            "Rows read per second.",
+        )?;
+        writeln!(
+            wtr,
+            "payment_engine_throughput_rows_per_second {}",
+            self.throughput()
+        )?;
+        wtr.flush()
+    }
+
+    pub fn write_prometheus_to_path<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        self.write_prometheus(BufWriter::new(File::create(path)?))
+    }
+
+    fn storage_latencies(&self) -> [(&'static str, &Latencies); 2] {
+        [
+            ("read", &self.storage_reads),
+            ("write", &self.storage_writes),
+        ]
+    }
+
+    fn stage_durations(&self) -> [(&'static str, Duration); 3] {
+        [
+            ("validation", self.validation_time),
+            ("processing", self.processing_time),
+            ("output", self.output_time),
+        ]
+    }
+}
+
+fn write_header<W: Write>(wtr: &mut W, name: &str, kind: &str, help: &str) -> io::Result<()> {
+    writeln!(wtr, "# HELP payment_engine_{} {}", name, help)?;
+    writeln!(wtr, "# TYPE payment_engine_{} {}", name, kind)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{Latencies, Metrics, BUCKET_BOUNDS, DECLINED};
+
+    #[test]
+    fn test_bucket_bounds() {
+        assert_eq!(Duration::from_micros(1), BUCKET_BOUNDS[0]);
+        assert_eq!(Duration::from_nanos(2_500), BUCKET_BOUNDS[1]);
+        assert_eq!(Duration::from_micros(5), BUCKET_BOUNDS[2]);
+        assert_eq!(Duration::from_micros(10), BUCKET_BOUNDS[3]);
+        assert_eq!(Duration::from_secs(10), BUCKET_BOUNDS[21]);
+    }
+
+    #[test]
+    fn test_percentiles() {
+        let mut latencies = Latencies::default();
+        assert_eq!(vec![Duration::ZERO], latencies.percentiles(&[0.5]));
+        for millis in (1..=100).rev() {
+            latencies.record(Duration::from_millis(millis));
+        }
+        assert_eq!(
+            vec![
+                Duration::from_millis(1),
+                Duration::from_millis(50),
+                Duration::from_millis(100),
+                Duration::from_millis(100)
+            ],
+            latencies.percentiles(&[0.0, 0.5, 0.99, 1.0])
+        );
+        // within a bucket, the percentile is its bound
+        assert_eq!(
+            vec![Duration::from_millis(25)],
+            latencies.percentiles(&[0.22])
+        );
+        assert_eq!(100, latencies.count());
+        assert_eq!(Duration::from_millis(5050), latencies.sum());
+
+        // past the last bound, the slowest call is all there is to go by
+        latencies.record(Duration::from_secs(30));
+        assert_eq!(vec![Duration::from_secs(30)], latencies.percentiles(&[1.0]));
+    }
+
+    #[test]
+    fn test_write_prometheus() {
+        let mut metrics = Metrics {
+            rows_read: 3,
+            elapsed: Duration::from_secs(2),
+            ..Default::default()
+        };
+        metrics.count_applied("Deposit");
+        metrics.count_applied("Deposit");
+        metrics.count_rejected(DECLINED);
+        metrics.storage_reads.record(Duration::from_millis(2));
+
+        let mut buf = Vec::new();
+        metrics.write_prometheus(&mut buf).unwrap();
+        let output = String::from_utf8(buf).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert!(lines.contains(&"# TYPE payment_engine_rows_read_total counter"));
+        assert!(lines.contains(&"payment_engine_rows_read_total 3"));
+        assert!(lines.contains(&r#"payment_engine_transactions_applied_total{type="deposit"} 2"#));
+        assert!(
+            lines.contains(&r#"payment_engine_transactions_rejected_total{reason="declined"} 1"#)
+        );
+        assert!(lines.contains(&"# TYPE payment_engine_storage_latency_seconds histogram"));
+        assert!(lines
+            .contains(&r#"payment_engine_storage_latency_seconds_bucket{op="read",le="0.001"} 0"#));
+        assert!(lines.contains(
+            &r#"payment_engine_storage_latency_seconds_bucket{op="read",le="0.0025"} 1"#
+        ));
+        assert!(lines
+            .contains(&r#"payment_engine_storage_latency_seconds_bucket{op="read",le="+Inf"} 1"#));
+        assert!(lines.contains(&r#"payment_engine_storage_latency_seconds_count{op="write"} 0"#));
+        assert!(lines.contains(&"payment_engine_throughput_rows_per_second 1.5"));
+    }
+
+    #[test]
+    fn test_write_summary() {
+        let mut metrics = Metrics {
+            rows_read: 1,
+            ..Default::default()
+        };
+        metrics.count_applied("Withdrawal");
+        let mut buf = Vec::new();
+        metrics.write_summary(&mut buf).unwrap();
+        let output = String::from_utf8(buf).unwrap();
+        assert!(output.starts_with("rows read: 1\napplied withdrawal: 1\n"));
+    }
+}
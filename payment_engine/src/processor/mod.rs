@@ -1,4 +1,12 @@
+pub mod credit_limit;
+pub mod events;
+pub mod journal;
+pub mod metrics;
+pub mod pipeline;
 pub mod record;
+pub mod report;
+pub mod rules;
 pub mod transaction;
 pub mod tx_processor;
 pub mod utils;
+pub mod validation;
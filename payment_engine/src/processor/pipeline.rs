@@ -0,0 +1,180 @@
+use std::fs::File;
+use std::time::{Duration, Instant};
+use std::{error::Error, io};
+
+use csv::{Reader, StringRecord, Writer};
+use tokio::{sync::mpsc, task};
+
+use crate::storage::record_storage::RecordStorage;
+
+use super::{
+    report::{InvalidRow, RunAborted},
+    transaction::Transaction,
+    tx_processor::{apply_transaction, finish_run, handle_invalid_row, RunOptions, RunReport},
+    validation::{parse_row, validate_headers},
+};
+
+/// Rows a stage may run ahead of the next one before it has to wait.
+pub const QUEUE_CAPACITY: usize = 1024;
+
+/// What the validation stage hands over to the storage stage.
+enum Validated {
+    Transaction(Transaction),
+    Invalid(InvalidRow),
+}
+
+/// Same as [`run_with_options`](super::tx_processor::run_with_options), but reads,
+/// validates and applies rows in three async stages connected by bounded queues.
+///
+/// CSV parsing runs on a blocking thread, as the csv reader only reads blocking, and
+/// validation is a task of its own, so both keep going while the storage stage waits on
+/// IO. The storage stage runs on the calling task and sees the rows in input order,
+/// which keeps the results identical to [`run`](super::tx_processor::run).
+///
+/// rusqlite and the log storage only have blocking calls, so the storage stage blocks
+/// whatever drives it. Run this with [`Runtime::block_on`](tokio::runtime::Runtime::block_on)
+/// or on a multi-threaded runtime, so the validation task still gets a thread.
+pub async fn run_pipelined<W>(
+    mut rdr: Reader<File>,
+    wtr: Writer<W>,
+    mut record_storage: impl RecordStorage,
+    mut options: RunOptions,
+) -> Result<RunReport, Box<dyn Error>>
+where
+    W: io::Write + 'static,
+{
+    let started = Instant::now();
+    let mut report = RunReport::new(&record_storage)?;
+
+    let headers = rdr.headers()?.clone();
+    validate_headers(&headers)?;
+
+    let (row_tx, mut row_rx) = mpsc::channel::<csv::Result<(StringRecord, u64)>>(QUEUE_CAPACITY);
+    let (validated_tx, mut validated_rx) = mpsc::channel::<csv::Result<Validated>>(QUEUE_CAPACITY);
+
+    // parsing; a failed send means a later stage stopped early
+    let parsing = task::spawn_blocking(move || {
+        let mut row = StringRecord::new();
+        loop {
+            let parsed = match rdr.read_record(&mut row) {
+                Ok(false) => break,
+                Ok(true) => Ok((row.clone(), rdr.position().byte())),
+                Err(err) => Err(err),
+            };
+            if row_tx.blocking_send(parsed).is_err() {
+                break;
+            }
+        }
+        rdr
+    });
+
+    // validation
+    let validation = task::spawn(async move {
+        let mut validation_time = Duration::ZERO;
+        while let Some(row) = row_rx.recv().await {
+            let validation_started = Instant::now();
+            let validated = row.map(|(row, end)| match parse_row(&row, &headers) {
+                Ok(txn) => Validated::Transaction(txn),
+                Err(reason) => Validated::Invalid(InvalidRow::new(&row, end, reason)),
+            });
+            validation_time += validation_started.elapsed();
+            if validated_tx.send(validated).await.is_err() {
+                break;
+            }
+        }
+        validation_time
+    });
+
+    // storage, on the calling task, so the storage doesn't have to be `Send`
+    let stored = async {
+        while let Some(validated) = validated_rx.recv().await {
+            match validated? {
+                Validated::Transaction(txn) => {
+                    apply_transaction(txn, &mut record_storage, &mut options, &mut report)?
+                }
+                Validated::Invalid(invalid_row) => {
+                    handle_invalid_row(invalid_row, &options, &mut report, started)?
+                }
+            }
+        }
+        Ok::<(), Box<dyn Error>>(())
+    }
+    .await;
+    // winds the other stages down if storage stopped early
+    drop(validated_rx);
+
+    let validation_time = validation.await.map_err(|_| "validation stage panicked")?;
+    report.metrics.validation_time = validation_time;
+    let rdr = parsing.await.map_err(|_| "parsing stage panicked")?;
+
+    // the parsing stage owns the input until it is done, so the rows are read back only now
+    if let Err(mut err) = stored {
+        if let Some(aborted) = err.downcast_mut::<RunAborted>() {
+            aborted.metrics.validation_time = validation_time;
+            aborted.invalid_row.read_raw(rdr.get_ref())?;
+        }
+        return Err(err);
+    }
+    for invalid_row in &mut report.invalid_rows {
+        invalid_row.read_raw(rdr.get_ref())?;
+    }
+
+    finish_run(wtr, record_storage, options, report, started)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::File, io};
+
+    use csv::{Reader, Trim, Writer};
+
+    use super::run_pipelined;
+    use crate::{
+        processor::{report::RunAborted, tx_processor::RunOptions, validation::ValidationMode},
+        storage::mem_storage::MemStorage,
+    };
+
+    #[tokio::test]
+    async fn test_run_pipelined_strict_mode_stops_at_invalid_row() {
+        let wtr = Writer::from_writer(io::sink());
+        let err = run_pipelined(
+            open_test_file("invalid_rows"),
+            wtr,
+            MemStorage::new(),
+            RunOptions::default(),
+        )
+        .await
+        .unwrap_err();
+        assert!(err.to_string().starts_with("line 3:"));
+        assert!(err.to_string().ends_with(r#"("deposit, 1, 2, -5.0")"#));
+        let aborted = err.downcast_ref::<RunAborted>().unwrap();
+        assert_eq!(2, aborted.metrics.rows_read);
+    }
+
+    #[tokio::test]
+    async fn test_run_pipelined_lenient_mode_skips_invalid_rows() {
+        let options = RunOptions {
+            validation: ValidationMode::Lenient,
+            ..Default::default()
+        };
+        let wtr = Writer::from_writer(io::sink());
+        let report = run_pipelined(
+            open_test_file("invalid_rows"),
+            wtr,
+            MemStorage::new(),
+            options,
+        )
+        .await
+        .unwrap();
+        assert_eq!(4, report.invalid_rows.len());
+        assert_eq!(2, report.journal.entries().len());
+    }
+
+    fn open_test_file(file_name: &str) -> Reader<File> {
+        let file_in: File = File::open(format!("./resources/{}.csv", file_name)).unwrap();
+        csv::ReaderBuilder::new()
+            .flexible(true)
+            .trim(Trim::All)
+            .from_reader(file_in)
+    }
+}
@@ -1,16 +1,17 @@
 use rusqlite::Row;
-use serde::Serialize;
+use serde::ser::{Serialize, SerializeStruct, Serializer};
 
-use super::utils::int_to_bool;
+use super::journal::{Account, JournalEntry};
 
-#[derive(Debug, Serialize, Copy, Clone, PartialEq)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub struct Record {
     pub client: u16,
     pub available: f64,
     pub held: f64,
     pub total: f64,
-    #[serde(serialize_with = "int_to_bool")]
     pub locked: Option<u8>,
+    /// How far below zero `available` may go.
+    pub credit_limit: f64,
 }
 
 impl Record {
@@ -21,8 +22,53 @@ impl Record {
             held: 0.0,
             total: 0.0,
             locked: None,
+            credit_limit: 0.0,
         }
     }
+
+    pub fn is_locked(&self) -> bool {
+        self.locked == Some(1)
+    }
+
+    /// Funds that can still be withdrawn, including the unused part of the credit limit.
+    pub fn headroom(&self) -> f64 {
+        self.available + self.credit_limit
+    }
+
+    /// Derives the record that results from posting `entry` to this client's accounts.
+    pub fn apply(&self, entry: &JournalEntry) -> Record {
+        let available_delta = entry.delta(&Account::ClientAvailable(self.client));
+        let held_delta = entry.delta(&Account::ClientHeld(self.client));
+        Record {
+            client: self.client,
+            available: self.available + available_delta,
+            held: self.held + held_delta,
+            total: self.total + available_delta + held_delta,
+            locked: if entry.locked_client == Some(self.client) {
+                Some(1)
+            } else {
+                self.locked
+            },
+            credit_limit: self.credit_limit,
+        }
+    }
+}
+
+impl Serialize for Record {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut state = serializer.serialize_struct("Record", 7)?;
+        state.serialize_field("client", &self.client)?;
+        state.serialize_field("available", &self.available)?;
+        state.serialize_field("held", &self.held)?;
+        state.serialize_field("total", &self.total)?;
+        state.serialize_field("locked", &self.is_locked())?;
+        state.serialize_field("credit_limit", &self.credit_limit)?;
+        state.serialize_field("headroom", &self.headroom())?;
+        state.end()
+    }
 }
 
 impl From<&Row<'_>> for Record {
@@ -33,6 +79,7 @@ impl From<&Row<'_>> for Record {
             held: row.get(2).unwrap(),
             total: row.get(3).unwrap(),
             locked: row.get(4).unwrap(),
+            credit_limit: row.get(5).unwrap_or_default(),
         }
     }
 }
@@ -0,0 +1,124 @@
+use std::fmt::Display;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::{error::Error, io};
+
+use csv::{StringRecord, Writer};
+use serde::Serialize;
+
+use super::metrics::Metrics;
+use super::rules::{RuleAction, RuleHit};
+use super::transaction::{Transaction, TransactionType};
+
+/// A transaction that was rejected, or applied but flagged for review.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct Rejection {
+    pub tx: u32,
+    pub client: u16,
+    #[serde(rename = "type")]
+    pub tx_type: TransactionType,
+    pub outcome: RuleAction,
+    pub rule: String,
+    pub reason: String,
+}
+
+impl Rejection {
+    pub fn from_hit(txn: &Transaction, hit: &RuleHit) -> Self {
+        Rejection {
+            tx: txn.tx,
+            client: txn.client,
+            tx_type: txn.tx_type,
+            outcome: hit.action,
+            rule: hit.rule.to_string(),
+            reason: hit.reason.clone(),
+        }
+    }
+}
+
+/// An input row that failed validation.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct InvalidRow {
+    pub line: u64,
+    /// The row as written in the input, filled in by [`InvalidRow::read_raw`].
+    pub raw: String,
+    pub reason: String,
+    /// Byte offsets of the row in the input, without the ones of the next row.
+    #[serde(skip)]
+    bytes: (u64, u64),
+}
+
+impl InvalidRow {
+    /// `end` is the reader's byte offset right after `row`.
+    pub fn new(row: &StringRecord, end: u64, reason: String) -> Self {
+        let position = row.position();
+        InvalidRow {
+            line: position.map(|pos| pos.line()).unwrap_or_default(),
+            raw: String::new(),
+            reason,
+            bytes: (position.map(|pos| pos.byte()).unwrap_or(end), end),
+        }
+    }
+
+    /// Reads the row back from the input `file`, leaving the file where the reader left it.
+    pub fn read_raw(&mut self, mut file: &File) -> io::Result<()> {
+        let resume_at = file.stream_position()?;
+        let (start, end) = self.bytes;
+        let mut bytes = vec![0; (end - start) as usize];
+        file.seek(SeekFrom::Start(start))?;
+        let read = file.read_exact(&mut bytes);
+        file.seek(SeekFrom::Start(resume_at))?;
+        read?;
+        self.raw = String::from_utf8_lossy(&bytes)
+            .trim_end_matches(['\r', '\n'])
+            .to_string();
+        Ok(())
+    }
+}
+
+impl Display for InvalidRow {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {} ({:?})", self.line, self.reason, self.raw)
+    }
+}
+
+/// The error a strict run stops with at an invalid row. It carries the metrics up to
+/// that row, so they can still be written out.
+#[derive(Debug)]
+pub struct RunAborted {
+    pub invalid_row: InvalidRow,
+    pub metrics: Metrics,
+}
+
+impl Display for RunAborted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.invalid_row.fmt(f)
+    }
+}
+
+impl Error for RunAborted {}
+
+pub fn write_rejections<W>(rejections: &[Rejection], wtr: Writer<W>) -> Result<(), Box<dyn Error>>
+where
+    W: io::Write,
+{
+    write_rows(rejections, wtr)
+}
+
+pub fn write_invalid_rows<W>(rows: &[InvalidRow], wtr: Writer<W>) -> Result<(), Box<dyn Error>>
+where
+    W: io::Write,
+{
+    write_rows(rows, wtr)
+}
+
+fn write_rows<T, W>(rows: &[T], mut wtr: Writer<W>) -> Result<(), Box<dyn Error>>
+where
+    T: Serialize,
+    W: io::Write,
+{
+    for row in rows {
+        wtr.serialize(row)?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
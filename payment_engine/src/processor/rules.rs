@@ -0,0 +1,337 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::str::FromStr;
+use std::{error::Error, io};
+
+use csv::{Reader, Trim};
+use serde::{Deserialize, Serialize};
+use strum_macros::{Display, EnumString};
+
+use super::transaction::{Transaction, TransactionType};
+
+/// What happens to a transaction that trips a rule.
+#[derive(Debug, Deserialize, Serialize, EnumString, Clone, Copy, Display, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum RuleAction {
+    /// Apply the transaction but report it.
+    Flag,
+    /// Drop the transaction and report it.
+    Reject,
+    /// Apply the transaction, then lock the client account.
+    Lock,
+}
+
+/// A check evaluated before a transaction is processed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rule {
+    /// Largest amount a single withdrawal or outgoing transfer may have.
+    MaxWithdrawal(f64),
+    /// Largest volume of withdrawals and outgoing transfers per client within one
+    /// run. A run is one business day's batch, so this is the client's daily volume.
+    MaxDailyWithdrawal(f64),
+    /// Number of disputes a client may file.
+    MaxDisputes(u32),
+    /// Trips on the client's n-th chargeback.
+    ChargebackCount(u32),
+}
+
+impl Rule {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Rule::MaxWithdrawal(_) => "max_withdrawal",
+            Rule::MaxDailyWithdrawal(_) => "max_daily_withdrawal",
+            Rule::MaxDisputes(_) => "max_disputes",
+            Rule::ChargebackCount(_) => "chargeback_count",
+        }
+    }
+
+    fn parse(name: &str, limit: f64) -> Result<Self, Box<dyn Error>> {
+        if !limit.is_finite() || limit < 0.0 {
+            return Err(From::from(format!(
+                "invalid limit {} for rule {}",
+                limit, name
+            )));
+        }
+        match name {
+            "max_withdrawal" => Ok(Rule::MaxWithdrawal(limit)),
+            "max_daily_withdrawal" => Ok(Rule::MaxDailyWithdrawal(limit)),
+            "max_disputes" => Ok(Rule::MaxDisputes(Self::count(name, limit)?)),
+            "chargeback_count" => Ok(Rule::ChargebackCount(Self::count(name, limit)?)),
+            x => Err(From::from(format!("unknown rule: {}", x))),
+        }
+    }
+
+    /// A count limit has to be a whole number, `2.5` disputes isn't rounded to 2.
+    fn count(name: &str, limit: f64) -> Result<u32, Box<dyn Error>> {
+        if limit.fract() != 0.0 || limit > u32::MAX as f64 {
+            return Err(From::from(format!(
+                "invalid limit {} for rule {}, expected a whole number",
+                limit, name
+            )));
+        }
+        Ok(limit as u32)
+    }
+}
+
+/// A rule together with the action taken when it trips.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RuleConfig {
+    pub rule: Rule,
+    pub action: RuleAction,
+}
+
+#[derive(Debug, Deserialize)]
+struct RuleRow {
+    rule: String,
+    limit: f64,
+    action: Option<String>,
+}
+
+/// A rule that tripped for a particular transaction.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuleHit {
+    pub rule: &'static str,
+    pub action: RuleAction,
+    pub reason: String,
+}
+
+/// Evaluates the configured rules against incoming transactions.
+///
+/// The counters only see transactions passed to [`RuleEngine::observe`], i.e. the
+/// ones that were actually applied, so the engine works without any storage.
+#[derive(Debug, Default, Clone)]
+pub struct RuleEngine {
+    rules: Vec<RuleConfig>,
+    withdrawn: HashMap<u16, f64>,
+    disputes: HashMap<u16, u32>,
+    chargebacks: HashMap<u16, u32>,
+}
+
+impl RuleEngine {
+    pub fn new(rules: Vec<RuleConfig>) -> Self {
+        RuleEngine {
+            rules,
+            ..Default::default()
+        }
+    }
+
+    /// Loads rules from a `rule, limit, action` CSV. The action defaults to `reject`.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let rdr = csv::ReaderBuilder::new()
+            .flexible(true)
+            .trim(Trim::All)
+            .from_path(path)?;
+        Self::from_reader(rdr)
+    }
+
+    pub fn from_reader<R: io::Read>(mut rdr: Reader<R>) -> Result<Self, Box<dyn Error>> {
+        let mut rules = Vec::new();
+        for row in rdr.deserialize() {
+            let row: RuleRow = row?;
+            let action = match row.action.as_deref() {
+                None | Some("") => RuleAction::Reject,
+                Some(action) => RuleAction::from_str(action)
+                    .map_err(|_| format!("unknown rule action: {}", action))?,
+            };
+            rules.push(RuleConfig {
+                rule: Rule::parse(&row.rule, row.limit)?,
+                action,
+            });
+        }
+        Ok(Self::new(rules))
+    }
+
+    /// Returns every rule `txn` trips, without changing any counters.
+    pub fn check(&self, txn: &Transaction) -> Vec<RuleHit> {
+        self.rules
+            .iter()
+            .filter_map(|config| {
+                self.violation(&config.rule, txn).map(|reason| RuleHit {
+                    rule: config.rule.name(),
+                    action: config.action,
+                    reason,
+                })
+            })
+            .collect()
+    }
+
+    /// Updates the counters with a transaction that has been applied.
+    pub fn observe(&mut self, txn: &Transaction) {
+        match txn.tx_type {
+            TransactionType::Withdrawal | TransactionType::Transfer => {
+                *self.withdrawn.entry(txn.client).or_insert(0.0) += txn.amount.unwrap_or_default()
+            }
+            TransactionType::Dispute => *self.disputes.entry(txn.client).or_insert(0) += 1,
+            TransactionType::Chargeback => *self.chargebacks.entry(txn.client).or_insert(0) += 1,
+            _ => {}
+        }
+    }
+
+    fn violation(&self, rule: &Rule, txn: &Transaction) -> Option<String> {
+        match (rule, txn.tx_type) {
+            // a transfer moves funds out of the sender just like a withdrawal
+            (
+                Rule::MaxWithdrawal(limit),
+                tx_type @ (TransactionType::Withdrawal | TransactionType::Transfer),
+            ) => {
+                let amount = txn.amount.unwrap_or_default();
+                let kind = if tx_type == TransactionType::Transfer {
+                    "transfer"
+                } else {
+                    "withdrawal"
+                };
+                (amount > *limit)
+                    .then(|| format!("{} of {} exceeds the limit of {}", kind, amount, limit))
+            }
+            (
+                Rule::MaxDailyWithdrawal(limit),
+                TransactionType::Withdrawal | TransactionType::Transfer,
+            ) => {
+                let volume = self.withdrawn.get(&txn.client).copied().unwrap_or_default()
+                    + txn.amount.unwrap_or_default();
+                (volume > *limit).then(|| {
+                    format!(
+                        "daily withdrawals of {} exceed the limit of {}",
+                        volume, limit
+                    )
+                })
+            }
+            (Rule::MaxDisputes(limit), TransactionType::Dispute) => {
+                let count = self.disputes.get(&txn.client).copied().unwrap_or_default() + 1;
+                (count > *limit)
+                    .then(|| format!("{} disputes exceed the limit of {}", count, limit))
+            }
+            (Rule::ChargebackCount(limit), TransactionType::Chargeback) => {
+                let count = self
+                    .chargebacks
+                    .get(&txn.client)
+                    .copied()
+                    .unwrap_or_default()
+                    + 1;
+                (count >= *limit)
+                    .then(|| format!("{} chargebacks reach the limit of {}", count, limit))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use csv::Trim;
+
+    use super::{Rule, RuleAction, RuleConfig, RuleEngine};
+    use crate::processor::transaction::{DisputeStatus, Transaction, TransactionType};
+
+    #[test]
+    fn test_max_withdrawal() {
+        let engine = make_engine(Rule::MaxWithdrawal(10.0), RuleAction::Reject);
+        assert!(engine
+            .check(&make_txn(TransactionType::Withdrawal, Some(10.0)))
+            .is_empty());
+        let hits = engine.check(&make_txn(TransactionType::Withdrawal, Some(10.5)));
+        assert_eq!(1, hits.len());
+        assert_eq!("max_withdrawal", hits[0].rule);
+        assert_eq!(RuleAction::Reject, hits[0].action);
+
+        // deposits aren't affected, transfers out are
+        assert!(engine
+            .check(&make_txn(TransactionType::Deposit, Some(10.5)))
+            .is_empty());
+        let hits = engine.check(&make_txn(TransactionType::Transfer, Some(10.5)));
+        assert_eq!(1, hits.len());
+        assert_eq!("max_withdrawal", hits[0].rule);
+    }
+
+    #[test]
+    fn test_max_daily_withdrawal() {
+        let mut engine = make_engine(Rule::MaxDailyWithdrawal(10.0), RuleAction::Flag);
+        let withdrawal = make_txn(TransactionType::Withdrawal, Some(6.0));
+        assert!(engine.check(&withdrawal).is_empty());
+        engine.observe(&withdrawal);
+        assert_eq!(1, engine.check(&withdrawal).len());
+
+        // other clients have their own volume
+        let mut other_client = withdrawal;
+        other_client.client = 2;
+        assert!(engine.check(&other_client).is_empty());
+
+        // outgoing transfers count towards the sender's volume
+        let mut transfer = make_txn(TransactionType::Transfer, Some(5.0));
+        transfer.client = 2;
+        assert!(engine.check(&transfer).is_empty());
+        engine.observe(&transfer);
+        assert_eq!(1, engine.check(&other_client).len());
+    }
+
+    #[test]
+    fn test_max_disputes() {
+        let mut engine = make_engine(Rule::MaxDisputes(1), RuleAction::Reject);
+        let dispute = make_txn(TransactionType::Dispute, None);
+        assert!(engine.check(&dispute).is_empty());
+        engine.observe(&dispute);
+        assert_eq!(1, engine.check(&dispute).len());
+    }
+
+    #[test]
+    fn test_chargeback_count() {
+        let mut engine = make_engine(Rule::ChargebackCount(2), RuleAction::Lock);
+        let chargeback = make_txn(TransactionType::Chargeback, None);
+        assert!(engine.check(&chargeback).is_empty());
+        engine.observe(&chargeback);
+        let hits = engine.check(&chargeback);
+        assert_eq!(1, hits.len());
+        assert_eq!(RuleAction::Lock, hits[0].action);
+    }
+
+    #[test]
+    fn test_from_reader() {
+        let data = "rule, limit, action\nmax_withdrawal, 100.0\nchargeback_count, 2, lock\n";
+        let rdr = csv::ReaderBuilder::new()
+            .flexible(true)
+            .trim(Trim::All)
+            .from_reader(data.as_bytes());
+        let engine = RuleEngine::from_reader(rdr).unwrap();
+        assert_eq!(
+            vec![
+                RuleConfig {
+                    rule: Rule::MaxWithdrawal(100.0),
+                    action: RuleAction::Reject
+                },
+                RuleConfig {
+                    rule: Rule::ChargebackCount(2),
+                    action: RuleAction::Lock
+                },
+            ],
+            engine.rules
+        );
+
+        for data in [
+            "rule, limit\nmax_everything, 1.0\n",
+            "rule, limit\nmax_disputes, 2.5\n",
+            "rule, limit\nchargeback_count, -1\n",
+            "rule, limit\nmax_withdrawal, -0.5\n",
+        ] {
+            let rdr = csv::ReaderBuilder::new()
+                .trim(Trim::All)
+                .from_reader(data.as_bytes());
+            assert!(RuleEngine::from_reader(rdr).is_err(), "{}", data);
+        }
+    }
+
+    fn make_engine(rule: Rule, action: RuleAction) -> RuleEngine {
+        RuleEngine::new(vec![RuleConfig { rule, action }])
+    }
+
+    fn make_txn(tx_type: TransactionType, amount: Option<f64>) -> Transaction {
+        Transaction {
+            tx_type,
+            client: 1,
+            tx: 1,
+            amount,
+            destination: None,
+            dispute_status: DisputeStatus::None,
+        }
+    }
+}
@@ -4,6 +4,7 @@ use std::result::Result;
 use std::{error::Error, str::FromStr};
 use strum_macros::{Display, EnumString};
 
+use super::journal::JournalEntry;
 use super::record::Record;
 
 #[derive(Debug, Deserialize, Serialize, EnumString, Clone, Copy, Display, PartialEq)]
@@ -14,6 +15,7 @@ pub enum TransactionType {
     Dispute,
     Resolve,
     Chargeback,
+    Transfer,
 }
 
 #[derive(Debug, Deserialize, Default, Serialize, EnumString, Clone, Copy, Display, PartialEq)]
@@ -38,6 +40,8 @@ pub struct Transaction {
     pub client: u16,
     pub tx: u32,
     pub amount: Option<f64>,
+    /// Receiving client of a transfer.
+    pub destination: Option<u16>,
     #[serde(default)]
     pub dispute_status: DisputeStatus,
 }
@@ -51,87 +55,100 @@ impl Transaction {
         }
     }
 
+    /// The other client involved in this transaction, if any: the destination of a
+    /// transfer, or of the transfer being corrected.
+    pub fn counterparty(self: &Transaction, txn_to_check: Option<Transaction>) -> Option<u16> {
+        match self.tx_type {
+            TransactionType::Transfer => self.destination,
+            TransactionType::Dispute | TransactionType::Resolve | TransactionType::Chargeback => {
+                txn_to_check
+                    .filter(|txn| txn.tx_type == TransactionType::Transfer)
+                    .and_then(|txn| txn.destination)
+            }
+            _ => None,
+        }
+    }
+
     fn update_dispute_status(&self, disp_st: DisputeStatus) -> Self {
         Transaction {
-            tx_type: self.tx_type,
-            client: self.client,
-            tx: self.tx,
-            amount: self.amount,
             dispute_status: disp_st,
+            ..*self
         }
     }
 
-    fn process_deposit(&self, current_rec: &Record) -> Record {
-        Record {
-            client: current_rec.client,
-            available: current_rec.available + self.amount.unwrap(),
-            held: current_rec.held,
-            total: current_rec.total + self.amount.unwrap(),
-            locked: current_rec.locked,
+    fn process_deposit(&self) -> JournalEntry {
+        JournalEntry::deposit(self, self.amount.unwrap())
+    }
+
+    fn process_withdrawal(&self, current_rec: &Record) -> Option<JournalEntry> {
+        // do we have enough funds, counting the credit limit?
+        if (current_rec.headroom() - self.amount.unwrap()) < 0.0 {
+            None
+        } else {
+            Some(JournalEntry::withdrawal(self, self.amount.unwrap()))
         }
     }
 
-    fn process_withdrawal(&self, current_rec: &Record) -> Option<Record> {
-        // do we have enough funds?
-        if (current_rec.available - self.amount.unwrap()) < 0.0 {
+    fn process_transfer(
+        &self,
+        current_rec: &Record,
+        destination_rec: Option<&Record>,
+    ) -> Option<JournalEntry> {
+        // both sides must be open and the sender must have enough funds
+        let destination_rec = destination_rec?;
+        if current_rec.is_locked()
+            || destination_rec.is_locked()
+            || (current_rec.headroom() - self.amount.unwrap()) < 0.0
+        {
             None
         } else {
-            Some(Record {
-                client: current_rec.client,
-                available: current_rec.available - self.amount.unwrap(),
-                held: current_rec.held,
-                total: current_rec.total - self.amount.unwrap(),
-                locked: current_rec.locked,
-            })
+            Some(JournalEntry::transfer(
+                self,
+                destination_rec.client,
+                self.amount.unwrap(),
+            ))
         }
     }
 
     fn process_dispute(
         &self,
-        current_rec: &Record,
         disputed_txn: &Transaction,
-    ) -> (Option<Record>, Option<Transaction>) {
+    ) -> (Option<JournalEntry>, Option<Transaction>) {
+        let amount = disputed_txn.amount.unwrap();
+        let entry = match disputed_txn.destination {
+            Some(destination) => JournalEntry::transfer_dispute(self, destination, amount),
+            None => JournalEntry::dispute(self, amount),
+        };
         (
-            Some(Record {
-                client: current_rec.client,
-                available: current_rec.available - disputed_txn.amount.unwrap(),
-                held: current_rec.held + disputed_txn.amount.unwrap(),
-                total: current_rec.total,
-                locked: current_rec.locked,
-            }),
+            Some(entry),
             Some(disputed_txn.update_dispute_status(DisputeStatus::Disputed)),
         )
     }
 
     fn process_resolve(
         &self,
-        current_rec: &Record,
         txn_to_resolve: &Transaction,
-    ) -> (Option<Record>, Option<Transaction>) {
-        let new_rec = Record {
-            client: current_rec.client,
-            available: current_rec.available + txn_to_resolve.amount.unwrap(),
-            held: current_rec.held - txn_to_resolve.amount.unwrap(),
-            total: current_rec.total,
-            locked: current_rec.locked,
+    ) -> (Option<JournalEntry>, Option<Transaction>) {
+        let amount = txn_to_resolve.amount.unwrap();
+        let entry = match txn_to_resolve.destination {
+            Some(destination) => JournalEntry::transfer_resolve(self, destination, amount),
+            None => JournalEntry::resolve(self, amount),
         };
         let updated_txn = txn_to_resolve.update_dispute_status(DisputeStatus::Resolved);
-        (Some(new_rec), Some(updated_txn))
+        (Some(entry), Some(updated_txn))
     }
 
     fn process_chargeback(
         &self,
-        current_rec: &Record,
         chargeback: &Transaction,
-    ) -> (Option<Record>, Option<Transaction>) {
+    ) -> (Option<JournalEntry>, Option<Transaction>) {
+        let amount = chargeback.amount.unwrap();
+        let entry = match chargeback.destination {
+            Some(destination) => JournalEntry::transfer_chargeback(self, destination, amount),
+            None => JournalEntry::chargeback(self, amount),
+        };
         (
-            Some(Record {
-                client: current_rec.client,
-                available: current_rec.available,
-                held: current_rec.held - chargeback.amount.unwrap(),
-                total: current_rec.total - chargeback.amount.unwrap(),
-                locked: Some(1),
-            }),
+            Some(entry),
             Some(chargeback.update_dispute_status(DisputeStatus::Chargedback)),
         )
     }
@@ -141,6 +158,11 @@ impl Transaction {
             // only check that the amount field is present
             TransactionType::Deposit => self.amount.is_some(),
             TransactionType::Withdrawal => self.amount.is_some(),
+            TransactionType::Transfer => {
+                self.amount.is_some()
+                    && self.destination.is_some()
+                    && self.destination != Some(self.client)
+            }
 
             // more checks
             TransactionType::Dispute => self.is_valid_correcting_txn(txn_to_check),
@@ -162,26 +184,30 @@ impl Transaction {
             && txn_to_check.unwrap().amount.is_some()
     }
 
+    /// Validates the transaction and returns the journal entry to post together with
+    /// the disputed transaction whose status changed, if any.
+    ///
+    /// `counterparty_rec` is the record of the client returned by [`Transaction::counterparty`].
     pub fn process(
         self: &Transaction,
         current_rec: &Record,
+        counterparty_rec: Option<&Record>,
         transaction_to_check: Option<Transaction>,
-    ) -> Result<(Option<Record>, Option<Transaction>), Box<dyn Error>> {
+    ) -> Result<(Option<JournalEntry>, Option<Transaction>), Box<dyn Error>> {
         if !self.is_valid_transaction(transaction_to_check) {
             return Ok((None, None)); // means this is an error and we ignore it
         }
 
         match self.tx_type {
-            TransactionType::Deposit => Ok((Some(self.process_deposit(current_rec)), None)),
+            TransactionType::Deposit => Ok((Some(self.process_deposit()), None)),
             TransactionType::Withdrawal => Ok((self.process_withdrawal(current_rec), None)),
-            TransactionType::Dispute => {
-                Ok(self.process_dispute(current_rec, &transaction_to_check.unwrap()))
-            }
-            TransactionType::Resolve => {
-                Ok(self.process_resolve(current_rec, &transaction_to_check.unwrap()))
+            TransactionType::Transfer => {
+                Ok((self.process_transfer(current_rec, counterparty_rec), None))
             }
+            TransactionType::Dispute => Ok(self.process_dispute(&transaction_to_check.unwrap())),
+            TransactionType::Resolve => Ok(self.process_resolve(&transaction_to_check.unwrap())),
             TransactionType::Chargeback => {
-                Ok(self.process_chargeback(current_rec, &transaction_to_check.unwrap()))
+                Ok(self.process_chargeback(&transaction_to_check.unwrap()))
             }
         }
     }
@@ -197,6 +223,7 @@ impl From<&Row<'_>> for Transaction {
             tx_type: TransactionType::from_str(txn_type_str.as_str()).unwrap(),
             dispute_status: DisputeStatus::from_str(disp_st_str.as_str()).unwrap(),
             amount: row.get(4).unwrap_or_default(),
+            destination: row.get(5).unwrap_or_default(),
         }
     }
 }
@@ -235,7 +262,10 @@ mod tests {
         let test_deposit = get_test_transaction(TransactionType::Deposit);
         let current_rec = make_unlocked_record(0.0, 0.0, 0.0);
         let expected_result = make_unlocked_record(0.0001, 0.0, 0.0001);
-        assert_eq!(expected_result, test_deposit.process_deposit(&current_rec));
+        assert_eq!(
+            expected_result,
+            current_rec.apply(&test_deposit.process_deposit())
+        );
     }
 
     #[test]
@@ -245,7 +275,9 @@ mod tests {
         let expected_result = make_unlocked_record(0.0, 0.0, 0.0);
         assert_eq!(
             Some(expected_result),
-            test_deposit.process_withdrawal(&current_rec)
+            test_deposit
+                .process_withdrawal(&current_rec)
+                .map(|entry| current_rec.apply(&entry))
         );
     }
 
@@ -256,6 +288,33 @@ mod tests {
         assert_eq!(None, test_deposit.process_withdrawal(&current_rec));
     }
 
+    #[test]
+    fn test_process_withdrawal_within_credit_limit() {
+        let test_withdrawal = make_undisputed_txn(TransactionType::Withdrawal, 1, 1, Some(1.5));
+        let mut current_rec = make_unlocked_record(1.0, 0.0, 1.0);
+        current_rec.credit_limit = 1.0;
+
+        let new_rec = current_rec.apply(&test_withdrawal.process_withdrawal(&current_rec).unwrap());
+        assert_eq!((-0.5, -0.5), (new_rec.available, new_rec.total));
+        assert_eq!(0.5, new_rec.headroom());
+
+        // the limit is exhausted now
+        assert_eq!(None, test_withdrawal.process_withdrawal(&new_rec));
+    }
+
+    #[test]
+    fn test_process_dispute_past_credit_limit() {
+        let test_dispute = make_undisputed_txn(TransactionType::Dispute, 1, 1, None);
+        let disputed_txn = make_undisputed_txn(TransactionType::Deposit, 1, 1, Some(5.0));
+        let mut current_rec = make_unlocked_record(1.0, 0.0, 1.0);
+        current_rec.credit_limit = 1.0;
+
+        let (entry, _) = test_dispute.process_dispute(&disputed_txn);
+        let new_rec = current_rec.apply(&entry.unwrap());
+        assert_eq!((-4.0, 5.0), (new_rec.available, new_rec.held));
+        assert_eq!(-3.0, new_rec.headroom());
+    }
+
     #[test]
     fn test_process_dispute() {
         let test_dipute = make_undisputed_txn(TransactionType::Dispute, 1, 1, None);
@@ -264,8 +323,11 @@ mod tests {
         let current_rec = make_unlocked_record(100.0, 20.50, 120.50);
         let expected_result = make_unlocked_record(80.0, 40.50, 120.50);
 
-        let (result, txn_to_update) = test_dipute.process_dispute(&current_rec, &disputed_txn);
-        assert_eq!(Some(expected_result), result);
+        let (result, txn_to_update) = test_dipute.process_dispute(&disputed_txn);
+        assert_eq!(
+            Some(expected_result),
+            result.map(|entry| current_rec.apply(&entry))
+        );
         assert_eq!(
             Some(disputed_txn.update_dispute_status(DisputeStatus::Disputed)),
             txn_to_update
@@ -280,8 +342,11 @@ mod tests {
         let current_rec = make_unlocked_record(80.0, 40.50, 120.50);
         let expected_result = make_unlocked_record(100.0, 20.50, 120.50);
 
-        let (result, txn_to_update) = test_resolve.process_resolve(&current_rec, &txn_to_resolve);
-        assert_eq!(Some(expected_result), result);
+        let (result, txn_to_update) = test_resolve.process_resolve(&txn_to_resolve);
+        assert_eq!(
+            Some(expected_result),
+            result.map(|entry| current_rec.apply(&entry))
+        );
         assert_eq!(
             Some(txn_to_resolve.update_dispute_status(DisputeStatus::Resolved)),
             txn_to_update
@@ -300,16 +365,131 @@ mod tests {
             held: 20.50,
             total: 100.50,
             locked: Some(1),
+            credit_limit: 0.0,
         };
 
-        let (result, txn_to_update) = test_chargeback.process_chargeback(&current_rec, &chargeback);
-        assert_eq!(Some(expected_result), result);
+        let (result, txn_to_update) = test_chargeback.process_chargeback(&chargeback);
+        assert_eq!(
+            Some(expected_result),
+            result.map(|entry| current_rec.apply(&entry))
+        );
         assert_eq!(
             Some(chargeback.update_dispute_status(DisputeStatus::Chargedback)),
             txn_to_update
         );
     }
 
+    #[test]
+    fn test_process_transfer_success() {
+        let test_transfer = make_transfer(2, Some(1.5));
+        let source_rec = make_unlocked_record(2.0, 0.0, 2.0);
+        let destination_rec = Record::new(2);
+
+        let entry = test_transfer
+            .process_transfer(&source_rec, Some(&destination_rec))
+            .unwrap();
+        assert_eq!(
+            make_unlocked_record(0.5, 0.0, 0.5),
+            source_rec.apply(&entry)
+        );
+        assert_eq!(
+            Record {
+                client: 2,
+                available: 1.5,
+                held: 0.0,
+                total: 1.5,
+                locked: None,
+                credit_limit: 0.0,
+            },
+            destination_rec.apply(&entry)
+        );
+    }
+
+    #[test]
+    fn test_process_transfer_fail() {
+        let test_transfer = make_transfer(2, Some(1.5));
+        let destination_rec = Record::new(2);
+
+        // not enough funds
+        let source_rec = make_unlocked_record(1.0, 0.0, 1.0);
+        assert_eq!(
+            None,
+            test_transfer.process_transfer(&source_rec, Some(&destination_rec))
+        );
+
+        // source is locked
+        let mut source_rec = make_unlocked_record(2.0, 0.0, 2.0);
+        source_rec.locked = Some(1);
+        assert_eq!(
+            None,
+            test_transfer.process_transfer(&source_rec, Some(&destination_rec))
+        );
+
+        // destination is locked
+        let source_rec = make_unlocked_record(2.0, 0.0, 2.0);
+        let mut destination_rec = Record::new(2);
+        destination_rec.locked = Some(1);
+        assert_eq!(
+            None,
+            test_transfer.process_transfer(&source_rec, Some(&destination_rec))
+        );
+    }
+
+    #[test]
+    fn test_is_invalid_transfer() {
+        assert!(!make_transfer(2, None).is_valid_transaction(None));
+        assert!(!make_transfer(1, Some(1.0)).is_valid_transaction(None));
+        let mut test_transfer = make_transfer(2, Some(1.0));
+        test_transfer.destination = None;
+        assert!(!test_transfer.is_valid_transaction(None));
+        assert!(make_transfer(2, Some(1.0)).is_valid_transaction(None));
+    }
+
+    #[test]
+    fn test_dispute_and_chargeback_transfer() {
+        let transfer = make_transfer(2, Some(1.5));
+        let test_dispute = make_undisputed_txn(TransactionType::Dispute, 1, 1, None);
+        let test_chargeback = make_undisputed_txn(TransactionType::Chargeback, 1, 1, None);
+        assert_eq!(Some(2), test_dispute.counterparty(Some(transfer)));
+
+        let source_rec = make_unlocked_record(0.5, 0.0, 0.5);
+        let destination_rec = Record {
+            client: 2,
+            available: 1.5,
+            held: 0.0,
+            total: 1.5,
+            locked: None,
+            credit_limit: 0.0,
+        };
+
+        let (entry, disputed) = test_dispute.process_dispute(&transfer);
+        let entry = entry.unwrap();
+        let (source_rec, destination_rec) =
+            (source_rec.apply(&entry), destination_rec.apply(&entry));
+        assert_eq!(make_unlocked_record(0.5, 0.0, 0.5), source_rec);
+        assert_eq!(
+            (0.0, 1.5),
+            (destination_rec.available, destination_rec.held)
+        );
+
+        let (entry, _) = test_chargeback.process_chargeback(&disputed.unwrap());
+        let entry = entry.unwrap();
+        assert_eq!(
+            make_unlocked_record(2.0, 0.0, 2.0),
+            source_rec.apply(&entry)
+        );
+        let destination_rec = destination_rec.apply(&entry);
+        assert_eq!(
+            (0.0, 0.0, 0.0),
+            (
+                destination_rec.available,
+                destination_rec.held,
+                destination_rec.total
+            )
+        );
+        assert!(destination_rec.is_locked());
+    }
+
     #[test]
     fn test_is_invalid_corrective_transaction() {
         let test_dispute = get_test_correction(TransactionType::Dispute);
@@ -318,19 +498,19 @@ mod tests {
         let corrective_transactions = [test_dispute, test_resolve, test_chargeback];
 
         // the transaction to correct is missing
-        corrective_transactions.map(|tx| {
+        corrective_transactions.iter().for_each(|tx| {
             assert!(!tx.is_valid_transaction(None));
         });
 
         // the transaction to correct doesn't have correct client_id
         let tx_to_correct = make_undisputed_txn(TransactionType::Withdrawal, 2, 1, None);
-        corrective_transactions.map(|tx| {
+        corrective_transactions.iter().for_each(|tx| {
             assert!(!tx.is_valid_transaction(Some(tx_to_correct)));
         });
 
         // the transaction to correct misses the amount
         let tx_to_correct = make_undisputed_txn(TransactionType::Withdrawal, 1, 1, None);
-        corrective_transactions.map(|tx| {
+        corrective_transactions.iter().for_each(|tx| {
             assert!(!tx.is_valid_transaction(Some(tx_to_correct)));
         });
     }
@@ -344,7 +524,7 @@ mod tests {
 
         // the transaction to correct looks fine
         let tx_to_correct = get_test_transaction(TransactionType::Deposit);
-        correcting_transactions.map(|tx| {
+        correcting_transactions.iter().for_each(|tx| {
             assert!(tx.is_valid_correcting_txn(Some(tx_to_correct)));
         });
     }
@@ -358,6 +538,7 @@ mod tests {
             tx_type: TransactionType::Deposit,
             tx: 1,
             amount: Some(2.134),
+            destination: None,
             dispute_status: DisputeStatus::Disputed,
         };
 
@@ -374,6 +555,7 @@ mod tests {
             tx_type: TransactionType::Deposit,
             tx: 1,
             amount: Some(2.134),
+            destination: None,
             dispute_status: DisputeStatus::None,
         };
 
@@ -412,9 +594,16 @@ mod tests {
             client,
             tx,
             amount,
+            destination: None,
             dispute_status: DisputeStatus::None,
         }
     }
+    fn make_transfer(destination: u16, amount: Option<f64>) -> Transaction {
+        Transaction {
+            destination: Some(destination),
+            ..make_undisputed_txn(TransactionType::Transfer, 1, 1, amount)
+        }
+    }
     fn make_unlocked_record(available: f64, held: f64, total: f64) -> Record {
         Record {
             client: 1,
@@ -422,6 +611,7 @@ mod tests {
             held,
             total,
             locked: None,
+            credit_limit: 0.0,
         }
     }
     fn get_test_transaction(tx_type: TransactionType) -> Transaction {
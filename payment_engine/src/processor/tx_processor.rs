@@ -1,73 +1,323 @@
 use std::fs::File;
+use std::time::Instant;
 use std::{error::Error, io};
 
 use std::result::Result;
 
-use csv::{Reader, Writer};
+use csv::{Reader, StringRecord, Writer};
 
 use crate::storage::{
-    db_storage::DbStorage, mem_storage::MemStorage, record_storage::RecordStorage,
+    db_storage::DbStorage,
+    mem_storage::MemStorage,
+    record_storage::{DuplicateTransaction, RecordStorage},
 };
 
 use super::utils::create_pool;
-use super::{record::Record, transaction::Transaction};
+use super::{
+    credit_limit::CreditLimits,
+    events::{BalanceEvent, EventSink},
+    journal::Journal,
+    metrics::{Metrics, DECLINED, DUPLICATE, INVALID_ROW},
+    record::Record,
+    report::{InvalidRow, Rejection, RunAborted},
+    rules::{RuleAction, RuleEngine},
+    transaction::Transaction,
+    validation::{parse_row, validate_headers, ValidationMode},
+};
+
+/// Settings for a single run of the engine.
+#[derive(Debug, Default)]
+pub struct RunOptions {
+    pub credit_limits: CreditLimits,
+    pub rules: RuleEngine,
+    /// Receives an event for every record changed by an applied transaction.
+    pub events: Option<EventSink>,
+    pub validation: ValidationMode,
+}
 
-pub fn run_in_mem<W>(file: Reader<File>, wtr: Writer<W>) -> Result<(), Box<dyn Error>>
+/// What a run produced besides the client records.
+#[derive(Debug, Default)]
+pub struct RunReport {
+    /// The journal the client records were derived from, including the entries of
+    /// earlier runs on the same storage.
+    pub journal: Journal,
+    /// Transactions rejected or flagged by the rules.
+    pub rejections: Vec<Rejection>,
+    /// Rows skipped in lenient mode.
+    pub invalid_rows: Vec<InvalidRow>,
+    pub metrics: Metrics,
+}
+
+impl RunReport {
+    /// An empty report, with the journal the storage kept from earlier runs.
+    pub(super) fn new(record_storage: &impl RecordStorage) -> Result<Self, Box<dyn Error>> {
+        Ok(RunReport {
+            journal: Journal::from_entries(record_storage.journal_entries()?)?,
+            ..Default::default()
+        })
+    }
+}
+
+pub fn run_in_mem<W>(
+    file: Reader<File>,
+    wtr: Writer<W>,
+    options: RunOptions,
+) -> Result<RunReport, Box<dyn Error>>
 where
     W: io::Write + 'static,
 {
     let mem_storage = MemStorage::new();
-    run(file, wtr, mem_storage)
+    run_with_options(file, wtr, mem_storage, options)
 }
 
-pub fn run_with_db<W>(file: Reader<File>, wtr: Writer<W>) -> Result<(), Box<dyn Error>>
+pub fn run_with_db<W>(
+    file: Reader<File>,
+    wtr: Writer<W>,
+    options: RunOptions,
+) -> Result<RunReport, Box<dyn Error>>
 where
     W: io::Write + 'static,
 {
     let db_pool = create_pool()?;
     let db_storage = DbStorage::new(db_pool.clone());
-    run(file, wtr, db_storage)
+    run_with_options(file, wtr, db_storage, options)
 }
 
+/// Processes every transaction in `rdr` and writes the final client records to `wtr`.
 pub fn run<W>(
+    rdr: Reader<File>,
+    wtr: Writer<W>,
+    record_storage: impl RecordStorage,
+) -> Result<RunReport, Box<dyn Error>>
+where
+    W: io::Write + 'static,
+{
+    run_with_options(rdr, wtr, record_storage, RunOptions::default())
+}
+
+pub fn run_with_options<W>(
     mut rdr: Reader<File>,
     wtr: Writer<W>,
     mut record_storage: impl RecordStorage,
-) -> Result<(), Box<dyn Error>>
+    mut options: RunOptions,
+) -> Result<RunReport, Box<dyn Error>>
 where
     W: io::Write + 'static,
 {
+    let started = Instant::now();
+    let mut report = RunReport::new(&record_storage)?;
+
+    let headers = rdr.headers()?.clone();
+    validate_headers(&headers)?;
+
     // read and process
-    for txn in rdr.deserialize() {
-        let txn: Transaction = txn?;
-        let current_client_data: Record = record_storage.get_client_record(txn.client)?;
-        let mut txn_to_check: Option<Transaction> = None;
-        if let Some(tx_id) = txn.tx_id_to_check() {
-            txn_to_check = record_storage.get_transaction(tx_id)?;
+    let mut row = StringRecord::new();
+    while rdr.read_record(&mut row)? {
+        let validation_started = Instant::now();
+        let parsed = parse_row(&row, &headers);
+        report.metrics.validation_time += validation_started.elapsed();
+        match parsed {
+            Ok(txn) => apply_transaction(txn, &mut record_storage, &mut options, &mut report)?,
+            Err(reason) => {
+                let mut invalid_row = InvalidRow::new(&row, rdr.position().byte(), reason);
+                invalid_row.read_raw(rdr.get_ref())?;
+                handle_invalid_row(invalid_row, &options, &mut report, started)?
+            }
         }
-        _ = record_storage.store_transaction(txn);
-        let (maybe_record, maybe_txn) = txn.process(&current_client_data, txn_to_check)?;
+    }
 
-        _ = match (maybe_record, maybe_txn) {
-            (Some(record), Some(txn)) => record_storage.update_record_and_txn(record, txn),
-            (Some(record), None) => record_storage.update_record(record),
-            _ => Ok(()), // this shouldn't happen,
-        };
+    finish_run(wtr, record_storage, options, report, started)
+}
+
+/// Aborts the run with a [`RunAborted`] in strict mode, otherwise records the row as
+/// skipped.
+pub(super) fn handle_invalid_row(
+    invalid_row: InvalidRow,
+    options: &RunOptions,
+    report: &mut RunReport,
+    started: Instant,
+) -> Result<(), Box<dyn Error>> {
+    report.metrics.rows_read += 1;
+    report.metrics.count_rejected(INVALID_ROW);
+    match options.validation {
+        ValidationMode::Strict => {
+            report.metrics.elapsed = started.elapsed();
+            Err(Box::new(RunAborted {
+                invalid_row,
+                metrics: report.metrics.clone(),
+            }))
+        }
+        ValidationMode::Lenient => {
+            report.invalid_rows.push(invalid_row);
+            Ok(())
+        }
+    }
+}
+
+/// Runs a validated transaction through the rules and applies it to storage.
+pub(super) fn apply_transaction(
+    txn: Transaction,
+    record_storage: &mut impl RecordStorage,
+    options: &mut RunOptions,
+    report: &mut RunReport,
+) -> Result<(), Box<dyn Error>> {
+    let started = Instant::now();
+    report.metrics.rows_read += 1;
+    let result = process_transaction(txn, record_storage, options, report);
+    report.metrics.processing_time += started.elapsed();
+    result
+}
+
+fn process_transaction(
+    txn: Transaction,
+    record_storage: &mut impl RecordStorage,
+    options: &mut RunOptions,
+    report: &mut RunReport,
+) -> Result<(), Box<dyn Error>> {
+    let limits = &options.credit_limits;
+    let rules = &mut options.rules;
+
+    let hits = rules.check(&txn);
+    report
+        .rejections
+        .extend(hits.iter().map(|hit| Rejection::from_hit(&txn, hit)));
+    if let Some(hit) = hits.iter().find(|hit| hit.action == RuleAction::Reject) {
+        report.metrics.count_rejected(hit.rule);
+        return Ok(());
+    }
+
+    let metrics = &mut report.metrics;
+    let current_client_data: Record = limits.apply(
+        metrics
+            .storage_reads
+            .time(|| record_storage.get_client_record(txn.client))?,
+    );
+    let mut txn_to_check: Option<Transaction> = None;
+    if let Some(tx_id) = txn.tx_id_to_check() {
+        txn_to_check = metrics
+            .storage_reads
+            .time(|| record_storage.get_transaction(tx_id))?;
+    }
+    let counterparty_data: Option<Record> = match txn.counterparty(txn_to_check) {
+        Some(client_id) => Some(
+            limits.apply(
+                metrics
+                    .storage_reads
+                    .time(|| record_storage.get_client_record(client_id))?,
+            ),
+        ),
+        None => None,
+    };
+    let stored = metrics
+        .storage_writes
+        .time(|| record_storage.store_transaction(txn));
+    if let Err(err) = stored {
+        if err.is::<DuplicateTransaction>() {
+            metrics.count_rejected(DUPLICATE);
+            return Ok(());
+        }
+        return Err(err);
+    }
+    let (maybe_entry, maybe_txn) = txn.process(
+        &current_client_data,
+        counterparty_data.as_ref(),
+        txn_to_check,
+    )?;
+
+    if let Some(entry) = maybe_entry {
+        let mut record = current_client_data.apply(&entry);
+        if hits.iter().any(|hit| hit.action == RuleAction::Lock) {
+            record.locked = Some(1);
+        }
+        let counterparty_record = counterparty_data.map(|rec| rec.apply(&entry));
+        record_storage.stage_journal_entry(&entry);
+        // transfers touch two clients and have to land together
+        report
+            .metrics
+            .storage_writes
+            .time(|| match (counterparty_record, maybe_txn) {
+                (Some(other), maybe_txn) => {
+                    record_storage.update_records(vec![record, other], maybe_txn)
+                }
+                (None, Some(txn)) => record_storage.update_record_and_txn(record, txn),
+                (None, None) => record_storage.update_record(record),
+            })?;
+        // only what made it into storage is posted
+        report.journal.post(entry)?;
+        rules.observe(&txn);
+        report.metrics.count_applied(txn.tx_type);
+
+        if let Some(sink) = options.events.as_mut() {
+            let changes = std::iter::once((current_client_data, record))
+                .chain(counterparty_data.zip(counterparty_record));
+            for (before, after) in changes {
+                let event = BalanceEvent::new(&txn, &before, &after);
+                // e.g. the sender of a disputed transfer, only the destination's funds move
+                if event.before != event.after {
+                    sink.emit(&event)?;
+                }
+            }
+        }
+    } else {
+        report.metrics.count_rejected(DECLINED);
     }
+    Ok(())
+}
+
+/// Flushes the events, checks the journal against the records and writes the client
+/// records to `wtr`.
+pub(super) fn finish_run<W>(
+    wtr: Writer<W>,
+    record_storage: impl RecordStorage,
+    mut options: RunOptions,
+    mut report: RunReport,
+    started: Instant,
+) -> Result<RunReport, Box<dyn Error>>
+where
+    W: io::Write + 'static,
+{
+    if let Some(sink) = options.events.as_mut() {
+        sink.flush()?;
+    }
+    // the journal posts an entry once its records are stored, so each client it knows
+    // has a record with the balances of its accounts
+    let records = report
+        .journal
+        .clients()
+        .into_iter()
+        .map(|client| record_storage.get_client_record(client))
+        .collect::<Result<Vec<Record>, _>>()?;
+    report.journal.check_invariants(&records)?;
 
     // print back
-    record_storage.write_records(wtr)
+    let output_started = Instant::now();
+    record_storage.write_records(wtr)?;
+    report.metrics.output_time = output_started.elapsed();
+    report.metrics.elapsed = started.elapsed();
+    Ok(report)
 }
 
 #[cfg(test)]
 mod tests {
 
-    use std::fs::File;
+    use std::{fs, fs::File, io};
 
     use csv::{Reader, Trim, Writer};
 
-    use super::run;
-    use crate::{processor::record::Record, storage::record_storage::MockRecordStorage};
+    use super::{run, run_with_options, RunOptions};
+    use crate::{
+        processor::{
+            events::EventSink,
+            journal::Account,
+            metrics::{DUPLICATE, INVALID_ROW},
+            record::Record,
+            report::RunAborted,
+            validation::ValidationMode,
+        },
+        storage::{
+            log_storage::LogStorage, mem_storage::MemStorage, record_storage::MockRecordStorage,
+        },
+    };
     // cargo test --package payment_engine --bin payment_engine -- processor::tx_processor::tests::test_run --exact --show-output
 
     #[test]
@@ -76,10 +326,20 @@ mod tests {
         let mut record_storage = MockRecordStorage::new();
         let wtr = Writer::from_path("./resources/tmp.csv").ok().unwrap();
 
+        // once to apply the deposit, once to check the stored record against the journal
+        let mut deposited = false;
         record_storage
             .expect_get_client_record()
-            .once()
-            .returning(move |_| Ok(Record::new(1)));
+            .times(2)
+            .returning(move |client| {
+                let mut record = Record::new(client);
+                if deposited {
+                    record.available = 1.0;
+                    record.total = 1.0;
+                }
+                deposited = true;
+                Ok(record)
+            });
         record_storage
             .expect_store_transaction()
             .once()
@@ -92,7 +352,144 @@ mod tests {
             .expect_write_records()
             .once()
             .returning(|_: Writer<File>| Ok(()));
-        _ = run(reader, wtr, record_storage);
+        run(reader, wtr, record_storage).unwrap();
+    }
+
+    #[test]
+    fn test_run_stops_when_a_write_fails() {
+        let mut record_storage = MockRecordStorage::new();
+        record_storage
+            .expect_get_client_record()
+            .returning(|client| Ok(Record::new(client)));
+        record_storage
+            .expect_store_transaction()
+            .returning(|_| Ok(()));
+        record_storage
+            .expect_update_record()
+            .once()
+            .returning(|_| Err(From::from("disk full")));
+        // no expectation for write_records, the accounts mustn't be written
+        let wtr = Writer::from_writer(io::sink());
+        let err = run(open_test_file("t1"), wtr, record_storage).unwrap_err();
+        assert_eq!("disk full", err.to_string());
+    }
+
+    #[test]
+    fn test_rerun_on_log_storage_rejects_duplicates() {
+        let dir = std::env::temp_dir().join("payment_engine_test_rerun");
+        _ = fs::remove_dir_all(&dir);
+        let first = run(
+            open_test_file("t1"),
+            Writer::from_writer(io::sink()),
+            LogStorage::open(&dir).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(Some(&1), first.metrics.applied.get("deposit"));
+
+        let path = dir.join("accounts.csv");
+        let second = run(
+            open_test_file("t1"),
+            Writer::from_path(&path).unwrap(),
+            LogStorage::open(&dir).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(Some(&1), second.metrics.rejected.get(DUPLICATE));
+        // the journal carries over, so it still matches the records
+        assert_eq!(1, second.journal.entries().len());
+        assert_eq!(1.0, second.journal.balance(&Account::ClientAvailable(1)));
+        let accounts = fs::read_to_string(&path).unwrap();
+        assert!(accounts.contains("\n1,1.0,0.0,1.0,false,"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_run_emits_events() {
+        let events_path = std::env::temp_dir().join("payment_engine_test_events.jsonl");
+        let options = RunOptions {
+            events: Some(EventSink::to_path(&events_path).unwrap()),
+            ..Default::default()
+        };
+        let wtr = Writer::from_writer(io::sink());
+        run_with_options(open_test_file("test1"), wtr, MemStorage::new(), options).unwrap();
+
+        // 10 rows, of which the failed withdrawal and the late resolve aren't applied
+        let events = fs::read_to_string(&events_path).unwrap();
+        assert_eq!(8, events.lines().count());
+        assert!(events
+            .lines()
+            .nth(5)
+            .unwrap()
+            .contains(r#""type":"chargeback""#));
+        fs::remove_file(events_path).unwrap();
+    }
+
+    #[test]
+    fn test_disputed_transfer_events_skip_the_sender() {
+        let events_path = std::env::temp_dir().join("payment_engine_test_transfer_events.jsonl");
+        let options = RunOptions {
+            events: Some(EventSink::to_path(&events_path).unwrap()),
+            ..Default::default()
+        };
+        let wtr = Writer::from_writer(io::sink());
+        run_with_options(
+            open_test_file("transfer_test"),
+            wtr,
+            MemStorage::new(),
+            options,
+        )
+        .unwrap();
+
+        let events = fs::read_to_string(&events_path).unwrap();
+        let disputed: Vec<&str> = events
+            .lines()
+            .filter(|line| line.contains(r#""tx":5,"type":"dispute""#))
+            .collect();
+        // the sender's record doesn't change, only the destination's funds are held
+        assert_eq!(1, disputed.len());
+        assert!(disputed[0].starts_with(r#"{"client":3,"#));
+        fs::remove_file(events_path).unwrap();
+    }
+
+    #[test]
+    fn test_run_strict_mode_stops_at_invalid_row() {
+        let wtr = Writer::from_writer(io::sink());
+        let err = run(open_test_file("invalid_rows"), wtr, MemStorage::new()).unwrap_err();
+        assert_eq!(
+            r#"line 3: amount must be positive, got -5.0 ("deposit, 1, 2, -5.0")"#,
+            err.to_string()
+        );
+        // the metrics up to the invalid row come along with the error
+        let aborted = err.downcast_ref::<RunAborted>().unwrap();
+        assert_eq!(2, aborted.metrics.rows_read);
+        assert_eq!(Some(&1), aborted.metrics.rejected.get(INVALID_ROW));
+    }
+
+    #[test]
+    fn test_run_lenient_mode_skips_invalid_rows() {
+        let options = RunOptions {
+            validation: ValidationMode::Lenient,
+            ..Default::default()
+        };
+        let wtr = Writer::from_writer(io::sink());
+        let report = run_with_options(
+            open_test_file("invalid_rows"),
+            wtr,
+            MemStorage::new(),
+            options,
+        )
+        .unwrap();
+
+        let lines: Vec<u64> = report.invalid_rows.iter().map(|row| row.line).collect();
+        assert_eq!(vec![3, 4, 5, 6], lines);
+        assert_eq!("deposit, 1", report.invalid_rows[1].raw);
+        assert_eq!(
+            "dispute must not have an amount",
+            report.invalid_rows[2].reason
+        );
+        assert_eq!(2, report.journal.entries().len());
+        assert_eq!(6, report.metrics.rows_read);
+        assert_eq!(Some(&4), report.metrics.rejected.get("invalid_row"));
+        assert_eq!(Some(&2), report.metrics.applied.get("deposit"));
     }
 
     fn open_test_file(file_name: &str) -> Reader<File> {
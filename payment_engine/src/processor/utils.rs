@@ -1,26 +1,28 @@
 use csv::{Reader, Trim};
 use r2d2::{Pool, PooledConnection};
 use r2d2_sqlite::SqliteConnectionManager;
-use serde::Serializer;
+use std::path::Path;
 use std::result::Result;
-use std::{env, error::Error, fs::File};
+use std::{error::Error, fs::File};
 
-pub fn get_file_reader() -> Result<Reader<File>, Box<dyn Error>> {
-    match env::args_os().nth(1) {
-        None => Err(From::from("expected a file name, but got none")),
-        Some(file_path) => {
-            let file_in = File::open(file_path)?;
-            let rdr = csv::ReaderBuilder::new()
-                .flexible(true)
-                .trim(Trim::All)
-                .from_reader(file_in);
-            Ok(rdr)
-        }
-    }
+pub fn get_file_reader<P: AsRef<Path>>(file_path: P) -> Result<Reader<File>, Box<dyn Error>> {
+    let file_in = File::open(file_path)?;
+    let rdr = csv::ReaderBuilder::new()
+        .flexible(true)
+        .trim(Trim::All)
+        .from_reader(file_in);
+    Ok(rdr)
 }
 
 pub fn create_pool() -> Result<Pool<SqliteConnectionManager>, Box<dyn Error>> {
-    let manager = SqliteConnectionManager::file("records.db");
+    create_pool_at("records.db")
+}
+
+/// Same as [`create_pool`], with the database at `path`. Existing tables are dropped.
+pub fn create_pool_at<P: AsRef<Path>>(
+    path: P,
+) -> Result<Pool<SqliteConnectionManager>, Box<dyn Error>> {
+    let manager = SqliteConnectionManager::file(path);
     let pool = Pool::builder().max_size(5).build(manager)?;
     init_db(&pool)?;
     Ok(pool)
@@ -39,7 +41,8 @@ fn init_db(db_pool: &Pool<SqliteConnectionManager>) -> Result<(), Box<dyn Error>
            client integer,
            tx_type text,
            disp_st text,
-           amount real
+           amount real,
+           destination integer
        )",
         [],
     )?;
@@ -57,20 +60,10 @@ fn init_db(db_pool: &Pool<SqliteConnectionManager>) -> Result<(), Box<dyn Error>
          available real,
          held real,
          total real,
-         locked integer
+         locked integer,
+         credit_limit real
      )",
         [],
     )?;
     Ok(())
 }
-
-pub fn int_to_bool<S>(value: &Option<u8>, serializer: S) -> Result<S::Ok, S::Error>
-where
-    S: Serializer,
-{
-    let s = match value {
-        Some(1) => "true",
-        _ => "false",
-    };
-    serializer.serialize_str(s)
-}
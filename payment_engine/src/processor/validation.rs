@@ -0,0 +1,188 @@
+use csv::StringRecord;
+use strum_macros::{Display, EnumString};
+
+use super::transaction::{Transaction, TransactionType, CORRECTING_TRANSACTION_TYPES};
+
+/// Columns every input file starts with, in this order.
+pub const REQUIRED_HEADERS: [&str; 4] = ["type", "client", "tx", "amount"];
+/// Column that may follow the required ones.
+pub const OPTIONAL_HEADERS: [&str; 1] = ["destination"];
+/// Amounts carry at most this many decimal places.
+pub const MAX_DECIMAL_PLACES: usize = 4;
+
+/// How the engine reacts to a row that fails validation.
+#[derive(Debug, Default, EnumString, Clone, Copy, Display, PartialEq, Eq)]
+#[strum(serialize_all = "lowercase")]
+pub enum ValidationMode {
+    /// Abort the run on the first invalid row.
+    #[default]
+    Strict,
+    /// Skip invalid rows and report them.
+    Lenient,
+}
+
+pub fn validate_headers(headers: &StringRecord) -> Result<(), String> {
+    let expected = REQUIRED_HEADERS.iter().chain(OPTIONAL_HEADERS.iter());
+    let valid = headers.len() >= REQUIRED_HEADERS.len()
+        && headers.len() <= REQUIRED_HEADERS.len() + OPTIONAL_HEADERS.len()
+        && headers
+            .iter()
+            .zip(expected)
+            .all(|(found, wanted)| found == *wanted);
+    if valid {
+        Ok(())
+    } else {
+        Err(format!(
+            "invalid header {:?}, expected \"{}\" optionally followed by \"{}\"",
+            headers.iter().collect::<Vec<&str>>().join(","),
+            REQUIRED_HEADERS.join(","),
+            OPTIONAL_HEADERS.join(",")
+        ))
+    }
+}
+
+/// Parses a row into a [`Transaction`], checking the things serde can't:
+/// column count, amount sign and precision, and which columns each type may use.
+pub fn parse_row(row: &StringRecord, headers: &StringRecord) -> Result<Transaction, String> {
+    // corrections may leave out the amount column
+    if row.len() < REQUIRED_HEADERS.len() - 1 || row.len() > headers.len() {
+        return Err(format!(
+            "expected between {} and {} columns, got {}",
+            REQUIRED_HEADERS.len() - 1,
+            headers.len(),
+            row.len()
+        ));
+    }
+    let txn: Transaction = row
+        .deserialize(Some(headers))
+        .map_err(|err| match err.kind() {
+            csv::ErrorKind::Deserialize { err, .. } => err.to_string(),
+            _ => err.to_string(),
+        })?;
+
+    let raw_amount = row.get(3).unwrap_or_default();
+    // name the type the way it was written in the input
+    let kind = txn.tx_type.to_string().to_lowercase();
+    if CORRECTING_TRANSACTION_TYPES.contains(&txn.tx_type) {
+        if txn.amount.is_some() {
+            return Err(format!("{} must not have an amount", kind));
+        }
+    } else {
+        match txn.amount {
+            None => return Err(format!("{} requires an amount", kind)),
+            Some(amount) if !amount.is_finite() || amount <= 0.0 => {
+                return Err(format!("amount must be positive, got {}", raw_amount))
+            }
+            // the precision check below only reads the digits after the point
+            Some(_) if raw_amount.contains(['e', 'E']) => {
+                return Err(format!(
+                    "amount {} must be written without an exponent",
+                    raw_amount
+                ))
+            }
+            Some(_) if decimal_places(raw_amount) > MAX_DECIMAL_PLACES => {
+                return Err(format!(
+                    "amount {} has more than {} decimal places",
+                    raw_amount, MAX_DECIMAL_PLACES
+                ))
+            }
+            _ => {}
+        }
+    }
+
+    match (txn.tx_type, txn.destination) {
+        (TransactionType::Transfer, None) => Err("transfer requires a destination".to_string()),
+        (TransactionType::Transfer, _) => Ok(txn),
+        (_, Some(_)) => Err(format!("{} must not have a destination", kind)),
+        (_, None) => Ok(txn),
+    }
+}
+
+/// Decimal places written in `amount`, ignoring trailing zeros.
+fn decimal_places(amount: &str) -> usize {
+    match amount.split_once('.') {
+        Some((_, fraction)) => fraction.trim_end_matches('0').len(),
+        None => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use csv::StringRecord;
+
+    use super::{parse_row, validate_headers, ValidationMode};
+    use crate::processor::transaction::TransactionType;
+
+    #[test]
+    fn test_validate_headers() {
+        assert!(validate_headers(&to_record("type,client,tx,amount")).is_ok());
+        assert!(validate_headers(&to_record("type,client,tx,amount,destination")).is_ok());
+        assert!(validate_headers(&to_record("type,client,tx")).is_err());
+        assert!(validate_headers(&to_record("client,type,tx,amount")).is_err());
+        assert!(validate_headers(&to_record("type,client,tx,amount,destination,memo")).is_err());
+    }
+
+    #[test]
+    fn test_parse_valid_rows() {
+        let headers = to_record("type,client,tx,amount,destination");
+        let txn = parse_row(&to_record("deposit,1,2,1.2345"), &headers).unwrap();
+        assert_eq!(
+            (TransactionType::Deposit, 1, 2, Some(1.2345)),
+            (txn.tx_type, txn.client, txn.tx, txn.amount)
+        );
+        assert!(parse_row(&to_record("dispute,1,2"), &headers).is_ok());
+        assert!(parse_row(&to_record("resolve,1,2,"), &headers).is_ok());
+        assert!(parse_row(&to_record("withdrawal,1,2,1.50000"), &headers).is_ok());
+        assert!(parse_row(&to_record("transfer,1,2,1.5,3"), &headers).is_ok());
+    }
+
+    #[test]
+    fn test_parse_invalid_rows() {
+        let headers = to_record("type,client,tx,amount,destination");
+        let invalid_rows = [
+            ("deposit,1", "expected between 3 and 5 columns, got 2"),
+            (
+                "deposit,1,2,1.0,3,4",
+                "expected between 3 and 5 columns, got 6",
+            ),
+            ("deposit,1,2", "deposit requires an amount"),
+            ("deposit,1,2,-1.0", "amount must be positive, got -1.0"),
+            ("withdrawal,1,2,0", "amount must be positive, got 0"),
+            (
+                "deposit,1,2,1.23456",
+                "amount 1.23456 has more than 4 decimal places",
+            ),
+            (
+                "deposit,1,2,12345678e-12",
+                "amount 12345678e-12 must be written without an exponent",
+            ),
+            ("dispute,1,2,1.0", "dispute must not have an amount"),
+            ("deposit,1,2,1.0,3", "deposit must not have a destination"),
+            ("transfer,1,2,1.0", "transfer requires a destination"),
+        ];
+        for (row, reason) in invalid_rows {
+            assert_eq!(
+                Err(reason.to_string()),
+                parse_row(&to_record(row), &headers)
+            );
+        }
+        // serde's own complaints come through as well
+        assert!(parse_row(&to_record("refund,1,2,1.0"), &headers).is_err());
+        assert!(parse_row(&to_record("deposit,x,2,1.0"), &headers).is_err());
+    }
+
+    #[test]
+    fn test_validation_mode_from_str() {
+        assert_eq!(
+            ValidationMode::Lenient,
+            ValidationMode::from_str("lenient").unwrap()
+        );
+        assert!(ValidationMode::from_str("relaxed").is_err());
+    }
+
+    fn to_record(line: &str) -> StringRecord {
+        StringRecord::from(line.split(',').collect::<Vec<&str>>())
+    }
+}
@@ -0,0 +1,267 @@
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::path::Path;
+use std::{error::Error, io};
+
+use csv::{Reader, Trim};
+use serde::Deserialize;
+
+/// One row of an accounts file as written by the engine. Extra columns are ignored.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+pub struct AccountRow {
+    pub client: u16,
+    pub available: f64,
+    pub held: f64,
+    pub total: f64,
+    pub locked: bool,
+}
+
+/// A way in which a produced accounts file differs from the expected one.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Difference {
+    /// The client is expected but wasn't produced.
+    MissingClient(u16),
+    /// The client was produced but isn't expected.
+    UnexpectedClient(u16),
+    /// The client appears more than once in one of the files.
+    DuplicateClient { client: u16, file: &'static str },
+    BalanceMismatch {
+        client: u16,
+        field: &'static str,
+        expected: f64,
+        actual: f64,
+    },
+    LockMismatch {
+        client: u16,
+        expected: bool,
+        actual: bool,
+    },
+}
+
+impl Display for Difference {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Difference::MissingClient(client) => write!(f, "client {}: missing", client),
+            Difference::UnexpectedClient(client) => write!(f, "client {}: unexpected", client),
+            Difference::DuplicateClient { client, file } => {
+                write!(f, "client {}: duplicated in {} file", client, file)
+            }
+            Difference::BalanceMismatch {
+                client,
+                field,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "client {}: {} expected {} but got {}",
+                client, field, expected, actual
+            ),
+            Difference::LockMismatch {
+                client,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "client {}: locked expected {} but got {}",
+                client, expected, actual
+            ),
+        }
+    }
+}
+
+pub fn read_accounts<P: AsRef<Path>>(path: P) -> Result<Vec<AccountRow>, Box<dyn Error>> {
+    let rdr = csv::ReaderBuilder::new()
+        .flexible(true)
+        .trim(Trim::All)
+        .from_path(path)?;
+    read_accounts_from(rdr)
+}
+
+pub fn read_accounts_from<R: io::Read>(
+    mut rdr: Reader<R>,
+) -> Result<Vec<AccountRow>, Box<dyn Error>> {
+    let mut rows = Vec::new();
+    for row in rdr.deserialize() {
+        rows.push(row?);
+    }
+    Ok(rows)
+}
+
+/// Compares two sets of accounts regardless of row order. Balances within
+/// `tolerance` of each other are considered equal. Differences are sorted by client.
+pub fn reconcile(
+    actual: &[AccountRow],
+    expected: &[AccountRow],
+    tolerance: f64,
+) -> Vec<Difference> {
+    let mut differences = Vec::new();
+    let actual = index_by_client(actual, "actual", &mut differences);
+    let expected = index_by_client(expected, "expected", &mut differences);
+
+    for (client, expected_row) in expected.iter() {
+        match actual.get(client) {
+            None => differences.push(Difference::MissingClient(*client)),
+            Some(actual_row) => compare_rows(actual_row, expected_row, tolerance, &mut differences),
+        }
+    }
+    differences.extend(
+        actual
+            .keys()
+            .filter(|client| !expected.contains_key(client))
+            .map(|client| Difference::UnexpectedClient(*client)),
+    );
+    differences.sort_by_key(client_of);
+    differences
+}
+
+fn index_by_client<'a>(
+    rows: &'a [AccountRow],
+    file: &'static str,
+    differences: &mut Vec<Difference>,
+) -> BTreeMap<u16, &'a AccountRow> {
+    let mut index = BTreeMap::new();
+    for row in rows {
+        if index.insert(row.client, row).is_some() {
+            differences.push(Difference::DuplicateClient {
+                client: row.client,
+                file,
+            });
+        }
+    }
+    index
+}
+
+fn compare_rows(
+    actual: &AccountRow,
+    expected: &AccountRow,
+    tolerance: f64,
+    differences: &mut Vec<Difference>,
+) {
+    let balances = [
+        ("available", expected.available, actual.available),
+        ("held", expected.held, actual.held),
+        ("total", expected.total, actual.total),
+    ];
+    for (field, expected_value, actual_value) in balances {
+        // NaN never compares as within tolerance
+        if (expected_value - actual_value).abs() <= tolerance {
+            continue;
+        }
+        differences.push(Difference::BalanceMismatch {
+            client: expected.client,
+            field,
+            expected: expected_value,
+            actual: actual_value,
+        });
+    }
+    if expected.locked != actual.locked {
+        differences.push(Difference::LockMismatch {
+            client: expected.client,
+            expected: expected.locked,
+            actual: actual.locked,
+        });
+    }
+}
+
+fn client_of(difference: &Difference) -> u16 {
+    match difference {
+        Difference::MissingClient(client) | Difference::UnexpectedClient(client) => *client,
+        Difference::DuplicateClient { client, .. }
+        | Difference::BalanceMismatch { client, .. }
+        | Difference::LockMismatch { client, .. } => *client,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use csv::Trim;
+
+    use super::{read_accounts_from, reconcile, AccountRow, Difference};
+
+    #[test]
+    fn test_reconcile_ignores_row_order() {
+        let actual =
+            parse("client,available,held,total,locked\n2,2.0,0.0,2.0,false\n1,1.5,0.0,1.5,true\n");
+        let expected = parse("client, available, held, total, locked\n1, 1.5, 0.0, 1.5, true\n2, 2.0, 0.0, 2.0, false\n");
+        assert!(reconcile(&actual, &expected, 0.0).is_empty());
+    }
+
+    #[test]
+    fn test_reconcile_tolerance() {
+        let actual = vec![make_row(1, 1.50004, false)];
+        let expected = vec![make_row(1, 1.5, false)];
+        assert!(reconcile(&actual, &expected, 0.0001).is_empty());
+        assert_eq!(2, reconcile(&actual, &expected, 0.00001).len());
+    }
+
+    #[test]
+    fn test_reconcile_reports_differences() {
+        let actual = vec![
+            make_row(1, 1.0, true),
+            make_row(3, 3.0, false),
+            make_row(4, 4.0, false),
+        ];
+        let expected = vec![
+            make_row(1, 1.0, false),
+            make_row(2, 2.0, false),
+            make_row(4, 4.5, false),
+        ];
+
+        let differences = reconcile(&actual, &expected, 0.0);
+        assert_eq!(
+            vec![
+                Difference::LockMismatch {
+                    client: 1,
+                    expected: false,
+                    actual: true
+                },
+                Difference::MissingClient(2),
+                Difference::UnexpectedClient(3),
+                Difference::BalanceMismatch {
+                    client: 4,
+                    field: "available",
+                    expected: 4.5,
+                    actual: 4.0
+                },
+                Difference::BalanceMismatch {
+                    client: 4,
+                    field: "total",
+                    expected: 4.5,
+                    actual: 4.0
+                },
+            ],
+            differences
+        );
+        assert_eq!("client 2: missing", differences[1].to_string());
+    }
+
+    #[test]
+    fn test_reconcile_duplicates() {
+        let actual = vec![make_row(1, 1.0, false), make_row(1, 1.0, false)];
+        let expected = vec![make_row(1, 1.0, false)];
+        assert_eq!(
+            vec![Difference::DuplicateClient {
+                client: 1,
+                file: "actual"
+            }],
+            reconcile(&actual, &expected, 0.0)
+        );
+    }
+
+    fn parse(data: &str) -> Vec<AccountRow> {
+        let rdr = csv::ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(data.as_bytes());
+        read_accounts_from(rdr).unwrap()
+    }
+
+    fn make_row(client: u16, available: f64, locked: bool) -> AccountRow {
+        AccountRow {
+            client,
+            available,
+            held: 0.0,
+            total: available,
+            locked,
+        }
+    }
+}
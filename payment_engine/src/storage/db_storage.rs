@@ -2,13 +2,14 @@ use std::error::Error;
 
 use r2d2::{Pool, PooledConnection};
 use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::{params, Connection, ErrorCode};
 
 use crate::processor::{
     record::Record,
     transaction::{Transaction, CORRECTING_TRANSACTION_TYPES},
 };
 
-use super::record_storage::RecordStorage;
+use super::record_storage::{DuplicateTransaction, RecordStorage};
 
 pub struct DbStorage {
     db_pool: Pool<SqliteConnectionManager>,
@@ -18,6 +19,33 @@ impl DbStorage {
     pub fn new(db_pool: Pool<SqliteConnectionManager>) -> Self {
         Self { db_pool }
     }
+
+    fn upsert_record(conn: &Connection, rec: &Record) -> Result<(), Box<dyn Error>> {
+        if let Some(locked) = rec.locked {
+            conn.execute(
+                "INSERT OR REPLACE INTO records (client, available, held, total, locked, credit_limit) values (?1, ?2, ?3, ?4, ?5, ?6)",
+                [&rec.client.to_string(), &rec.available.to_string(), &rec.held.to_string(), &rec.total.to_string(), &locked.to_string(), &rec.credit_limit.to_string()],
+            )?;
+        } else {
+            conn.execute(
+                "INSERT OR REPLACE INTO records (client, available, held, total, credit_limit) values (?1, ?2, ?3, ?4, ?5)",
+                [&rec.client.to_string(), &rec.available.to_string(), &rec.held.to_string(), &rec.total.to_string(), &rec.credit_limit.to_string()],
+            )?;
+        }
+        Ok(())
+    }
+
+    fn update_dispute_status(conn: &Connection, txn: &Transaction) -> Result<(), Box<dyn Error>> {
+        conn.execute(
+            "UPDATE transactions SET disp_st=:disp_st WHERE tx = :tx AND tx_type = :tx_type",
+            &[
+                (":disp_st", &txn.dispute_status.to_string()),
+                (":tx", &txn.tx.to_string()),
+                (":tx_type", &txn.tx_type.to_string()),
+            ],
+        )?;
+        Ok(())
+    }
 }
 
 impl RecordStorage for DbStorage {
@@ -26,15 +54,23 @@ impl RecordStorage for DbStorage {
         // normal deposit/withdrawal - has amount - insert it
         if !CORRECTING_TRANSACTION_TYPES.contains(&txn.tx_type) {
             conn.execute(
-                "INSERT INTO transactions (tx_type, client, tx, disp_st, amount) values (?1, ?2, ?3, ?4, ?5)",
-                [
+                "INSERT INTO transactions (tx_type, client, tx, disp_st, amount, destination) values (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
                     &txn.tx_type.to_string(),
                     &txn.client.to_string(),
                     &txn.tx.to_string(),
                     &txn.dispute_status.to_string(),
                     &txn.amount.unwrap_or_default().to_string(),
+                    &txn.destination.map(|d| d.to_string()),
                 ],
-            )?;
+            )
+            .map_err(|err| match err.sqlite_error_code() {
+                // tx is the primary key
+                Some(ErrorCode::ConstraintViolation) => {
+                    Box::new(DuplicateTransaction(txn.tx)) as Box<dyn Error>
+                }
+                _ => Box::new(err),
+            })?;
         } else {
             // corrective txn
             conn.execute(
@@ -87,19 +123,7 @@ impl RecordStorage for DbStorage {
 
     fn update_record(&mut self, rec: Record) -> Result<(), Box<dyn Error>> {
         let conn: PooledConnection<SqliteConnectionManager> = self.db_pool.get().unwrap();
-        if rec.locked.is_some() {
-            conn.execute(
-                "INSERT OR REPLACE INTO records (client, available, held, total, locked) values (?1, ?2, ?3, ?4, ?5)",
-                [&rec.client.to_string(), &rec.available.to_string(), &rec.held.to_string(), &rec.total.to_string(), &rec.locked.unwrap().to_string()],
-            )?;
-        } else {
-            conn.execute(
-                "INSERT OR REPLACE INTO records (client, available, held, total) values (?1, ?2, ?3, ?4)",
-                [&rec.client.to_string(), &rec.available.to_string(), &rec.held.to_string(), &rec.total.to_string()],
-            )?;
-        }
-
-        Ok(())
+        Self::upsert_record(&conn, &rec)
     }
 
     fn update_record_and_txn(
@@ -107,28 +131,22 @@ impl RecordStorage for DbStorage {
         rec: Record,
         txn: Transaction,
     ) -> Result<(), Box<dyn Error>> {
-        self.update_record(rec)?;
+        self.update_records(vec![rec], Some(txn))
+    }
+
+    fn update_records(
+        &mut self,
+        recs: Vec<Record>,
+        txn: Option<Transaction>,
+    ) -> Result<(), Box<dyn Error>> {
         let mut conn: PooledConnection<SqliteConnectionManager> = self.db_pool.get().unwrap();
         let tx = conn.transaction()?;
-        if rec.locked.is_some() {
-            tx.execute(
-                "INSERT OR REPLACE INTO records (client, available, held, total, locked) values (?1, ?2, ?3, ?4, ?5)",
-                [&rec.client.to_string(), &rec.available.to_string(), &rec.held.to_string(), &rec.total.to_string(), &rec.locked.unwrap().to_string()],
-            )?;
-        } else {
-            tx.execute(
-                "INSERT OR REPLACE INTO records (client, available, held, total) values (?1, ?2, ?3, ?4)",
-                [&rec.client.to_string(), &rec.available.to_string(), &rec.held.to_string(), &rec.total.to_string()],
-            )?;
+        for rec in recs.iter() {
+            Self::upsert_record(&tx, rec)?;
+        }
+        if let Some(txn) = txn {
+            Self::update_dispute_status(&tx, &txn)?;
         }
-        tx.execute(
-            "UPDATE transactions SET disp_st=:disp_st WHERE tx = :tx AND tx_type = :tx_type",
-            &[
-                (":disp_st", &txn.dispute_status.to_string()),
-                (":tx", &txn.tx.to_string()),
-                (":tx_type", &txn.tx_type.to_string()),
-            ],
-        )?;
         tx.commit().map_err(|e| Box::from(e.to_string()))
     }
 
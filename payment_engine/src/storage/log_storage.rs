@@ -0,0 +1,926 @@
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::processor::{
+    journal::{Account, JournalEntry, Posting},
+    record::Record,
+    transaction::{DisputeStatus, Transaction, TransactionType, CORRECTING_TRANSACTION_TYPES},
+};
+
+use super::record_storage::{DuplicateTransaction, RecordStorage};
+
+pub const LOG_FILE_NAME: &str = "storage.log";
+pub const INDEX_FILE_NAME: &str = "storage.idx";
+/// Superseded entries before the log is compacted, as long as there are at least as
+/// many of them as live ones.
+pub const DEFAULT_COMPACTION_THRESHOLD: usize = 10_000;
+/// Frames appended before the log is synced to disk.
+pub const DEFAULT_SYNC_INTERVAL: usize = 256;
+
+const LOG_MAGIC: &[u8; 4] = b"PELG";
+const INDEX_MAGIC: &[u8; 4] = b"PEIX";
+const FORMAT_VERSION: u32 = 1;
+const INDEX_VERSION: u32 = 3;
+/// Magic, version and generation.
+const HEADER_LEN: u64 = 16;
+/// Body length and checksum.
+const FRAME_HEADER_LEN: u64 = 8;
+
+const RECORD_ENTRY: u8 = 1;
+const TRANSACTION_ENTRY: u8 = 2;
+const JOURNAL_ENTRY: u8 = 3;
+const RECORD_ENTRY_LEN: usize = 37;
+const TRANSACTION_ENTRY_LEN: usize = 21;
+/// Journal entries are this long before their postings. Other entries are longer, so
+/// this much tells the length of any entry.
+const JOURNAL_ENTRY_HEAD_LEN: usize = 12;
+const POSTING_LEN: usize = 11;
+
+/// Stores records, transactions and the journal in an append-only binary log.
+///
+/// The log starts with a header carrying a generation number, followed by frames of
+/// `body length, checksum, entries`. Every write appends a single frame, so
+/// [`RecordStorage::update_records`] lands all or nothing, together with the journal
+/// entry the records are derived from. Only the position of the latest entry per client
+/// and transaction, and of every journal entry, is kept in memory; the entries
+/// themselves are read back from the log.
+///
+/// Frames are synced to disk in groups of `sync_interval`, and whenever the accounts
+/// are written out or the storage is dropped. A crash can lose the frames of the last
+/// group, but never part of a frame.
+///
+/// On open the positions are loaded from the index file, if it belongs to the same
+/// generation, and the rest of the log is replayed on top. A frame cut short by a crash
+/// fails its checksum and is truncated away. Once `compaction_threshold` entries have
+/// been superseded, and they outnumber the live ones, the live entries are rewritten
+/// into a new generation of the log. Each rewrite is paid for by at least as many
+/// dead entries, so compaction costs stay linear in the number of writes.
+pub struct LogStorage {
+    dir: PathBuf,
+    file: File,
+    generation: u64,
+    /// Length of the log up to the last complete frame.
+    len: u64,
+    records: BTreeMap<u16, u64>,
+    transactions: HashMap<u32, u64>,
+    /// Journal entries are never superseded, all of them are kept in posting order.
+    journal: Vec<u64>,
+    /// Written with the next update.
+    staged_journal_entry: Option<JournalEntry>,
+    /// Entries in the log that a later one has replaced.
+    dead_entries: usize,
+    compaction_threshold: usize,
+    frames_since_sync: usize,
+    sync_interval: usize,
+}
+
+impl LogStorage {
+    /// Opens the log in `dir`, creating both if they don't exist.
+    pub fn open<P: AsRef<Path>>(dir: P) -> Result<Self, Box<dyn Error>> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(dir.join(LOG_FILE_NAME))?;
+        if file.metadata()?.len() == 0 {
+            file.write_all(&encode_header(0))?;
+            file.sync_data()?;
+        }
+        let generation = read_header(&mut file)?;
+
+        let mut storage = LogStorage {
+            dir,
+            file,
+            generation,
+            len: HEADER_LEN,
+            records: BTreeMap::new(),
+            transactions: HashMap::new(),
+            journal: Vec::new(),
+            staged_journal_entry: None,
+            dead_entries: 0,
+            compaction_threshold: DEFAULT_COMPACTION_THRESHOLD,
+            frames_since_sync: 0,
+            sync_interval: DEFAULT_SYNC_INTERVAL,
+        };
+        storage.load_index();
+        storage.replay()?;
+        Ok(storage)
+    }
+
+    pub fn set_compaction_threshold(&mut self, dead_entries: usize) {
+        self.compaction_threshold = dead_entries.max(1);
+    }
+
+    pub fn set_sync_interval(&mut self, frames: usize) {
+        self.sync_interval = frames.max(1);
+    }
+
+    /// Syncs the frames appended since the last sync to disk.
+    pub fn sync(&mut self) -> Result<(), Box<dyn Error>> {
+        if self.frames_since_sync > 0 {
+            self.file.sync_data()?;
+            self.frames_since_sync = 0;
+        }
+        Ok(())
+    }
+
+    /// Rewrites the log with only the latest entry per client and transaction, and the
+    /// journal.
+    pub fn compact(&mut self) -> Result<(), Box<dyn Error>> {
+        let mut entries = Vec::new();
+        let offsets = self.records.values().chain(self.transactions.values());
+        for offset in offsets.chain(self.journal.iter()) {
+            entries.push(self.read_entry(*offset)?);
+        }
+
+        let generation = self.generation + 1;
+        let tmp_path = self.dir.join(format!("{}.tmp", LOG_FILE_NAME));
+        let mut tmp = File::create(&tmp_path)?;
+        tmp.write_all(&encode_header(generation))?;
+        let (frame, offsets) = encode_frame(HEADER_LEN, &entries);
+        tmp.write_all(&frame)?;
+        tmp.sync_all()?;
+        drop(tmp);
+        fs::rename(&tmp_path, self.dir.join(LOG_FILE_NAME))?;
+
+        self.file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(self.dir.join(LOG_FILE_NAME))?;
+        self.generation = generation;
+        self.len = HEADER_LEN + frame.len() as u64;
+        self.records.clear();
+        self.transactions.clear();
+        self.journal.clear();
+        for (entry, offset) in entries.iter().zip(offsets) {
+            self.index_entry(entry, offset);
+        }
+        self.dead_entries = 0;
+        self.frames_since_sync = 0;
+        self.write_index()
+    }
+
+    /// Saves the entry positions, so the next open only replays what comes after.
+    pub fn write_index(&mut self) -> Result<(), Box<dyn Error>> {
+        // the index mustn't cover frames that aren't on disk yet
+        self.sync()?;
+        let mut buf = Vec::new();
+        buf.extend_from_slice(INDEX_MAGIC);
+        buf.extend_from_slice(&INDEX_VERSION.to_le_bytes());
+        buf.extend_from_slice(&self.generation.to_le_bytes());
+        buf.extend_from_slice(&self.len.to_le_bytes());
+        buf.extend_from_slice(&(self.dead_entries as u64).to_le_bytes());
+        buf.extend_from_slice(&(self.records.len() as u32).to_le_bytes());
+        for (client, offset) in self.records.iter() {
+            buf.extend_from_slice(&client.to_le_bytes());
+            buf.extend_from_slice(&offset.to_le_bytes());
+        }
+        buf.extend_from_slice(&(self.transactions.len() as u32).to_le_bytes());
+        for (tx, offset) in self.transactions.iter() {
+            buf.extend_from_slice(&tx.to_le_bytes());
+            buf.extend_from_slice(&offset.to_le_bytes());
+        }
+        buf.extend_from_slice(&(self.journal.len() as u32).to_le_bytes());
+        for offset in self.journal.iter() {
+            buf.extend_from_slice(&offset.to_le_bytes());
+        }
+        buf.extend_from_slice(&checksum(&buf).to_le_bytes());
+
+        // written aside first, so a crash never leaves half an index behind
+        let tmp_path = self.dir.join(format!("{}.tmp", INDEX_FILE_NAME));
+        let mut tmp = File::create(&tmp_path)?;
+        tmp.write_all(&buf)?;
+        tmp.sync_all()?;
+        fs::rename(tmp_path, self.dir.join(INDEX_FILE_NAME))?;
+        Ok(())
+    }
+
+    /// Picks up the positions from the index file, unless it's missing, damaged or
+    /// belongs to another generation of the log. Anything it doesn't cover is replayed.
+    fn load_index(&mut self) {
+        let Ok(buf) = fs::read(self.dir.join(INDEX_FILE_NAME)) else {
+            return;
+        };
+        if let Some(positions) = decode_index(&buf, self.generation) {
+            self.len = positions.len;
+            self.dead_entries = positions.dead_entries;
+            self.records = positions.records;
+            self.transactions = positions.transactions;
+            self.journal = positions.journal;
+        }
+    }
+
+    /// Applies every complete frame after `self.len` and cuts off a torn one at the end.
+    fn replay(&mut self) -> Result<(), Box<dyn Error>> {
+        let file_len = self.file.metadata()?.len();
+        if self.len > file_len {
+            // the index is ahead of the log, so it can't be trusted
+            self.len = HEADER_LEN;
+            self.dead_entries = 0;
+            self.records.clear();
+            self.transactions.clear();
+            self.journal.clear();
+        }
+        self.file.seek(SeekFrom::Start(self.len))?;
+        let mut rest = Vec::new();
+        self.file.read_to_end(&mut rest)?;
+
+        let mut pos = 0;
+        while let Some((body, frame_len)) = next_frame(&rest[pos..]) {
+            let body_offset = self.len + FRAME_HEADER_LEN;
+            let mut entry_pos = 0;
+            while entry_pos < body.len() {
+                let (entry, entry_len) = decode_entry(&body[entry_pos..])?;
+                self.index_entry(&entry, body_offset + entry_pos as u64);
+                entry_pos += entry_len;
+            }
+            self.len += frame_len as u64;
+            pos += frame_len;
+        }
+        if self.len < file_len {
+            self.file.set_len(self.len)?;
+            self.file.sync_data()?;
+        }
+        Ok(())
+    }
+
+    fn append(&mut self, entries: &[Entry]) -> Result<(), Box<dyn Error>> {
+        let (frame, offsets) = encode_frame(self.len, entries);
+        self.file.seek(SeekFrom::Start(self.len))?;
+        self.file.write_all(&frame)?;
+        self.len += frame.len() as u64;
+        for (entry, offset) in entries.iter().zip(offsets) {
+            self.index_entry(entry, offset);
+        }
+
+        self.frames_since_sync += 1;
+        if self.dead_entries >= self.compaction_threshold.max(self.live_entries()) {
+            self.compact()
+        } else if self.frames_since_sync >= self.sync_interval {
+            self.sync()
+        } else {
+            Ok(())
+        }
+    }
+
+    fn live_entries(&self) -> usize {
+        self.records.len() + self.transactions.len() + self.journal.len()
+    }
+
+    fn index_entry(&mut self, entry: &Entry, offset: u64) {
+        let replaced = match entry {
+            Entry::Record(rec) => self.records.insert(rec.client, offset),
+            Entry::Transaction(txn) => self.transactions.insert(txn.tx, offset),
+            Entry::Journal(_) => {
+                self.journal.push(offset);
+                None
+            }
+        };
+        self.dead_entries += replaced.is_some() as usize;
+    }
+
+    fn read_entry(&self, offset: u64) -> Result<Entry, Box<dyn Error>> {
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(offset))?;
+        let mut buf = vec![0u8; JOURNAL_ENTRY_HEAD_LEN];
+        file.read_exact(&mut buf)?;
+        buf.resize(entry_len(&buf)?, 0);
+        file.read_exact(&mut buf[JOURNAL_ENTRY_HEAD_LEN..])?;
+        Ok(decode_entry(&buf)?.0)
+    }
+}
+
+impl Drop for LogStorage {
+    fn drop(&mut self) {
+        // best effort; without it the next open replays a bit more of the log, or loses
+        // the frames that weren't synced yet
+        _ = self.write_index();
+    }
+}
+
+impl RecordStorage for LogStorage {
+    fn store_transaction(&mut self, txn: Transaction) -> Result<(), Box<dyn Error>> {
+        if CORRECTING_TRANSACTION_TYPES.contains(&txn.tx_type) {
+            // corrections only change the dispute status of the transaction they refer to
+            return Ok(());
+        }
+        if self.transactions.contains_key(&txn.tx) {
+            return Err(Box::new(DuplicateTransaction(txn.tx)));
+        }
+        self.append(&[Entry::Transaction(txn)])
+    }
+
+    fn get_transaction(&mut self, tx_id: u32) -> Result<Option<Transaction>, Box<dyn Error>> {
+        match self.transactions.get(&tx_id) {
+            Some(offset) => match self.read_entry(*offset)? {
+                Entry::Transaction(txn) => Ok(Some(txn)),
+                _ => Err(From::from(format!("bad index entry for tx {}", tx_id))),
+            },
+            None => Ok(None),
+        }
+    }
+
+    fn get_client_record(&self, client_id: u16) -> Result<Record, Box<dyn Error>> {
+        match self.records.get(&client_id) {
+            Some(offset) => match self.read_entry(*offset)? {
+                Entry::Record(rec) => Ok(rec),
+                _ => Err(From::from(format!(
+                    "bad index entry for client {}",
+                    client_id
+                ))),
+            },
+            None => Ok(Record::new(client_id)),
+        }
+    }
+
+    fn update_record(&mut self, rec: Record) -> Result<(), Box<dyn Error>> {
+        self.update_records(vec![rec], None)
+    }
+
+    fn update_record_and_txn(
+        &mut self,
+        rec: Record,
+        txn: Transaction,
+    ) -> Result<(), Box<dyn Error>> {
+        self.update_records(vec![rec], Some(txn))
+    }
+
+    fn update_records(
+        &mut self,
+        recs: Vec<Record>,
+        txn: Option<Transaction>,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut entries: Vec<Entry> = self
+            .staged_journal_entry
+            .take()
+            .into_iter()
+            .map(Entry::Journal)
+            .collect();
+        entries.extend(recs.into_iter().map(Entry::Record));
+        entries.extend(txn.map(Entry::Transaction));
+        self.append(&entries)
+    }
+
+    fn write_records<W>(&self, mut wtr: csv::Writer<W>) -> Result<(), Box<dyn Error>>
+    where
+        W: io::Write + 'static,
+    {
+        for client in self.records.keys() {
+            wtr.serialize(self.get_client_record(*client)?)?;
+        }
+        wtr.flush()?;
+        // the accounts shouldn't report anything that isn't on disk yet
+        self.file.sync_data()?;
+        Ok(())
+    }
+
+    fn stage_journal_entry(&mut self, entry: &JournalEntry) {
+        self.staged_journal_entry = Some(entry.clone());
+    }
+
+    fn journal_entries(&self) -> Result<Vec<JournalEntry>, Box<dyn Error>> {
+        self.journal
+            .iter()
+            .map(|offset| match self.read_entry(*offset)? {
+                Entry::Journal(entry) => Ok(entry),
+                _ => Err(From::from(format!(
+                    "bad index entry for journal entry at {}",
+                    offset
+                ))),
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Entry {
+    Record(Record),
+    Transaction(Transaction),
+    Journal(JournalEntry),
+}
+
+fn encode_header(generation: u64) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(HEADER_LEN as usize);
+    buf.extend_from_slice(LOG_MAGIC);
+    buf.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    buf.extend_from_slice(&generation.to_le_bytes());
+    buf
+}
+
+/// Returns the generation of the log.
+fn read_header(file: &mut File) -> Result<u64, Box<dyn Error>> {
+    let mut buf = [0u8; HEADER_LEN as usize];
+    file.seek(SeekFrom::Start(0))?;
+    file.read_exact(&mut buf)
+        .map_err(|_| "storage log is too short for its header")?;
+    if &buf[0..4] != LOG_MAGIC {
+        return Err(From::from("not a storage log"));
+    }
+    let version = u32::from_le_bytes(buf[4..8].try_into()?);
+    if version != FORMAT_VERSION {
+        return Err(From::from(format!(
+            "unsupported storage log version {}",
+            version
+        )));
+    }
+    Ok(u64::from_le_bytes(buf[8..16].try_into()?))
+}
+
+/// Encodes `entries` as one frame starting at `frame_offset` in the log and returns it
+/// together with the log offset of each entry.
+fn encode_frame(frame_offset: u64, entries: &[Entry]) -> (Vec<u8>, Vec<u64>) {
+    let mut body = Vec::new();
+    let mut offsets = Vec::with_capacity(entries.len());
+    for entry in entries {
+        offsets.push(frame_offset + FRAME_HEADER_LEN + body.len() as u64);
+        encode_entry(entry, &mut body);
+    }
+    let mut frame = Vec::with_capacity(FRAME_HEADER_LEN as usize + body.len());
+    frame.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    frame.extend_from_slice(&checksum(&body).to_le_bytes());
+    frame.extend_from_slice(&body);
+    (frame, offsets)
+}
+
+/// Returns the body and total length of the frame at the start of `buf`, if it's
+/// complete and its checksum matches.
+fn next_frame(buf: &[u8]) -> Option<(&[u8], usize)> {
+    let header = buf.get(..FRAME_HEADER_LEN as usize)?;
+    let body_len = u32::from_le_bytes(header[0..4].try_into().ok()?) as usize;
+    let expected = u32::from_le_bytes(header[4..8].try_into().ok()?);
+    let frame_len = FRAME_HEADER_LEN as usize + body_len;
+    let body = buf.get(FRAME_HEADER_LEN as usize..frame_len)?;
+    (checksum(body) == expected).then_some((body, frame_len))
+}
+
+/// Length of the entry that starts with `head`, which has to be at least
+/// [`JOURNAL_ENTRY_HEAD_LEN`] bytes long.
+fn entry_len(head: &[u8]) -> Result<usize, Box<dyn Error>> {
+    match head[0] {
+        RECORD_ENTRY => Ok(RECORD_ENTRY_LEN),
+        TRANSACTION_ENTRY => Ok(TRANSACTION_ENTRY_LEN),
+        JOURNAL_ENTRY => Ok(JOURNAL_ENTRY_HEAD_LEN + head[11] as usize * POSTING_LEN),
+        x => Err(From::from(format!("unknown storage log entry kind {}", x))),
+    }
+}
+
+fn encode_entry(entry: &Entry, buf: &mut Vec<u8>) {
+    match entry {
+        Entry::Record(rec) => {
+            buf.push(RECORD_ENTRY);
+            buf.extend_from_slice(&rec.client.to_le_bytes());
+            buf.extend_from_slice(&rec.available.to_le_bytes());
+            buf.extend_from_slice(&rec.held.to_le_bytes());
+            buf.extend_from_slice(&rec.total.to_le_bytes());
+            buf.push(rec.locked.is_some() as u8);
+            buf.push(rec.locked.unwrap_or_default());
+            buf.extend_from_slice(&rec.credit_limit.to_le_bytes());
+        }
+        Entry::Transaction(txn) => {
+            buf.push(TRANSACTION_ENTRY);
+            buf.push(tx_type_to_byte(txn.tx_type));
+            buf.extend_from_slice(&txn.client.to_le_bytes());
+            buf.extend_from_slice(&txn.tx.to_le_bytes());
+            buf.push(txn.amount.is_some() as u8);
+            buf.extend_from_slice(&txn.amount.unwrap_or_default().to_le_bytes());
+            buf.push(txn.destination.is_some() as u8);
+            buf.extend_from_slice(&txn.destination.unwrap_or_default().to_le_bytes());
+            buf.push(dispute_status_to_byte(txn.dispute_status));
+        }
+        Entry::Journal(entry) => {
+            buf.push(JOURNAL_ENTRY);
+            buf.extend_from_slice(&entry.tx.to_le_bytes());
+            buf.extend_from_slice(&entry.client.to_le_bytes());
+            buf.push(tx_type_to_byte(entry.tx_type));
+            buf.push(entry.locked_client.is_some() as u8);
+            buf.extend_from_slice(&entry.locked_client.unwrap_or_default().to_le_bytes());
+            buf.push(entry.postings.len() as u8);
+            for posting in entry.postings.iter() {
+                let (kind, client) = account_to_bytes(posting.account);
+                buf.push(kind);
+                buf.extend_from_slice(&client.to_le_bytes());
+                buf.extend_from_slice(&posting.amount.to_le_bytes());
+            }
+        }
+    }
+}
+
+/// Decodes the entry at the start of `buf` and returns it with its length.
+fn decode_entry(buf: &[u8]) -> Result<(Entry, usize), Box<dyn Error>> {
+    let head = buf
+        .get(..JOURNAL_ENTRY_HEAD_LEN)
+        .ok_or("truncated storage log entry")?;
+    let len = entry_len(head)?;
+    let buf = buf.get(..len).ok_or("truncated storage log entry")?;
+    let entry = match buf[0] {
+        RECORD_ENTRY => Entry::Record(Record {
+            client: u16::from_le_bytes(buf[1..3].try_into()?),
+            available: f64::from_le_bytes(buf[3..11].try_into()?),
+            held: f64::from_le_bytes(buf[11..19].try_into()?),
+            total: f64::from_le_bytes(buf[19..27].try_into()?),
+            locked: (buf[27] != 0).then_some(buf[28]),
+            credit_limit: f64::from_le_bytes(buf[29..37].try_into()?),
+        }),
+        TRANSACTION_ENTRY => Entry::Transaction(Transaction {
+            tx_type: tx_type_from_byte(buf[1])?,
+            client: u16::from_le_bytes(buf[2..4].try_into()?),
+            tx: u32::from_le_bytes(buf[4..8].try_into()?),
+            amount: (buf[8] != 0).then(|| f64::from_le_bytes(buf[9..17].try_into().unwrap())),
+            destination: (buf[17] != 0)
+                .then(|| u16::from_le_bytes(buf[18..20].try_into().unwrap())),
+            dispute_status: dispute_status_from_byte(buf[20])?,
+        }),
+        _ => Entry::Journal(JournalEntry {
+            tx: u32::from_le_bytes(buf[1..5].try_into()?),
+            client: u16::from_le_bytes(buf[5..7].try_into()?),
+            tx_type: tx_type_from_byte(buf[7])?,
+            locked_client: (buf[8] != 0)
+                .then(|| u16::from_le_bytes(buf[9..11].try_into().unwrap())),
+            postings: buf[JOURNAL_ENTRY_HEAD_LEN..]
+                .chunks_exact(POSTING_LEN)
+                .map(|posting| {
+                    Ok(Posting {
+                        account: account_from_bytes(
+                            posting[0],
+                            u16::from_le_bytes(posting[1..3].try_into()?),
+                        )?,
+                        amount: f64::from_le_bytes(posting[3..11].try_into()?),
+                    })
+                })
+                .collect::<Result<Vec<Posting>, Box<dyn Error>>>()?,
+        }),
+    };
+    Ok((entry, len))
+}
+
+/// What the index file holds.
+struct Positions {
+    /// Length of the log the index covers.
+    len: u64,
+    /// Superseded entries in that part of the log.
+    dead_entries: usize,
+    records: BTreeMap<u16, u64>,
+    transactions: HashMap<u32, u64>,
+    journal: Vec<u64>,
+}
+
+/// Returns the positions in the index, if it's intact and belongs to `generation`.
+fn decode_index(buf: &[u8], generation: u64) -> Option<Positions> {
+    let (content, sum) = buf.split_at(buf.len().checked_sub(4)?);
+    if checksum(content) != u32::from_le_bytes(sum.try_into().ok()?) {
+        return None;
+    }
+    let mut pos = 0;
+    let mut take = |n: usize| -> Option<&[u8]> {
+        let bytes = content.get(pos..pos + n)?;
+        pos += n;
+        Some(bytes)
+    };
+    if take(4)? != INDEX_MAGIC
+        || u32::from_le_bytes(take(4)?.try_into().ok()?) != INDEX_VERSION
+        || u64::from_le_bytes(take(8)?.try_into().ok()?) != generation
+    {
+        return None;
+    }
+    let len = u64::from_le_bytes(take(8)?.try_into().ok()?);
+    let dead_entries = u64::from_le_bytes(take(8)?.try_into().ok()?) as usize;
+
+    let mut records = BTreeMap::new();
+    for _ in 0..u32::from_le_bytes(take(4)?.try_into().ok()?) {
+        let client = u16::from_le_bytes(take(2)?.try_into().ok()?);
+        records.insert(client, u64::from_le_bytes(take(8)?.try_into().ok()?));
+    }
+    let mut transactions = HashMap::new();
+    for _ in 0..u32::from_le_bytes(take(4)?.try_into().ok()?) {
+        let tx = u32::from_le_bytes(take(4)?.try_into().ok()?);
+        transactions.insert(tx, u64::from_le_bytes(take(8)?.try_into().ok()?));
+    }
+    let mut journal = Vec::new();
+    for _ in 0..u32::from_le_bytes(take(4)?.try_into().ok()?) {
+        journal.push(u64::from_le_bytes(take(8)?.try_into().ok()?));
+    }
+    Some(Positions {
+        len,
+        dead_entries,
+        records,
+        transactions,
+        journal,
+    })
+}
+
+fn account_to_bytes(account: Account) -> (u8, u16) {
+    match account {
+        Account::ClientAvailable(client) => (0, client),
+        Account::ClientHeld(client) => (1, client),
+        Account::ExternalSettlement => (2, 0),
+        Account::ChargebackLoss => (3, 0),
+    }
+}
+
+fn account_from_bytes(kind: u8, client: u16) -> Result<Account, Box<dyn Error>> {
+    match kind {
+        0 => Ok(Account::ClientAvailable(client)),
+        1 => Ok(Account::ClientHeld(client)),
+        2 => Ok(Account::ExternalSettlement),
+        3 => Ok(Account::ChargebackLoss),
+        x => Err(From::from(format!("unknown ledger account {}", x))),
+    }
+}
+
+fn tx_type_to_byte(tx_type: TransactionType) -> u8 {
+    match tx_type {
+        TransactionType::Deposit => 0,
+        TransactionType::Withdrawal => 1,
+        TransactionType::Dispute => 2,
+        TransactionType::Resolve => 3,
+        TransactionType::Chargeback => 4,
+        TransactionType::Transfer => 5,
+    }
+}
+
+fn tx_type_from_byte(byte: u8) -> Result<TransactionType, Box<dyn Error>> {
+    match byte {
+        0 => Ok(TransactionType::Deposit),
+        1 => Ok(TransactionType::Withdrawal),
+        2 => Ok(TransactionType::Dispute),
+        3 => Ok(TransactionType::Resolve),
+        4 => Ok(TransactionType::Chargeback),
+        5 => Ok(TransactionType::Transfer),
+        x => Err(From::from(format!("unknown transaction type {}", x))),
+    }
+}
+
+fn dispute_status_to_byte(status: DisputeStatus) -> u8 {
+    match status {
+        DisputeStatus::None => 0,
+        DisputeStatus::Disputed => 1,
+        DisputeStatus::Resolved => 2,
+        DisputeStatus::Chargedback => 3,
+    }
+}
+
+fn dispute_status_from_byte(byte: u8) -> Result<DisputeStatus, Box<dyn Error>> {
+    match byte {
+        0 => Ok(DisputeStatus::None),
+        1 => Ok(DisputeStatus::Disputed),
+        2 => Ok(DisputeStatus::Resolved),
+        3 => Ok(DisputeStatus::Chargedback),
+        x => Err(From::from(format!("unknown dispute status {}", x))),
+    }
+}
+
+/// 32-bit FNV-1a, enough to tell a torn write from a complete one.
+fn checksum(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c_9dc5, |hash: u32, byte| {
+        (hash ^ *byte as u32).wrapping_mul(0x0100_0193)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::{self, OpenOptions},
+        io::Write,
+        path::PathBuf,
+    };
+
+    use super::{LogStorage, INDEX_FILE_NAME, LOG_FILE_NAME};
+    use crate::{
+        processor::{
+            journal::JournalEntry,
+            record::Record,
+            transaction::{DisputeStatus, Transaction, TransactionType},
+        },
+        storage::record_storage::{DuplicateTransaction, RecordStorage},
+    };
+
+    #[test]
+    fn test_store_and_get() {
+        let dir = test_dir("store_and_get");
+        let mut storage = LogStorage::open(&dir).unwrap();
+        let txn = make_deposit(1, 1, 1.5);
+        storage.store_transaction(txn).unwrap();
+        assert!(storage.store_transaction(txn).is_err());
+        assert_eq!(Some(txn), storage.get_transaction(1).unwrap());
+        assert_eq!(None, storage.get_transaction(2).unwrap());
+
+        storage.update_record(make_record(1, 1.5)).unwrap();
+        assert_eq!(make_record(1, 1.5), storage.get_client_record(1).unwrap());
+        assert_eq!(Record::new(2), storage.get_client_record(2).unwrap());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_reopen_replays_log() {
+        let dir = test_dir("reopen");
+        {
+            let mut storage = LogStorage::open(&dir).unwrap();
+            let txn = make_deposit(1, 1, 2.0);
+            storage.store_transaction(txn).unwrap();
+            storage.update_record(make_record(1, 2.0)).unwrap();
+            let disputed = Transaction {
+                dispute_status: DisputeStatus::Disputed,
+                ..txn
+            };
+            storage
+                .update_records(vec![make_record(1, 0.5)], Some(disputed))
+                .unwrap();
+        }
+        // without the index everything comes from the log
+        fs::remove_file(dir.join(INDEX_FILE_NAME)).unwrap();
+
+        let mut storage = LogStorage::open(&dir).unwrap();
+        assert_eq!(make_record(1, 0.5), storage.get_client_record(1).unwrap());
+        assert_eq!(
+            DisputeStatus::Disputed,
+            storage.get_transaction(1).unwrap().unwrap().dispute_status
+        );
+        drop(storage);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_index_is_combined_with_newer_frames() {
+        let dir = test_dir("index");
+        let mut storage = LogStorage::open(&dir).unwrap();
+        storage.update_record(make_record(1, 1.0)).unwrap();
+        storage.write_index().unwrap();
+        storage.update_record(make_record(2, 2.0)).unwrap();
+        // skip the write on drop, so the index only covers the first frame
+        std::mem::forget(storage);
+
+        let storage = LogStorage::open(&dir).unwrap();
+        assert_eq!(make_record(1, 1.0), storage.get_client_record(1).unwrap());
+        assert_eq!(make_record(2, 2.0), storage.get_client_record(2).unwrap());
+        drop(storage);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_torn_frame_is_truncated() {
+        let dir = test_dir("torn");
+        {
+            let mut storage = LogStorage::open(&dir).unwrap();
+            storage.update_record(make_record(1, 1.0)).unwrap();
+        }
+        let len = fs::metadata(dir.join(LOG_FILE_NAME)).unwrap().len();
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(dir.join(LOG_FILE_NAME))
+            .unwrap();
+        // a frame header announcing more than was written
+        file.write_all(&[37, 0, 0, 0, 1, 2, 3, 4, 1, 1]).unwrap();
+        drop(file);
+
+        let mut storage = LogStorage::open(&dir).unwrap();
+        assert_eq!(len, fs::metadata(dir.join(LOG_FILE_NAME)).unwrap().len());
+        assert_eq!(make_record(1, 1.0), storage.get_client_record(1).unwrap());
+        storage.update_record(make_record(1, 3.0)).unwrap();
+        drop(storage);
+        let storage = LogStorage::open(&dir).unwrap();
+        assert_eq!(make_record(1, 3.0), storage.get_client_record(1).unwrap());
+        drop(storage);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_compaction() {
+        let dir = test_dir("compaction");
+        let mut storage = LogStorage::open(&dir).unwrap();
+        storage.set_compaction_threshold(5);
+        storage.store_transaction(make_deposit(1, 7, 1.0)).unwrap();
+        for i in 0..6 {
+            storage.update_record(make_record(1, i as f64)).unwrap();
+        }
+        // the fifth superseded record triggered a compaction down to one entry per key
+        assert_eq!(1, storage.generation);
+        assert_eq!(0, storage.dead_entries);
+        let compacted_len = fs::metadata(dir.join(LOG_FILE_NAME)).unwrap().len();
+        assert_eq!(16 + 8 + 37 + 21, compacted_len);
+        assert_eq!(make_record(1, 5.0), storage.get_client_record(1).unwrap());
+        assert!(storage.get_transaction(7).unwrap().is_some());
+
+        storage.update_record(make_record(2, 2.0)).unwrap();
+        drop(storage);
+        let storage = LogStorage::open(&dir).unwrap();
+        assert_eq!(make_record(1, 5.0), storage.get_client_record(1).unwrap());
+        assert_eq!(make_record(2, 2.0), storage.get_client_record(2).unwrap());
+        drop(storage);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_compaction_waits_for_dead_entries_to_outnumber_live_ones() {
+        let dir = test_dir("compaction_live");
+        let mut storage = LogStorage::open(&dir).unwrap();
+        storage.set_compaction_threshold(2);
+        for tx in 0..10 {
+            storage.store_transaction(make_deposit(1, tx, 1.0)).unwrap();
+        }
+        for i in 0..11 {
+            storage.update_record(make_record(1, i as f64)).unwrap();
+        }
+        // ten superseded records against eleven live entries
+        assert_eq!(0, storage.generation);
+        assert_eq!(10, storage.dead_entries);
+        drop(storage);
+
+        // the dead entries are remembered by the index
+        let mut storage = LogStorage::open(&dir).unwrap();
+        assert_eq!(10, storage.dead_entries);
+        storage.set_compaction_threshold(2);
+        storage.update_record(make_record(1, 11.0)).unwrap();
+        assert_eq!(1, storage.generation);
+        assert_eq!(make_record(1, 11.0), storage.get_client_record(1).unwrap());
+        drop(storage);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_syncs_in_groups() {
+        let dir = test_dir("sync");
+        let mut storage = LogStorage::open(&dir).unwrap();
+        storage.set_sync_interval(3);
+        storage.update_record(make_record(1, 1.0)).unwrap();
+        storage.update_record(make_record(2, 2.0)).unwrap();
+        assert_eq!(2, storage.frames_since_sync);
+        storage.update_record(make_record(3, 3.0)).unwrap();
+        assert_eq!(0, storage.frames_since_sync);
+
+        storage.update_record(make_record(4, 4.0)).unwrap();
+        storage.write_index().unwrap();
+        assert_eq!(0, storage.frames_since_sync);
+        drop(storage);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_journal_is_written_with_the_records() {
+        let dir = test_dir("journal");
+        let txn = make_deposit(1, 1, 2.0);
+        let entry = JournalEntry::deposit(&txn, 2.0);
+        {
+            let mut storage = LogStorage::open(&dir).unwrap();
+            storage.store_transaction(txn).unwrap();
+            assert!(matches!(
+                storage.store_transaction(txn).unwrap_err().downcast_ref(),
+                Some(DuplicateTransaction(1))
+            ));
+            storage.stage_journal_entry(&entry);
+            storage.update_record(make_record(1, 2.0)).unwrap();
+            // staged entries go out with one update only
+            storage.update_record(make_record(2, 0.0)).unwrap();
+        }
+        fs::remove_file(dir.join(INDEX_FILE_NAME)).unwrap();
+
+        let mut storage = LogStorage::open(&dir).unwrap();
+        assert_eq!(vec![entry.clone()], storage.journal_entries().unwrap());
+        storage.compact().unwrap();
+        assert_eq!(vec![entry.clone()], storage.journal_entries().unwrap());
+        drop(storage);
+        let storage = LogStorage::open(&dir).unwrap();
+        assert_eq!(vec![entry], storage.journal_entries().unwrap());
+        drop(storage);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_rejects_foreign_file() {
+        let dir = test_dir("foreign");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join(LOG_FILE_NAME), b"client,available\n1,1.0\n").unwrap();
+        assert!(LogStorage::open(&dir).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("payment_engine_log_storage_{}", name));
+        _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn make_deposit(client: u16, tx: u32, amount: f64) -> Transaction {
+        Transaction {
+            tx_type: TransactionType::Deposit,
+            client,
+            tx,
+            amount: Some(amount),
+            destination: None,
+            dispute_status: DisputeStatus::None,
+        }
+    }
+
+    fn make_record(client: u16, available: f64) -> Record {
+        Record {
+            available,
+            total: available,
+            ..Record::new(client)
+        }
+    }
+}
@@ -5,7 +5,7 @@ use crate::processor::{
     transaction::{Transaction, CORRECTING_TRANSACTION_TYPES},
 };
 
-use super::record_storage::RecordStorage;
+use super::record_storage::{DuplicateTransaction, RecordStorage};
 
 pub struct MemStorage {
     // maps tx_id to Transaction
@@ -32,6 +32,9 @@ impl Default for MemStorage {
 impl RecordStorage for MemStorage {
     fn store_transaction(&mut self, txn: Transaction) -> Result<(), Box<dyn Error>> {
         if !CORRECTING_TRANSACTION_TYPES.contains(&txn.tx_type) {
+            if self.transactions.contains_key(&txn.tx.to_string()) {
+                return Err(Box::new(DuplicateTransaction(txn.tx)));
+            }
             self.transactions.insert(txn.tx.to_string(), txn);
         } // we don's store corrections in this case
         Ok(())
@@ -68,11 +71,28 @@ impl RecordStorage for MemStorage {
         Ok(())
     }
 
+    fn update_records(
+        &mut self,
+        recs: Vec<Record>,
+        txn: Option<Transaction>,
+    ) -> Result<(), Box<dyn Error>> {
+        for rec in recs {
+            self.records.insert(rec.client.to_string(), rec);
+        }
+        if let Some(txn) = txn {
+            self.transactions.insert(txn.tx.to_string(), txn);
+        }
+        Ok(())
+    }
+
     fn write_records<W>(&self, mut wtr: csv::Writer<W>) -> Result<(), Box<dyn Error>>
     where
         W: std::io::Write + 'static,
     {
-        for record in self.records.values() {
+        // sorted so the output doesn't depend on the map's iteration order
+        let mut records: Vec<&Record> = self.records.values().collect();
+        records.sort_by_key(|record| record.client);
+        for record in records {
             wtr.serialize(record)?
         }
         wtr.flush()?;
@@ -1,3 +1,4 @@
 pub mod db_storage;
+pub mod log_storage;
 pub mod mem_storage;
 pub mod record_storage;
@@ -1,18 +1,45 @@
+use std::fmt::Display;
 use std::{error::Error, io};
 
-use crate::processor::{record::Record, transaction::Transaction};
+use crate::processor::{journal::JournalEntry, record::Record, transaction::Transaction};
 use csv::Writer;
 use mockall::mock;
 
+/// Returned by [`RecordStorage::store_transaction`] for an id that is already taken.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DuplicateTransaction(pub u32);
+
+impl Display for DuplicateTransaction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "transaction {} already stored", self.0)
+    }
+}
+
+impl Error for DuplicateTransaction {}
+
 pub trait RecordStorage {
+    /// Fails with [`DuplicateTransaction`] if a transaction with the same id is stored.
     fn store_transaction(&mut self, t: Transaction) -> Result<(), Box<dyn Error>>;
     fn get_transaction(&mut self, tx_id: u32) -> Result<Option<Transaction>, Box<dyn Error>>;
     fn get_client_record(&self, client_id: u16) -> Result<Record, Box<dyn Error>>;
     fn update_record(&mut self, r: Record) -> Result<(), Box<dyn Error>>;
     fn update_record_and_txn(&mut self, r: Record, t: Transaction) -> Result<(), Box<dyn Error>>;
+    /// Updates several records, and optionally a transaction, all or nothing.
+    fn update_records(
+        &mut self,
+        rs: Vec<Record>,
+        t: Option<Transaction>,
+    ) -> Result<(), Box<dyn Error>>;
     fn write_records<W>(&self, wtr: Writer<W>) -> Result<(), Box<dyn Error>>
     where
         W: io::Write + 'static;
+    /// Hands over the journal entry the records of the next update are derived from.
+    /// Storage that outlives a run writes it with that update, all or nothing.
+    fn stage_journal_entry(&mut self, _entry: &JournalEntry) {}
+    /// Journal entries written by earlier runs, in posting order.
+    fn journal_entries(&self) -> Result<Vec<JournalEntry>, Box<dyn Error>> {
+        Ok(Vec::new())
+    }
 }
 
 mock! {
@@ -23,6 +50,7 @@ mock! {
         fn get_client_record(&self, client_id: u16) -> Result<Record, Box<dyn Error>>;
         fn update_record(&mut self, r: Record) -> Result<(), Box<dyn Error>>;
         fn update_record_and_txn(&mut self, r: Record, t: Transaction) -> Result<(), Box<dyn Error>>;
+        fn update_records(&mut self, rs: Vec<Record>, t: Option<Transaction>) -> Result<(), Box<dyn Error>>;
         fn write_records<W>(&self, wtr: Writer<W>) -> Result<(), Box<dyn Error>> where W: io::Write + 'static;
     }
 }
@@ -1,5 +1 @@
-pub mod simple_test;
-pub mod test1;
-pub mod test2;
-
-pub const FILE_OUT_NAME: &str = "./resources/tmp.csv";
+pub mod pipeline_test;
@@ -0,0 +1,115 @@
+use std::{fs, process};
+
+use csv::Writer;
+use payment_engine::{
+    processor::{
+        credit_limit::CreditLimits,
+        pipeline::run_pipelined,
+        rules::RuleEngine,
+        tx_processor::{run_with_options, RunOptions, RunReport},
+        utils::create_pool_at,
+        validation::ValidationMode,
+    },
+    storage::{
+        db_storage::DbStorage, log_storage::LogStorage, mem_storage::MemStorage,
+        record_storage::RecordStorage,
+    },
+};
+
+use crate::utils::helpers::get_csv_reader;
+
+/// An input file together with the options it's run with.
+type Case = (&'static str, fn() -> RunOptions);
+
+/// Storage backends both runs are compared on. SQLite is the one whose IO the pipeline
+/// is there to overlap with parsing.
+const BACKENDS: [&str; 3] = ["mem", "db", "log"];
+
+#[tokio::test]
+async fn test_pipelined_matches_sync_run() {
+    let cases: [Case; 7] = [
+        ("simple_test", RunOptions::default),
+        ("test1", RunOptions::default),
+        ("test2", RunOptions::default),
+        ("transfer_test", RunOptions::default),
+        ("credit_limit_test", || RunOptions {
+            credit_limits: CreditLimits::from_path("./resources/credit_limits.csv").unwrap(),
+            ..Default::default()
+        }),
+        ("rules_test", || RunOptions {
+            rules: RuleEngine::from_path("./resources/rules.csv").unwrap(),
+            ..Default::default()
+        }),
+        ("invalid_rows", || RunOptions {
+            validation: ValidationMode::Lenient,
+            ..Default::default()
+        }),
+    ];
+
+    for (input_file_name, options) in cases {
+        for backend in BACKENDS {
+            let (sync_out, sync_report) =
+                run_to_string(input_file_name, options(), backend, false).await;
+            let (pipelined_out, pipelined_report) =
+                run_to_string(input_file_name, options(), backend, true).await;
+
+            assert_eq!(sync_out, pipelined_out, "{} [{}]", input_file_name, backend);
+            assert_eq!(
+                sync_report.journal.entries(),
+                pipelined_report.journal.entries()
+            );
+            assert_eq!(sync_report.rejections, pipelined_report.rejections);
+            assert_eq!(sync_report.invalid_rows, pipelined_report.invalid_rows);
+        }
+    }
+}
+
+async fn run_to_string(
+    input_file_name: &str,
+    options: RunOptions,
+    backend: &str,
+    pipelined: bool,
+) -> (String, RunReport) {
+    let dir = std::env::temp_dir().join(format!(
+        "payment_engine_pipeline_{}_{}_{}_{}",
+        process::id(),
+        input_file_name,
+        backend,
+        pipelined
+    ));
+    _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("accounts.csv");
+    let reader = get_csv_reader(input_file_name);
+    let wtr = Writer::from_path(&path).unwrap();
+    let report = match backend {
+        "mem" => run(reader, wtr, MemStorage::new(), options, pipelined).await,
+        "db" => {
+            let storage = DbStorage::new(create_pool_at(dir.join("records.db")).unwrap());
+            run(reader, wtr, storage, options, pipelined).await
+        }
+        _ => {
+            let storage = LogStorage::open(dir.join("log")).unwrap();
+            run(reader, wtr, storage, options, pipelined).await
+        }
+    };
+
+    let output = fs::read_to_string(&path).unwrap();
+    fs::remove_dir_all(dir).unwrap();
+    (output, report)
+}
+
+async fn run(
+    reader: csv::Reader<fs::File>,
+    wtr: Writer<fs::File>,
+    storage: impl RecordStorage,
+    options: RunOptions,
+    pipelined: bool,
+) -> RunReport {
+    if pipelined {
+        run_pipelined(reader, wtr, storage, options).await
+    } else {
+        run_with_options(reader, wtr, storage, options)
+    }
+    .unwrap()
+}
@@ -0,0 +1,207 @@
+//! Runs every `resources/<name>.csv` that has a `resources/<name>.expected.csv` next to it
+//! against each storage backend and compares the accounts with the expected ones, byte
+//! for byte.
+//!
+//! A case may have a `resources/<name>.args` file with extra command line flags, such as
+//! `--rules ./resources/rules.csv`. Each run gets its own temporary directory, so cases
+//! don't share a database. Set `UPDATE_GOLDEN=1` to write the current output to the
+//! expected files instead of comparing against them.
+
+use std::{
+    error::Error,
+    ffi::OsString,
+    fs,
+    path::{Path, PathBuf},
+    process, thread,
+};
+
+use csv::Writer;
+use payment_engine::{
+    cli::Args,
+    processor::{
+        tx_processor::run_with_options,
+        utils::{create_pool_at, get_file_reader},
+    },
+    storage::{db_storage::DbStorage, log_storage::LogStorage, mem_storage::MemStorage},
+};
+
+const RESOURCES_DIR: &str = "./resources";
+const EXPECTED_SUFFIX: &str = ".expected.csv";
+const UPDATE_ENV: &str = "UPDATE_GOLDEN";
+
+#[derive(Debug, Clone, Copy)]
+enum Backend {
+    Mem,
+    Db,
+    Log,
+}
+
+/// The first backend's output is the one written in update mode.
+const BACKENDS: [Backend; 3] = [Backend::Mem, Backend::Db, Backend::Log];
+
+impl Backend {
+    fn name(&self) -> &'static str {
+        match self {
+            Backend::Mem => "mem",
+            Backend::Db => "db",
+            Backend::Log => "log",
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Case {
+    name: String,
+    input: PathBuf,
+    expected: PathBuf,
+    args: Vec<OsString>,
+}
+
+#[test]
+fn test_golden_files() {
+    let update = std::env::var_os(UPDATE_ENV).is_some_and(|v| !v.is_empty() && v != "0");
+    let cases = discover_cases().unwrap();
+    assert!(!cases.is_empty(), "no golden files in {}", RESOURCES_DIR);
+
+    let failures: Vec<String> = thread::scope(|scope| {
+        let handles: Vec<_> = cases
+            .iter()
+            .map(|case| scope.spawn(move || check_case(case, update)))
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap())
+            .collect()
+    });
+    assert!(
+        failures.is_empty(),
+        "{} golden file failure(s), rerun with {}=1 to accept the output:\n{}",
+        failures.len(),
+        UPDATE_ENV,
+        failures.join("\n")
+    );
+}
+
+fn discover_cases() -> Result<Vec<Case>, Box<dyn Error>> {
+    let mut cases = Vec::new();
+    for entry in fs::read_dir(RESOURCES_DIR)? {
+        let expected = entry?.path();
+        let Some(name) = expected
+            .file_name()
+            .and_then(|file_name| file_name.to_str())
+            .and_then(|file_name| file_name.strip_suffix(EXPECTED_SUFFIX))
+        else {
+            continue;
+        };
+        let input = Path::new(RESOURCES_DIR).join(format!("{}.csv", name));
+        let args_path = Path::new(RESOURCES_DIR).join(format!("{}.args", name));
+        let args = match fs::read_to_string(&args_path) {
+            Ok(content) => content.split_whitespace().map(OsString::from).collect(),
+            Err(_) => Vec::new(),
+        };
+        cases.push(Case {
+            name: name.to_string(),
+            input,
+            expected,
+            args,
+        });
+    }
+    cases.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(cases)
+}
+
+/// Returns a message for every backend whose output doesn't match.
+fn check_case(case: &Case, update: bool) -> Vec<String> {
+    let dir = std::env::temp_dir().join(format!(
+        "payment_engine_golden_{}_{}",
+        process::id(),
+        case.name
+    ));
+    _ = fs::remove_dir_all(&dir);
+
+    let mut failures = Vec::new();
+    let mut outputs = Vec::new();
+    for backend in BACKENDS {
+        match run_case(case, backend, &dir.join(backend.name())) {
+            Ok(output) => outputs.push((backend, output)),
+            Err(err) => failures.push(format!("{} [{}]: {}", case.name, backend.name(), err)),
+        }
+    }
+
+    if update {
+        // only accept output all backends agree on
+        if let Some((_, first)) = outputs.first() {
+            for (backend, output) in outputs.iter().skip(1) {
+                failures.extend(compare(case, *backend, output, first));
+            }
+            if failures.is_empty() {
+                if let Err(err) = fs::copy(first, &case.expected) {
+                    failures.push(format!("{}: {}", case.name, err));
+                }
+            }
+        }
+    } else {
+        for (backend, output) in outputs.iter() {
+            failures.extend(compare(case, *backend, output, &case.expected));
+        }
+    }
+
+    _ = fs::remove_dir_all(&dir);
+    failures
+}
+
+/// Runs the case on `backend` inside `dir` and returns the path of the accounts file.
+fn run_case(case: &Case, backend: Backend, dir: &Path) -> Result<PathBuf, Box<dyn Error>> {
+    fs::create_dir_all(dir)?;
+    let mut args = vec![case.input.clone().into_os_string()];
+    args.extend(case.args.iter().cloned());
+    let options = Args::parse(args)?.run_options()?;
+
+    let reader = get_file_reader(&case.input)?;
+    let output = dir.join("accounts.csv");
+    let wtr = Writer::from_path(&output)?;
+    match backend {
+        Backend::Mem => run_with_options(reader, wtr, MemStorage::new(), options)?,
+        Backend::Db => {
+            let storage = DbStorage::new(create_pool_at(dir.join("records.db"))?);
+            run_with_options(reader, wtr, storage, options)?
+        }
+        Backend::Log => run_with_options(reader, wtr, LogStorage::open(dir.join("log"))?, options)?,
+    };
+    Ok(output)
+}
+
+/// Compares the accounts files byte for byte, reporting the lines that differ, so every
+/// column counts and not only the balances.
+fn compare(case: &Case, backend: Backend, actual: &Path, expected: &Path) -> Vec<String> {
+    let failure = |message: String| format!("{} [{}]: {}", case.name, backend.name(), message);
+    let contents = fs::read_to_string(actual).and_then(|a| Ok((a, fs::read_to_string(expected)?)));
+    let (actual, expected) = match contents {
+        Ok(contents) => contents,
+        Err(err) => return vec![failure(err.to_string())],
+    };
+    if actual == expected {
+        return Vec::new();
+    }
+
+    let (actual, expected): (Vec<_>, Vec<_>) =
+        (actual.lines().collect(), expected.lines().collect());
+    let mut failures: Vec<String> = (0..actual.len().max(expected.len()))
+        .filter_map(|i| {
+            let (got, want) = (actual.get(i).copied(), expected.get(i).copied());
+            (got != want).then(|| {
+                failure(format!(
+                    "line {}: got {:?}, expected {:?}",
+                    i + 1,
+                    got.unwrap_or("<none>"),
+                    want.unwrap_or("<none>")
+                ))
+            })
+        })
+        .collect();
+    if failures.is_empty() {
+        // same lines, so only the line endings differ
+        failures.push(failure("line endings differ".to_string()));
+    }
+    failures
+}
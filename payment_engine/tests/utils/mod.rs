@@ -1,3 +1 @@
 pub mod helpers;
-pub mod record;
-pub mod test_runner;